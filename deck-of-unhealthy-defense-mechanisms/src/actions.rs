@@ -5,10 +5,11 @@ use crate::geometry::{
   TILE_RADIUS, TILE_SIZE, TILE_WIDTH,
 };
 use crate::mechanisms::{BuildMechanism, Conveyor, ConveyorSide, Mechanism, MechanismType};
+use crate::random::GameRng;
 use crate::ui_glue::Draw;
+use crate::utils::auto_constant;
 use guard::guard;
 use ordered_float::OrderedFloat;
-use rand::prelude::*;
 use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
 
@@ -34,6 +35,9 @@ impl<'a> ActionUpdateContext<'a> {
   pub fn this_card_mut(&mut self) -> &mut CardInstance {
     self.game.cards.selected_mut().unwrap()
   }
+  pub fn rng(&mut self) -> &mut GameRng {
+    &mut self.game.rng
+  }
 }
 
 #[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
@@ -92,6 +96,8 @@ trait_enum! {
 pub struct SimpleAction {
   display_info: ActionDisplayInfo,
   is_card: bool,
+  /// whether playing this card exhausts it, rather than discarding it
+  consumed_on_use: bool,
   simple_action_type: SimpleActionType,
 
   progress: Time,
@@ -146,11 +152,26 @@ impl SimpleAction {
         flavor_text: flavor_text.to_string(),
       },
       is_card,
+      consumed_on_use: false,
       simple_action_type,
       progress: 0.0,
       cancel_progress: 0.0,
     }
   }
+  pub fn consumed_on_use(mut self) -> SimpleAction {
+    self.consumed_on_use = true;
+    self
+  }
+  pub fn exhausts(&self) -> bool {
+    self.consumed_on_use
+  }
+  /// Make this action cost less health. Upgraded actions get a "+" after their name.
+  pub fn upgrade(&mut self) {
+    self.display_info.name.push('+');
+    if let Cost::Fixed(cost) = &mut self.display_info.health_cost {
+      *cost = (*cost as f64 * auto_constant("upgrade_health_cost_factor", 0.6)).round() as i32;
+    }
+  }
   fn time_cost(&self) -> f64 {
     match self.display_info.time_cost {
       Cost::Fixed(cost) => cost as f64,
@@ -164,6 +185,7 @@ impl SimpleAction {
       _ => panic!(),
     }
   }
+  /// How long you spend recovering after the action finishes. A card spends this long in `Cards::cooling_down` before it reaches the discard or exhaust pile.
   fn cooldown_time(&self) -> f64 {
     self.time_cost() * 0.25
   }
@@ -195,22 +217,18 @@ impl ActionTrait for SimpleAction {
       context.game.player.health -= health_payment;
       if self.finished() > was_finished {
         if self.is_card {
-          match context.game.cards.selected_index {
-            Some(index) => {
-              if index + 1 == context.game.cards.deck.len() {
-                context.game.cards.selected_index = None;
-              } else {
-                context.game.cards.selected_index = Some(index + 1);
-              }
-            }
-            _ => unreachable!(),
-          }
+          context.game.cards.finish_playing_selected();
         }
-        self.simple_action_type.finish(context);
+        self.simple_action_type.finish(ActionUpdateContext {
+          game: &mut *context.game,
+        });
       }
     }
 
     if self.progress > self.time_cost() || self.cancel_progress > self.cooldown_time() {
+      if self.is_card {
+        context.game.cards.finish_cooldown();
+      }
       ActionStatus::Completed
     } else {
       ActionStatus::StillGoing
@@ -425,8 +443,11 @@ pub struct Reshuffle;
 
 impl SimpleActionTrait for Reshuffle {
   fn finish(&self, context: ActionUpdateContext) {
-    let cards = &mut context.game.cards;
-    cards.deck.shuffle(&mut rand::thread_rng());
-    cards.selected_index = Some(0);
+    let Game { cards, rng, .. } = context.game;
+    cards.reshuffle(rng);
+  }
+
+  fn possible(&self, game: &Game) -> bool {
+    !game.cards.discard_pile.is_empty()
   }
 }
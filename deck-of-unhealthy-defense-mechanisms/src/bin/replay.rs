@@ -0,0 +1,26 @@
+//! Replays an input log saved from the browser (the output of `rust_input_log()`) and checks that it reaches the same final state.
+//!
+//! Usage: `cargo run --bin replay -- path/to/log.json`
+
+use deck_of_unhealthy_defense_mechanisms::replay::InputLog;
+use std::fs;
+use std::process;
+
+fn main() {
+  let path = std::env::args()
+    .nth(1)
+    .expect("usage: replay path/to/log.json");
+  let log: InputLog = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+  match log.check() {
+    Ok(game) => println!(
+      "replayed {} frames; reached day {} with {} health",
+      log.frames.len(),
+      game.day,
+      game.player.health
+    ),
+    Err(message) => {
+      eprintln!("{}", message);
+      process::exit(1);
+    }
+  }
+}
@@ -0,0 +1,184 @@
+/*!
+
+Cards, as written down in `data/cards.json`.
+
+Each `CardDefinition` names the `SimpleActionType` the card performs (along with that action's parameters) and everything the player sees on the card, so new cards and text changes don't need to touch the code. Definitions are checked when they're loaded, so a mistake in the file shows up as an error naming the card, rather than as strange behavior during a game.
+
+*/
+use crate::actions::{Action, SimpleAction, SimpleActionType};
+use crate::cards::CardInstance;
+use crate::rewards::{CardPool, CardPoolEntry};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct CardDefinition {
+  /// how the code and other data refer to this card; never shown to the player
+  pub id: String,
+  pub name: String,
+  #[serde(default)]
+  pub rules_text: String,
+  #[serde(default)]
+  pub flavor_text: String,
+  pub time_cost: i32,
+  #[serde(default)]
+  pub health_cost: Option<i32>,
+  /// whether playing this card exhausts it, rather than discarding it
+  #[serde(default)]
+  pub consumed_on_use: bool,
+  pub action: SimpleActionType,
+  /// how rewards offer this card, if they do at all
+  #[serde(default)]
+  pub reward: Option<CardRewardDefinition>,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct CardRewardDefinition {
+  pub weight: f64,
+  pub first_day: i32,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug, Default)]
+pub struct CardDefinitions {
+  pub definitions: Vec<CardDefinition>,
+}
+
+impl CardDefinition {
+  pub fn validate(&self) -> Result<(), String> {
+    let problem = if self.id.is_empty() {
+      "has an empty id".to_string()
+    } else if self.name.is_empty() {
+      "has an empty name".to_string()
+    } else if self.time_cost <= 0 {
+      format!(
+        "has a time cost of {}, but it must be positive",
+        self.time_cost
+      )
+    } else if matches!(self.health_cost, Some(cost) if cost < 0) {
+      format!(
+        "has a health cost of {}, but it can't be negative",
+        self.health_cost.unwrap()
+      )
+    } else if matches!(self.action, SimpleActionType::Reshuffle(_)) {
+      "reshuffles, but that's only for the deck, not for cards".to_string()
+    } else if matches!(&self.reward, Some(reward) if !(reward.weight.is_finite() && reward.weight > 0.0))
+    {
+      "has a reward weight that isn't a positive number".to_string()
+    } else {
+      return Ok(());
+    };
+    Err(format!("card {:?} {}", self.id, problem))
+  }
+
+  pub fn instance(&self) -> CardInstance {
+    let mut action = SimpleAction::new(
+      self.time_cost,
+      self.health_cost,
+      &self.name,
+      &self.rules_text,
+      &self.flavor_text,
+      true,
+      self.action.clone(),
+    );
+    if self.consumed_on_use {
+      action = action.consumed_on_use();
+    }
+    CardInstance {
+      action: Action::SimpleAction(action),
+      upgraded: false,
+    }
+  }
+}
+
+impl CardDefinitions {
+  /// Parse and validate a list of card definitions in JSON.
+  pub fn load(json: &str) -> Result<CardDefinitions, String> {
+    let definitions: Vec<CardDefinition> =
+      serde_json::from_str(json).map_err(|error| format!("invalid card definitions: {}", error))?;
+    let mut ids = HashSet::new();
+    for definition in &definitions {
+      definition.validate()?;
+      if !ids.insert(&definition.id) {
+        return Err(format!("card {:?} is defined twice", definition.id));
+      }
+    }
+    Ok(CardDefinitions { definitions })
+  }
+
+  pub fn standard() -> CardDefinitions {
+    CardDefinitions::load(include_str!("../data/cards.json")).unwrap()
+  }
+
+  pub fn get(&self, id: &str) -> Option<&CardDefinition> {
+    self
+      .definitions
+      .iter()
+      .find(|definition| definition.id == id)
+  }
+
+  /// A new instance of the card with this `id`, which must exist.
+  pub fn instance(&self, id: &str) -> CardInstance {
+    self
+      .get(id)
+      .unwrap_or_else(|| panic!("no card is defined with id {:?}", id))
+      .instance()
+  }
+
+  /// The pool of every card that has a `reward`.
+  pub fn card_pool(&self) -> CardPool {
+    CardPool {
+      entries: self
+        .definitions
+        .iter()
+        .filter_map(|definition| {
+          definition.reward.as_ref().map(|reward| CardPoolEntry {
+            card: definition.instance(),
+            weight: reward.weight,
+            first_day: reward.first_day,
+          })
+        })
+        .collect(),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::game::STARTING_DECK;
+
+  #[test]
+  fn standard_cards_load() {
+    let cards = CardDefinitions::standard();
+    for id in STARTING_DECK {
+      assert!(cards.get(id).is_some(), "missing card {}", id);
+    }
+    assert!(!cards.card_pool().entries.is_empty());
+  }
+
+  #[test]
+  fn invalid_cards_are_rejected() {
+    let card = |id: &str, time_cost: i32, action: &str| {
+      format!(
+        r#"{{ "id": "{}", "name": "Card", "time_cost": {}, "action": {} }}"#,
+        id, time_cost, action
+      )
+    };
+    let conveyor = r#"{ "BuildConveyor": { "allow_splitting": false } }"#;
+    let load = |cards: &[String]| CardDefinitions::load(&format!("[{}]", cards.join(",")));
+
+    assert!(load(&[card("a", 2, conveyor), card("b", 3, conveyor)]).is_ok());
+    assert!(load(&[card("a", 0, conveyor)])
+      .unwrap_err()
+      .contains("time cost"));
+    assert!(load(&[card("a", 2, conveyor), card("a", 3, conveyor)])
+      .unwrap_err()
+      .contains("defined twice"));
+    assert!(load(&[card("a", 2, r#"{ "Reshuffle": null }"#)])
+      .unwrap_err()
+      .contains("reshuffles"));
+    assert!(load(&[card("a", 2, r#"{ "BuildMoat": null }"#)])
+      .unwrap_err()
+      .contains("invalid card definitions"));
+  }
+}
@@ -1,60 +1,146 @@
-use crate::actions::{Action, BuildConveyor, Cost, SimpleAction, SimpleActionType};
+use crate::actions::{Action, Cost};
 use crate::game::Game;
 use crate::geometry::FloatingVector;
-use crate::mechanisms::{BuildMechanism, BuildMine, BuildTower};
 use crate::ui_glue::Draw;
+use rand::seq::SliceRandom;
+use rand::Rng;
 use serde::{Deserialize, Serialize};
 
+/** All of the player's cards, each in one of five zones.
+
+You play cards from your hand. When a card's action finishes, the card leaves your hand and a replacement is drawn from the draw pile (if there's anything left in it). The played card cools down while you recover from playing it. Once the action's cooldown is over, it goes to the discard pile, so it can't be played again until you reshuffle at your deck; or, if it's consumed on use, it goes to the exhaust pile and is gone for good.
+*/
 #[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
 pub struct Cards {
-  pub deck: Vec<CardInstance>,
+  /// the next card to be drawn is the last one
+  pub draw_pile: Vec<CardInstance>,
+  pub hand: Vec<CardInstance>,
+  pub discard_pile: Vec<CardInstance>,
+  pub exhausted: Vec<CardInstance>,
+  /// the card that was just played, until its action's cooldown is over
+  #[serde(default)]
+  pub cooling_down: Option<CardInstance>,
+  pub hand_size: usize,
+  /// index into `hand`
   pub selected_index: Option<usize>,
 }
 #[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
 pub struct CardInstance {
   pub action: Action,
+  #[serde(default)]
+  pub upgraded: bool,
 }
 
 impl CardInstance {
-  pub fn basic_conveyor() -> Self {
-    CardInstance {
-      action: Action::SimpleAction(SimpleAction::new(2, Some(10), "Conveyor", "", "No matter how low you get, something keeps you moving forward. Is it hope for something better? Or is it just an endless grind, false hope leading you down the same corridor again and again and again?", true, SimpleActionType::BuildConveyor(BuildConveyor {
-      allow_splitting: false})
-      )),
-    }
-  }
-  pub fn basic_tower() -> Self {
-    CardInstance {
-      action: Action::SimpleAction(SimpleAction::new(4, Some(40), "Defensive Tower", "", "You think *I* have a problem?! *You're* the monsters who are trying to kill me! Why won't you just shut up already?!", true, SimpleActionType::BuildMechanism(BuildMechanism::BuildTower(BuildTower))),
-      ),
+  pub fn upgrade(&mut self) {
+    self.upgraded = true;
+    match &mut self.action {
+      Action::SimpleAction(action) => action.upgrade(),
     }
   }
-  pub fn basic_mine() -> Self {
-    CardInstance {
-      action: Action::SimpleAction(SimpleAction::new(
-        4,
-        Some(40),
-        "Mine",
-        "",
-        "Mine. Mine. This is all mine. I won't let ANY of you take it away from me!",
-        true,
-        SimpleActionType::BuildMechanism(BuildMechanism::BuildMine(BuildMine)),
-      )),
+  /// Whether playing this card exhausts it, rather than discarding it.
+  pub fn exhausts(&self) -> bool {
+    match &self.action {
+      Action::SimpleAction(action) => action.exhausts(),
     }
   }
 }
 
 impl Cards {
+  /// Start with all of `cards` in the draw pile, in order, and draw a hand from the front.
+  pub fn new(cards: Vec<CardInstance>, hand_size: usize) -> Cards {
+    let mut result = Cards {
+      draw_pile: cards.into_iter().rev().collect(),
+      hand: Vec::new(),
+      discard_pile: Vec::new(),
+      exhausted: Vec::new(),
+      cooling_down: None,
+      hand_size,
+      selected_index: None,
+    };
+    result.refill_hand();
+    result
+  }
   pub fn selected(&self) -> Option<&CardInstance> {
     self
       .selected_index
-      .map(|index| self.deck.get(index).unwrap())
+      .map(|index| self.hand.get(index).unwrap())
   }
   pub fn selected_mut(&mut self) -> Option<&mut CardInstance> {
     self
       .selected_index
-      .map(move |index| self.deck.get_mut(index).unwrap())
+      .map(move |index| self.hand.get_mut(index).unwrap())
+  }
+  /// Select the card `steps` places further along in the hand (or back, if negative), wrapping around.
+  pub fn rotate_selection(&mut self, steps: i32) {
+    if let Some(index) = self.selected_index {
+      let length = self.hand.len() as i32;
+      self.selected_index = Some((index as i32 + steps).rem_euclid(length) as usize);
+    }
+  }
+  /// Every card the player owns, in any zone except exhausted.
+  pub fn owned(&self) -> impl Iterator<Item = &CardInstance> {
+    self
+      .draw_pile
+      .iter()
+      .chain(&self.hand)
+      .chain(&self.discard_pile)
+      .chain(&self.cooling_down)
   }
+
+  /// Draw until the hand is full or the draw pile runs out.
+  pub fn refill_hand(&mut self) {
+    while self.hand.len() < self.hand_size {
+      match self.draw_pile.pop() {
+        Some(card) => self.hand.push(card),
+        None => break,
+      }
+    }
+    self.fix_selection();
+  }
+
+  /// Move the selected card out of the hand to cool down, after its action finished, and draw a replacement.
+  pub fn finish_playing_selected(&mut self) {
+    let index = self.selected_index.unwrap();
+    self.finish_cooldown();
+    self.cooling_down = Some(self.hand.remove(index));
+    self.refill_hand();
+  }
+
+  /// Move the card that was cooling down (if any) to the exhaust pile if it exhausts, or the discard pile otherwise.
+  pub fn finish_cooldown(&mut self) {
+    if let Some(card) = self.cooling_down.take() {
+      if card.exhausts() {
+        self.exhausted.push(card);
+      } else {
+        self.discard_pile.push(card);
+      }
+    }
+  }
+
+  /// Shuffle the discard pile back into the draw pile, then refill the hand.
+  pub fn reshuffle(&mut self, rng: &mut impl Rng) {
+    self.draw_pile.append(&mut self.discard_pile);
+    self.draw_pile.shuffle(rng);
+    self.refill_hand();
+  }
+
+  /// Put every card from the hand and the discard pile back into the draw pile, leaving nothing selected.
+  pub fn gather(&mut self) {
+    self.finish_cooldown();
+    self.draw_pile.append(&mut self.hand);
+    self.draw_pile.append(&mut self.discard_pile);
+    self.selected_index = None;
+  }
+
+  fn fix_selection(&mut self) {
+    self.selected_index = match self.selected_index {
+      _ if self.hand.is_empty() => None,
+      Some(index) => Some(index.min(self.hand.len() - 1)),
+      None => Some(0),
+    };
+  }
+
   pub fn draw(&self, game: &Game, draw: &mut impl Draw) {
     let activation = game.current_mechanism_activation();
     fn draw_action(
@@ -95,28 +181,233 @@ impl Cards {
       );
     }
 
-    if let Some(selected) = self.selected() {
-      draw_action(
-        draw,
-        &selected.action,
-        selected.action.possible(game),
-        FloatingVector::new(0.05, 0.4),
-        30.0,
-      );
-      selected.action.draw_preview(game, draw);
-      for (index, upcoming) in self.deck[self.selected_index.unwrap() + 1..]
-        .iter()
-        .enumerate()
-        .take(2)
-      {
+    for (index, card) in self.hand.iter().enumerate() {
+      let position = FloatingVector::new(0.03, 0.25 + index as f64 * 0.16);
+      if Some(index) == self.selected_index {
         draw_action(
           draw,
-          &upcoming.action,
-          false,
-          FloatingVector::new(0.03, 0.6 + (index as f64 * 0.14)),
-          18.0,
+          &card.action,
+          card.action.possible(game),
+          position,
+          26.0,
         );
+        card.action.draw_preview(game, draw);
+      } else {
+        draw_action(draw, &card.action, false, position, 18.0);
       }
     }
+
+    for (index, (name, pile)) in [
+      ("Draw pile", &self.draw_pile),
+      ("Discard pile", &self.discard_pile),
+      ("Exhausted", &self.exhausted),
+    ]
+    .iter()
+    .enumerate()
+    {
+      draw.text(
+        FloatingVector::new(0.03, 0.86 + index as f64 * 0.04),
+        16.0,
+        "#aaa",
+        &format!("{}: {}", name, pile.len()),
+      );
+    }
+    if let Some(card) = &self.cooling_down {
+      draw.text(
+        FloatingVector::new(0.03, 0.82),
+        16.0,
+        "#aaa",
+        &format!("Cooling down: {}", card.action.display_info().name),
+      );
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::card_definitions::CardDefinitions;
+  use crate::game::{OngoingIntent, WhichInteraction};
+  use crate::random::GameRng;
+  use crate::test_harness::Scenario;
+  use rand::SeedableRng;
+
+  fn names(cards: &[CardInstance]) -> Vec<String> {
+    cards
+      .iter()
+      .map(|card| card.action.display_info().name)
+      .collect()
+  }
+
+  #[test]
+  fn played_cards_move_between_zones() {
+    let definitions = CardDefinitions::standard();
+    let mut cards = Cards::new(
+      ["mine", "tower", "makeshift_tower", "tower"]
+        .iter()
+        .map(|id| definitions.instance(id))
+        .collect(),
+      2,
+    );
+    assert_eq!(names(&cards.hand), vec!["Mine", "Defensive Tower"]);
+    assert_eq!(cards.selected_index, Some(0));
+
+    cards.finish_playing_selected();
+    assert_eq!(
+      names(&cards.hand),
+      vec!["Defensive Tower", "Makeshift Tower"]
+    );
+    assert_eq!(
+      cards
+        .cooling_down
+        .as_ref()
+        .unwrap()
+        .action
+        .display_info()
+        .name,
+      "Mine"
+    );
+    assert!(cards.discard_pile.is_empty());
+    cards.finish_cooldown();
+    assert_eq!(cards.cooling_down, None);
+    assert_eq!(names(&cards.discard_pile), vec!["Mine"]);
+
+    cards.rotate_selection(-1);
+    assert_eq!(cards.selected_index, Some(1));
+    cards.finish_playing_selected();
+    cards.finish_cooldown();
+    assert_eq!(
+      names(&cards.hand),
+      vec!["Defensive Tower", "Defensive Tower"]
+    );
+    assert_eq!(names(&cards.exhausted), vec!["Makeshift Tower"]);
+    assert!(cards.draw_pile.is_empty());
+
+    // playing another card also ends the previous card's cooldown
+    cards.finish_playing_selected();
+    cards.finish_playing_selected();
+    assert_eq!(names(&cards.discard_pile), vec!["Mine", "Defensive Tower"]);
+    cards.finish_cooldown();
+    assert!(cards.hand.is_empty());
+    assert_eq!(cards.selected_index, None);
+
+    cards.reshuffle(&mut GameRng::seed_from_u64(0));
+    assert_eq!(cards.hand.len(), 2);
+    assert_eq!(cards.draw_pile.len(), 1);
+    assert!(cards.discard_pile.is_empty());
+    assert_eq!(cards.owned().count(), 3);
+    assert_eq!(cards.selected_index, Some(0));
+  }
+
+  #[test]
+  fn the_card_being_played_stays_selected() {
+    let mut scenario = Scenario::new();
+    scenario.game.player.position = FloatingVector::new(-6.0, 0.0);
+    scenario.game.rotate_card_selection(1);
+    assert_eq!(names(&scenario.game.cards.hand)[1], "Defensive Tower");
+    assert_eq!(scenario.game.cards.selected_index, Some(1));
+
+    scenario
+      .game
+      .initiate_interaction(WhichInteraction::PlayCard);
+    scenario.advance_with_intent(1.0, OngoingIntent::Interact(WhichInteraction::PlayCard));
+    scenario.game.rotate_card_selection(1);
+    assert_eq!(scenario.game.cards.selected_index, Some(1));
+    scenario.advance_with_intent(5.0, OngoingIntent::Interact(WhichInteraction::PlayCard));
+    assert_eq!(
+      names(&scenario.game.cards.discard_pile),
+      vec!["Defensive Tower"]
+    );
+  }
+
+  #[test]
+  fn playing_a_card_discards_it() {
+    let mut scenario = Scenario::new();
+    scenario.game.player.position = FloatingVector::new(-6.0, 0.0);
+    let hand_size = scenario.game.cards.hand.len();
+    scenario
+      .game
+      .initiate_interaction(WhichInteraction::PlayCard);
+    scenario.advance_with_intent(6.0, OngoingIntent::Interact(WhichInteraction::PlayCard));
+
+    let cards = &scenario.game.cards;
+    assert_eq!(names(&cards.discard_pile), vec!["Mine"]);
+    assert_eq!(cards.hand.len(), hand_size);
+    assert!(!names(&cards.hand).contains(&"Mine".to_string()));
+  }
+
+  #[test]
+  fn exhausted_cards_stay_exhausted() {
+    let mut scenario = Scenario::new();
+    let definitions = CardDefinitions::standard();
+    scenario.game.cards.hand[0] = definitions.instance("makeshift_tower");
+    let play_card_at = |scenario: &mut Scenario, position: FloatingVector| {
+      scenario.game.player.position = position;
+      scenario
+        .game
+        .initiate_interaction(WhichInteraction::PlayCard);
+      scenario.advance_with_intent(5.0, OngoingIntent::Interact(WhichInteraction::PlayCard));
+    };
+
+    // its action finishes after 1.5 seconds, and then it cools down for another 0.5
+    scenario.game.player.position = FloatingVector::new(-6.0, 0.0);
+    scenario
+      .game
+      .initiate_interaction(WhichInteraction::PlayCard);
+    scenario.advance_with_intent(1.75, OngoingIntent::Interact(WhichInteraction::PlayCard));
+    assert!(scenario
+      .game
+      .cards
+      .cooling_down
+      .as_ref()
+      .unwrap()
+      .exhausts());
+    assert!(scenario.game.cards.exhausted.is_empty());
+    scenario.advance_with_intent(1.0, OngoingIntent::Interact(WhichInteraction::PlayCard));
+    assert_eq!(scenario.game.cards.cooling_down, None);
+    assert_eq!(
+      names(&scenario.game.cards.exhausted),
+      vec!["Makeshift Tower"]
+    );
+    play_card_at(&mut scenario, FloatingVector::new(-10.0, 0.0));
+    assert_eq!(scenario.game.cards.discard_pile.len(), 1);
+
+    // reshuffle at the deck
+    scenario.game.player.position = FloatingVector::zeros();
+    scenario
+      .game
+      .initiate_interaction(WhichInteraction::ActivateMechanism);
+    scenario.advance_with_intent(
+      6.0,
+      OngoingIntent::Interact(WhichInteraction::ActivateMechanism),
+    );
+    assert!(scenario.game.cards.discard_pile.is_empty());
+    assert_eq!(
+      names(&scenario.game.cards.exhausted),
+      vec!["Makeshift Tower"]
+    );
+
+    // skip the end-of-day reward, which is always the last option
+    scenario.game.day_progress = 0.999;
+    scenario.advance(1.0);
+    let options = scenario.game.rewards.offer.as_ref().unwrap().options.len();
+    for _ in 1..options {
+      scenario
+        .game
+        .initiate_interaction(WhichInteraction::ActivateMechanism);
+    }
+    scenario
+      .game
+      .initiate_interaction(WhichInteraction::PlayCard);
+    assert_eq!(scenario.game.rewards.offer, None);
+    assert_eq!(
+      names(&scenario.game.cards.exhausted),
+      vec!["Makeshift Tower"]
+    );
+    assert!(!scenario
+      .game
+      .cards
+      .owned()
+      .any(|card| card.action.display_info().name == "Makeshift Tower"));
   }
 }
@@ -1,5 +1,6 @@
 use crate::actions::{Action, ActionStatus, ActionUpdateContext};
-use crate::cards::{CardInstance, Cards};
+use crate::card_definitions::CardDefinitions;
+use crate::cards::Cards;
 use crate::geometry::{
   FloatingVector, FloatingVectorExtension, Grid, GridBounds, GridVector, GridVectorExtension,
   EPSILON, TILE_RADIUS, TILE_SIZE, TILE_WIDTH,
@@ -8,15 +9,20 @@ use crate::mechanisms::{
   Deck, Mechanism, MechanismImmutableContext, MechanismType, MechanismUpdateContext,
 };
 use crate::movers::{
-  Monster, Mover, MoverBehavior, MoverCollideContext, MoverId, MoverImmutableContext, MoverType,
-  MoverUpdateContext,
+  DeckBody, Mover, MoverBehavior, MoverCollideContext, MoverId, MoverImmutableContext, MoverType,
+  MoverUpdateContext, PlayerBody,
 };
+use crate::random::GameRng;
+use crate::rewards::Rewards;
 use crate::ui_glue::Draw;
+use crate::utils::auto_constant;
+use crate::waves::{standard_waves, WaveDirector};
 use derivative::Derivative;
-use eliduprees_web_games_lib::auto_constant;
-use live_prop_test::{live_prop_test, lpt_assert_eq};
+use guard::guard;
+use live_prop_test::{live_prop_test, lpt_assert, lpt_assert_eq};
 use nalgebra::Vector2;
 use ordered_float::OrderedFloat;
+use rand::{RngCore, SeedableRng};
 use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
 use std::collections::{BTreeSet, HashMap};
@@ -25,6 +31,11 @@ use std::mem;
 pub type Time = f64;
 /// duration of each update in seconds:
 pub const UPDATE_DURATION: Time = 1.0 / 180.0;
+/// the ids of the cards you start with, from `data/cards.json`, in the order you draw them
+pub const STARTING_DECK: &[&str] = &[
+  "mine", "tower", "conveyor", "conveyor", "conveyor", "tower", "conveyor", "conveyor", "tower",
+  "conveyor",
+];
 
 #[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
 pub struct Game {
@@ -36,6 +47,10 @@ pub struct Game {
   pub day_progress: f64,
   pub horizon: f64,
   pub grid: Grid<Tile>,
+  pub rng: GameRng,
+  pub wave_director: WaveDirector,
+  pub rewards: Rewards,
+  pub game_over: Option<GameOverCause>,
   movers: HashMap<MoverId, MoverAndScheduleStuff>,
   next_mover_id: usize,
   upcoming_events: BTreeSet<UpcomingEvent>,
@@ -43,6 +58,7 @@ pub struct Game {
 #[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
 pub struct Player {
   pub position: FloatingVector,
+  pub body: MoverId,
   pub action_state: PlayerActionState,
   pub initiated_interaction: Option<WhichInteraction>,
   pub maximum_health: i32,
@@ -65,6 +81,12 @@ pub enum WhichInteraction {
   ActivateMechanism,
 }
 
+#[derive(Copy, Clone, PartialEq, Eq, Serialize, Deserialize, Debug)]
+pub enum GameOverCause {
+  PlayerDied,
+  DeckDestroyed,
+}
+
 #[derive(Copy, Clone, PartialEq, Serialize, Deserialize, Debug)]
 pub enum OngoingIntent {
   Move(FloatingVector),
@@ -119,23 +141,87 @@ enum UpcomingEventType {
   ),
 }
 
+#[live_prop_test]
 impl Game {
   pub fn check_invariants(&self) -> Result<(), String> {
-    // for (id, mover) in &self.movers {
-    //   guard!(let Some(tile) = self.tiles.get(mover.position.containing_tile()) else {return Err("mover is outside of grid".to_string())});
-    //   if !tile.movers.contains(&id) {
-    //     return Err(format!(
-    //       "Mover does not have a record in its tile: {:?}",
-    //       mover
-    //     ));
-    //   }
-    // }
-    // for (position, tile) in &self.grid {
-    //   for &id in &tile.movers {
-    //     guard!(let Some(mover) = self.movers.get(&id) else {return Err("tile retained reference to missing mover".to_string())});
-    //     lpt_assert_eq!(mover.position.containing_tile(), position);
-    //   }
-    // }
+    for (&id, stuff) in &self.movers {
+      // movers only get new stored bounds when they escape the old ones, which is EPSILON after they reach the edge
+      let [min, max] = stuff.mover.bounds(self.physics_time);
+      let stored_min = stuff.stored_bounds.min_tile_corner().to_floating();
+      let stored_max = stuff.stored_bounds.max_tile_corner().to_floating();
+      if (0..2).any(|dimension| {
+        min[dimension] < stored_min[dimension] - EPSILON * 2.0
+          || max[dimension] > stored_max[dimension] + EPSILON * 2.0
+      }) {
+        return Err(format!(
+          "Mover {:?} is outside its stored bounds: {:?}",
+          id, stuff
+        ));
+      }
+      for position in stuff.stored_bounds.tile_centers() {
+        if let Some(tile) = self.grid.get(position) {
+          if tile.movers.iter().filter(|&&id2| id2 == id).count() != 1 {
+            return Err(format!(
+              "Mover {:?} does not have exactly one record in tile {:?}",
+              id, position
+            ));
+          }
+        }
+      }
+      if let Some(schedule) = &stuff.schedule {
+        lpt_assert!(self.upcoming_events.contains(&UpcomingEvent {
+          time: schedule.time,
+          event_type: UpcomingEventType::Mover(id),
+        }));
+      }
+    }
+
+    for (position, tile) in &self.grid {
+      for &id in &tile.movers {
+        guard!(let Some(stuff) = self.movers.get(&id) else { return Err("tile retained reference to missing mover".to_string()) });
+        if !stuff.stored_bounds.tile_centers().any(|p| p == position) {
+          return Err(format!(
+            "Tile {:?} has a record of mover {:?}, which isn't stored there",
+            position, id
+          ));
+        }
+      }
+      lpt_assert_eq!(
+        tile.mechanism_schedule,
+        tile
+          .mechanism
+          .as_ref()
+          .and_then(|mechanism| mechanism.mechanism_type.next_wake(mechanism))
+      );
+      if let Some(time) = tile.mechanism_schedule {
+        lpt_assert!(self.upcoming_events.contains(&UpcomingEvent {
+          time: OrderedFloat(time),
+          event_type: UpcomingEventType::Mechanism(position),
+        }));
+      }
+    }
+
+    // each event was checked against its schedule above; make sure there are no extra ones
+    for event in &self.upcoming_events {
+      match event.event_type {
+        UpcomingEventType::Mover(id) => {
+          guard!(let Some(stuff) = self.movers.get(&id) else { return Err("upcoming event for missing mover".to_string()) });
+          lpt_assert_eq!(
+            stuff.schedule.as_ref().map(|schedule| schedule.time),
+            Some(event.time)
+          );
+        }
+        UpcomingEventType::Mechanism(position) => {
+          lpt_assert_eq!(
+            self
+              .grid
+              .get(position)
+              .and_then(|tile| tile.mechanism_schedule),
+            Some(event.time.0)
+          );
+        }
+      }
+    }
     Ok(())
   }
 
@@ -289,6 +375,10 @@ impl Game {
         // r^2 = (x+vx*t)^2 + (y+vy*t)^2
         // (vx^2+vy^2)t^2 + 2*(x*vx + y*vy) +
         let a = relative_velocity.magnitude_squared();
+        if a == 0.0 {
+          // not moving relative to each other, so they can't start touching
+          return None;
+        }
         let b = 2.0 * relative_velocity.dot(&relative_position);
         let c = relative_position.magnitude_squared() - radius * radius;
         let discriminant = b * b - 4.0 * a * c;
@@ -398,7 +488,6 @@ impl Game {
               .escape_bounds(MoverUpdateContext { id, game: self })
           }
           MoverEventType::CollideWith(other_id) => {
-            debug!("works");
             stuff.mover.behavior.clone().collide(MoverCollideContext {
               update_context: MoverUpdateContext { id, game: self },
               other_id,
@@ -442,14 +531,21 @@ impl Game {
   }
 
   pub fn new() -> Self {
+    Self::with_seed(rand::random())
+  }
+
+  /// Start a new game whose randomness is entirely determined by `seed`.
+  pub fn with_seed(seed: u64) -> Self {
     let radius = 10;
     let grid = Grid::new(
       GridVector::new(-(radius as i32) * TILE_WIDTH, -(radius as i32) * TILE_WIDTH),
       Vector2::new(radius * 2 + 1, radius * 2 + 1),
     );
+    let card_definitions = CardDefinitions::standard();
     let mut result = Game {
       player: Player {
         position: FloatingVector::zeros(),
+        body: MoverId::default(),
         action_state: PlayerActionState::Moving {
           velocity: FloatingVector::zeros(),
         },
@@ -457,27 +553,23 @@ impl Game {
         maximum_health: 100,
         health: 100.0,
       },
-      cards: Cards {
-        deck: vec![
-          CardInstance::basic_mine(),
-          CardInstance::basic_tower(),
-          CardInstance::basic_conveyor(),
-          CardInstance::basic_conveyor(),
-          CardInstance::basic_conveyor(),
-          CardInstance::basic_tower(),
-          CardInstance::basic_conveyor(),
-          CardInstance::basic_conveyor(),
-          CardInstance::basic_tower(),
-          CardInstance::basic_conveyor(),
-        ],
-        selected_index: Some(0),
-      },
+      cards: Cards::new(
+        STARTING_DECK
+          .iter()
+          .map(|id| card_definitions.instance(id))
+          .collect(),
+        auto_constant("hand_size", 3.0) as usize,
+      ),
       ui_time: 0.0,
       physics_time: 0.0,
       day: 1,
       day_progress: 0.0,
       horizon: 50.0,
       grid,
+      rng: GameRng::seed_from_u64(seed),
+      wave_director: WaveDirector::new(standard_waves()),
+      rewards: Rewards::new(card_definitions.card_pool(), 1),
+      game_over: None,
       movers: Default::default(),
       next_mover_id: 0,
       upcoming_events: Default::default(),
@@ -485,21 +577,22 @@ impl Game {
     result.create_mechanism(
       GridVector::zeros(),
       Mechanism {
-        mechanism_type: MechanismType::Deck(Deck {}),
+        mechanism_type: MechanismType::Deck(Deck::default()),
         ..Default::default()
       },
     );
     result.create_mover(Mover {
-      trajectory_base_time: 0.0,
-      position_at_base_time: FloatingVector::new(4.0, 6.0),
-      radius: 0.8 * TILE_RADIUS as f64,
-      mover_type: MoverType::Monster,
-      behavior: MoverBehavior::Monster(Monster {
-        home: FloatingVector::new(8.0, 12.0),
-        active_time: 0.5..0.9,
-        next_wake: 0.0,
+      radius: TILE_RADIUS as f64 * 0.9,
+      mover_type: MoverType::Deck,
+      behavior: MoverBehavior::DeckBody(DeckBody {
+        deck_position: GridVector::zeros(),
       }),
-
+      ..Default::default()
+    });
+    result.player.body = result.create_mover(Mover {
+      radius: auto_constant("player_radius", 0.4) * TILE_RADIUS as f64,
+      mover_type: MoverType::Player,
+      behavior: MoverBehavior::PlayerBody(PlayerBody),
       ..Default::default()
     });
     result
@@ -520,13 +613,51 @@ impl Game {
     None
   }
 
+  /// Start an interaction; or, once the game is over, start a new game; or, while a reward is being offered, make a choice about it.
   pub fn initiate_interaction(&mut self, which: WhichInteraction) {
+    if self.game_over.is_some() {
+      self.restart();
+      return;
+    }
+    if self.rewards.offer.is_some() {
+      self.reward_interaction(which);
+      return;
+    }
     self.player.initiated_interaction = Some(which);
   }
 
+  /** Select a different card in your hand, `steps` cards along (or back, if negative); or, while a reward is being offered, highlight a different option.
+
+  The card you're in the middle of playing stays selected until it's done, since finishing it is what moves it out of your hand.
+  */
+  pub fn rotate_card_selection(&mut self, steps: i32) {
+    if steps == 0 || self.game_over.is_some() {
+      return;
+    }
+    if let Some(offer) = &mut self.rewards.offer {
+      offer.rotate_highlight(steps);
+      return;
+    }
+    if matches!(&self.player.action_state, PlayerActionState::Interacting(interaction) if interaction.which == WhichInteraction::PlayCard)
+    {
+      return;
+    }
+    self.cards.rotate_selection(steps);
+  }
+
+  /// Replace this game with a new one, seeded from this one's RNG so that replays stay deterministic. `ui_time` carries over, because the UI keeps counting time across games.
+  pub fn restart(&mut self) {
+    let ui_time = self.ui_time;
+    *self = Game::with_seed(self.rng.next_u64());
+    self.ui_time = ui_time;
+  }
+
   fn update(&mut self, intent: OngoingIntent) {
     //if intent != OngoingIntent::Move(FloatingVector::zeros()) {
-    self.update_physics(intent);
+    // the world waits while you're choosing a reward
+    if self.game_over.is_none() && self.rewards.offer.is_none() {
+      self.update_physics(intent);
+    }
 
     self.ui_time += UPDATE_DURATION;
   }
@@ -542,6 +673,10 @@ impl Game {
     }
   }
 
+  #[live_prop_test(
+    precondition = "self.check_invariants()",
+    postcondition = "self.check_invariants()"
+  )]
   fn update_physics(&mut self, intent: OngoingIntent) {
     let former = self.clone();
 
@@ -625,12 +760,29 @@ impl Game {
       }
     }
 
+    // keep the player's body where the player is, moving along with them during this update
+    let player_velocity = match self.player.action_state {
+      PlayerActionState::Moving { velocity } => velocity,
+      _ => FloatingVector::zeros(),
+    };
+    let body_position = self.player.position - player_velocity * UPDATE_DURATION;
+    self.mutate_mover(self.player.body, |body| {
+      body.position_at_base_time = body_position;
+      body.velocity = player_velocity;
+    });
+
     let end_time = self.physics_time + UPDATE_DURATION;
 
     while matches!(self.upcoming_events.first(), Some(event) if event.time.0 < end_time) {
       self.do_next_event();
     }
     self.set_physics_time(end_time);
+    self.spawn_waves();
+    self.offer_reward_if_due();
+
+    if self.player.health <= 0.0 {
+      self.game_over = Some(GameOverCause::PlayerDied);
+    }
 
     let closest_monster_distance = self
       .movers
@@ -721,5 +873,48 @@ impl Game {
     );
 
     self.cards.draw(self, draw);
+    if let Some(offer) = &self.rewards.offer {
+      offer.draw(self, draw);
+    }
+
+    if let Some(cause) = self.game_over {
+      let message = match cause {
+        GameOverCause::PlayerDied => "You collapsed.",
+        GameOverCause::DeckDestroyed => "Your deck was destroyed.",
+      };
+      draw.text(FloatingVector::new(0.35, 0.3), 40.0, "#fff", message);
+      draw.text(
+        FloatingVector::new(0.35, 0.4),
+        24.0,
+        "#ccc",
+        &format!(
+          "You survived until day {}. Interact to start over.",
+          self.day
+        ),
+      );
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn interacting_after_game_over_restarts() {
+    let mut game = Game::with_seed(3);
+    game.update_until(2.0, OngoingIntent::Move(FloatingVector::new(1.0, 0.0)));
+    game.player.health = -1.0;
+    game.update_until(2.1, OngoingIntent::Move(FloatingVector::zeros()));
+    assert_eq!(game.game_over, Some(GameOverCause::PlayerDied));
+
+    let ui_time = game.ui_time;
+    game.initiate_interaction(WhichInteraction::PlayCard);
+    assert_eq!(game.game_over, None);
+    assert_eq!(game.physics_time, 0.0);
+    assert_eq!(game.ui_time, ui_time);
+    assert_eq!(game.player.health, game.player.maximum_health as f64);
+    assert_eq!(game.player.initiated_interaction, None);
+    game.check_invariants().unwrap();
   }
 }
@@ -1,6 +1,7 @@
 #![feature(min_type_alias_impl_trait)]
 #![feature(map_first_last)]
 
+#[cfg(target_arch = "wasm32")]
 #[allow(unused_macros)]
 macro_rules! debug {
   ($($args:tt)*) => {
@@ -8,15 +9,31 @@ macro_rules! debug {
   }
 }
 
+// there's no JS console when running natively (e.g. under `cargo test`), so use stderr
+#[cfg(not(target_arch = "wasm32"))]
+#[allow(unused_macros)]
+macro_rules! debug {
+  ($($args:tt)*) => {
+    eprintln!("{:?}", $($args)*);
+  }
+}
+
 #[macro_use]
 pub mod utils;
 pub mod actions;
+pub mod card_definitions;
 pub mod cards;
 pub mod game;
 pub mod geometry;
 pub mod mechanisms;
 pub mod movers;
+pub mod random;
+pub mod replay;
+pub mod rewards;
+#[cfg(test)]
+pub mod test_harness;
 pub mod ui_glue;
+pub mod waves;
 
 //use misc;
 //use modules::{self, Module};
@@ -5,8 +5,9 @@ use crate::geometry::{
   TILE_RADIUS, TILE_SIZE, TILE_WIDTH,
 };
 use crate::movers::{Material, Mover, MoverBehavior, MoverType, Projectile};
+use crate::random::GameRng;
 use crate::ui_glue::Draw;
-use eliduprees_web_games_lib::auto_constant;
+use crate::utils::auto_constant;
 use ordered_float::OrderedFloat;
 use rand::Rng;
 use serde::{Deserialize, Serialize};
@@ -75,6 +76,9 @@ impl<'a> MechanismUpdateContext<'a> {
   pub fn this(&self) -> &Mechanism {
     self.game.mechanism(self.position).unwrap()
   }
+  pub fn rng(&mut self) -> &mut GameRng {
+    &mut self.game.rng
+  }
   pub fn mutate_this<T, R, F: FnOnce(TypedMechanismView<T>) -> R>(&mut self, f: F) -> R {
     self
       .game
@@ -144,12 +148,24 @@ trait_enum! {
 
 impl Default for MechanismType {
   fn default() -> Self {
-    MechanismType::Deck(Deck {})
+    MechanismType::Deck(Deck::default())
   }
 }
 
 #[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
-pub struct Deck {}
+pub struct Deck {
+  pub maximum_health: i32,
+  pub health: f64,
+}
+
+impl Default for Deck {
+  fn default() -> Self {
+    Deck {
+      maximum_health: 100,
+      health: 100.0,
+    }
+  }
+}
 
 impl MechanismTrait for Deck {
   fn wake(&self, context: MechanismUpdateContext) {
@@ -188,11 +204,23 @@ impl MechanismTrait for Deck {
   }
 
   fn draw(&self, context: MechanismImmutableContext, draw: &mut dyn Draw) {
+    let center = context.position.to_floating();
+    draw.rectangle_on_map(10, center, TILE_SIZE.to_floating() * 0.9, "#f66");
+    let a = center + FloatingVector::new(0.0, TILE_RADIUS as f64 * 0.7);
     draw.rectangle_on_map(
-      10,
-      context.position.to_floating(),
-      TILE_SIZE.to_floating() * 0.9,
-      "#f66",
+      11,
+      a,
+      FloatingVector::new(TILE_WIDTH as f64 * 0.8, TILE_RADIUS as f64 * 0.2),
+      "#000",
+    );
+    draw.rectangle_on_map(
+      12,
+      a,
+      FloatingVector::new(
+        TILE_WIDTH as f64 * 0.8 * (self.health / self.maximum_health as f64).max(0.0),
+        TILE_RADIUS as f64 * 0.2,
+      ),
+      "#f00",
     );
   }
 }
@@ -217,26 +245,28 @@ impl BuildMechanismTrait for BuildMine {
 impl MechanismTrait for Mine {
   fn wake(&self, mut context: MechanismUpdateContext) {
     let position = context.position.to_floating();
+    let velocity = FloatingVector::new(
+      context.rng().gen_range(-0.5..=0.5),
+      context.rng().gen_range(-0.5..=0.5),
+    );
+    let perpendicular_position = context.rng().gen_range(-0.8..=0.8);
 
     context.game.create_mover(Mover {
       trajectory_base_time: context.game.physics_time,
       position_at_base_time: position,
-      velocity: FloatingVector::new(
-        rand::thread_rng().gen_range(-0.5..=0.5),
-        rand::thread_rng().gen_range(-0.5..=0.5),
-      ),
+      velocity,
       mover_type: MoverType::Material,
       behavior: MoverBehavior::Material(Material {
-        perpendicular_position: rand::thread_rng().gen_range(-0.8..=0.8),
+        perpendicular_position,
       }),
       ..Default::default()
     });
 
     let now = context.game.physics_time;
+    let delay = auto_constant("mine_delay", 1.0) * context.rng().gen_range(0.01..=2.0);
     context.mutate_this(|mut this: TypedMechanismView<Self>| {
       let this = this.mechanism_type_mut();
-      this.next_wake =
-        now + auto_constant("mine_delay", 1.0) * rand::thread_rng().gen_range(0.01..=2.0);
+      this.next_wake = now + delay;
     });
   }
 
@@ -434,11 +464,12 @@ impl MechanismTrait for Tower {
     }
 
     let now = context.game.physics_time;
+    let delay = TOWER_WAKE_DELAY * context.rng().gen_range(0.95..1.05);
     context.mutate_this(|mut this: TypedMechanismView<Self>| {
       let this = this.mechanism_type_mut();
       this.rebase(now);
       this.volition_at_base_time = volition.min(self.maximum_volition);
-      this.next_wake = now + TOWER_WAKE_DELAY * rand::thread_rng().gen_range(0.95..1.05);
+      this.next_wake = now + delay;
     });
   }
 
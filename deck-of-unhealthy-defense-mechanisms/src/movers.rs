@@ -1,15 +1,17 @@
-use crate::game::{Game, Time};
+use crate::game::{Game, GameOverCause, Time};
 use crate::geometry::{
-  Facing, FloatingVector, FloatingVectorExtension, GridVectorExtension, Rotation, TILE_RADIUS,
-  TILE_SIZE, TILE_WIDTH,
+  Facing, FloatingVector, FloatingVectorExtension, GridVector, GridVectorExtension, Rotation,
+  TILE_RADIUS, TILE_SIZE, TILE_WIDTH,
 };
 use crate::geometry::{GridBounds, EPSILON};
-use crate::mechanisms::{Conveyor, ConveyorSide, MechanismType};
+use crate::mechanisms::{Conveyor, ConveyorSide, Deck, MechanismType};
+use crate::random::GameRng;
 use crate::ui_glue::Draw;
-use crate::utils::Assume;
+use crate::utils::{auto_constant, Assume};
 use derivative::Derivative;
-use eliduprees_web_games_lib::auto_constant;
+use ordered_float::OrderedFloat;
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
 use std::convert::{TryFrom, TryInto};
 use std::marker::PhantomData;
 use std::ops::{Deref, DerefMut, Range};
@@ -21,6 +23,9 @@ pub enum MoverType {
   Monster,
   Projectile,
   Material,
+  MonsterShot,
+  Player,
+  Deck,
 }
 #[derive(
   Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, Debug, Default,
@@ -155,6 +160,9 @@ impl<'a> MoverUpdateContext<'a> {
       .mutate_mover(self.id, |mover| f(MoverView { mover, now }))
       .unwrap()
   }
+  pub fn rng(&mut self) -> &mut GameRng {
+    &mut self.game.rng
+  }
   // take self by value unnecessarily, to protect from accidentally doing stuff after destroyed
   pub fn destroy_this(self) {
     self.game.remove_mover(self.id);
@@ -192,12 +200,21 @@ impl<'a> MoverCollideContext<'a> {
       .mutate_mover(self.other_id, f)
       .unwrap()
   }
+  /// Whether the two movers are actually touching. A scheduled collision can be out of date if the other mover changed course without updating our schedule, so behaviors with lasting effects should check this.
+  pub fn touching(&self) -> bool {
+    let now = self.game.physics_time;
+    let (this, other) = (self.this(), self.other());
+    (other.position(now) - this.position(now)).magnitude()
+      <= this.radius + other.radius + TOUCHING_TOLERANCE
+  }
   // take self by value unnecessarily, to protect from accidentally doing stuff after destroyed
   pub fn destroy_this(self) {
     self.update_context.destroy_this();
   }
 }
 
+const TOUCHING_TOLERANCE: f64 = 0.01;
+
 #[allow(unused)]
 pub trait MoverBehaviorTrait {
   /** Perform a single scheduled update on this mover, possibly modifying the game state.
@@ -213,6 +230,8 @@ pub trait MoverBehaviorTrait {
 
   fn collide(&self, context: MoverCollideContext) {}
   fn escape_bounds(&self, context: MoverUpdateContext) {}
+  /// Called after a `Projectile` hits this mover and knocks it back.
+  fn hit_by_projectile(&self, context: MoverUpdateContext) {}
 
   fn draw(&self, context: MoverImmutableContext, draw: &mut dyn Draw);
 }
@@ -221,61 +240,371 @@ trait_enum! {
   #[derive(Clone, PartialEq, Serialize, Deserialize, Debug, Derivative)]
   #[derivative(Default)]
   pub enum MoverBehavior: MoverBehaviorTrait {
-    Monster, Projectile, #[derivative(Default)] Material,
+    Monster, Hunter, Splitter, Archer, Pathfinder, MonsterShot, Projectile, #[derivative(Default)] Material, PlayerBody, DeckBody,
   }
 }
 
+/// What every kind of monster has: somewhere to wait during the day, a part of the day when it attacks, and a schedule for steering.
 #[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
-pub struct Monster {
+pub struct MonsterBasics {
   pub home: FloatingVector,
   pub active_time: Range<f64>,
   pub next_wake: Time,
 }
 
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct Monster {
+  pub basics: MonsterBasics,
+}
+
 const MONSTER_WAKE_DELAY: Time = 0.1;
 
+impl MonsterBasics {
+  pub fn is_active(&self, context: &MoverUpdateContext) -> bool {
+    self.active_time.contains(&context.game.day_progress)
+  }
+
+  /// Where the monster should go: `active_target` during its active time, and its home the rest of the day.
+  fn target(
+    &self,
+    context: &MoverUpdateContext,
+    active_target: impl FnOnce() -> FloatingVector,
+  ) -> FloatingVector {
+    if self.is_active(context) {
+      active_target()
+    } else {
+      self.home
+    }
+  }
+}
+
+impl MoverBehavior {
+  pub fn monster_basics_mut(&mut self) -> Option<&mut MonsterBasics> {
+    match self {
+      MoverBehavior::Monster(monster) => Some(&mut monster.basics),
+      MoverBehavior::Hunter(monster) => Some(&mut monster.basics),
+      MoverBehavior::Splitter(monster) => Some(&mut monster.basics),
+      MoverBehavior::Archer(monster) => Some(&mut monster.basics),
+      MoverBehavior::Pathfinder(monster) => Some(&mut monster.basics),
+      _ => None,
+    }
+  }
+}
+
+/// The end of every monster's wake: set off at `velocity`, and wake again soon to steer some more.
+fn finish_monster_wake(context: &mut MoverUpdateContext, velocity: FloatingVector) {
+  let now = context.game.physics_time;
+  context.mutate_this(|mut this: MoverView| {
+    this.velocity = velocity;
+    this
+      .behavior
+      .monster_basics_mut()
+      .expect("only monsters can finish a monster wake")
+      .next_wake = now + MONSTER_WAKE_DELAY;
+  });
+}
+
+/// The velocity a monster should have after waking, accelerating towards `target` and slowing down in time to stop there.
+fn steer_towards(context: &MoverUpdateContext, target: FloatingVector) -> FloatingVector {
+  let relative_target = target - context.this().position(context.game.physics_time);
+
+  let acceleration = auto_constant("monster_acceleration", 4.0) * TILE_WIDTH as f64;
+  let max_speed = auto_constant("monster_max_speed", 1.6) * TILE_WIDTH as f64;
+  // Account for stopping distance:
+  // we might overshoot by an average speed of acceleration*MONSTER_WAKE_DELAY/2 due to not updating more frequently,
+  // so saturating-subtract that much
+  let target_speed = max_speed
+    .min(
+      (2.0 * relative_target.magnitude() * acceleration).sqrt()
+        - acceleration * MONSTER_WAKE_DELAY * 0.5,
+    )
+    .max(0.0);
+  //debug!("{:?}", relative_target);
+
+  let target_velocity = relative_target
+    .try_normalize(EPSILON)
+    .map_or(FloatingVector::zeros(), |target_direction| {
+      target_direction * target_speed
+    });
+  let mut velocity = context.this().velocity;
+  velocity.move_towards(target_velocity, acceleration * MONSTER_WAKE_DELAY);
+  velocity
+}
+
 impl MoverBehaviorTrait for Monster {
   fn wake(&self, mut context: MoverUpdateContext) {
-    let active = self.active_time.contains(&context.game.day_progress);
-    let target;
-    if active {
-      target = FloatingVector::zeros();
-    } else {
-      target = self.home;
+    let target = self.basics.target(&context, FloatingVector::zeros);
+    let velocity = steer_towards(&context, target);
+    finish_monster_wake(&mut context, velocity);
+  }
+
+  fn next_wake(&self, _this: &Mover) -> Option<Time> {
+    Some(self.basics.next_wake)
+  }
+
+  fn draw(&self, context: MoverImmutableContext, draw: &mut dyn Draw) {
+    draw.rectangle_on_map(
+      10,
+      context.this().position(context.game.physics_time),
+      TILE_SIZE.to_floating() * 0.8,
+      "#000",
+    );
+  }
+}
+
+/// A monster that moves toward whichever mechanism is closest to it, rather than heading straight for your deck.
+///
+/// Only the deck takes damage from monsters, so a hunter that reaches some other mechanism just waits next to it.
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct Hunter {
+  pub basics: MonsterBasics,
+}
+
+impl MoverBehaviorTrait for Hunter {
+  fn wake(&self, mut context: MoverUpdateContext) {
+    let position = context.this().position(context.game.physics_time);
+    let target = self.basics.target(&context, || {
+      context
+        .game
+        .grid
+        .iter()
+        .filter(|(tile_position, _)| context.game.mechanism(*tile_position).is_some())
+        .map(|(tile_position, _)| tile_position.to_floating())
+        .min_by_key(|&target| OrderedFloat((target - position).magnitude_squared()))
+        .unwrap_or_else(FloatingVector::zeros)
+    });
+    let velocity = steer_towards(&context, target);
+    finish_monster_wake(&mut context, velocity);
+  }
+
+  fn next_wake(&self, _this: &Mover) -> Option<Time> {
+    Some(self.basics.next_wake)
+  }
+
+  fn draw(&self, context: MoverImmutableContext, draw: &mut dyn Draw) {
+    draw.rectangle_on_map(
+      10,
+      context.this().position(context.game.physics_time),
+      TILE_SIZE.to_floating() * 0.8,
+      "#400",
+    );
+  }
+}
+
+/// A monster that breaks into two smaller ones when a projectile hits it, until it has no splits left.
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct Splitter {
+  pub basics: MonsterBasics,
+  pub splits_left: u32,
+}
+
+impl MoverBehaviorTrait for Splitter {
+  fn wake(&self, mut context: MoverUpdateContext) {
+    let target = self.basics.target(&context, FloatingVector::zeros);
+    let velocity = steer_towards(&context, target);
+    finish_monster_wake(&mut context, velocity);
+  }
+
+  fn next_wake(&self, _this: &Mover) -> Option<Time> {
+    Some(self.basics.next_wake)
+  }
+
+  fn hit_by_projectile(&self, context: MoverUpdateContext) {
+    if self.splits_left == 0 {
+      return;
+    }
+    let now = context.game.physics_time;
+    let this = context.this().clone();
+    let position = this.position(now);
+    // the pieces fly apart sideways, so that the next shot can't hit both
+    let outwards = position
+      .try_normalize(EPSILON)
+      .unwrap_or_else(|| FloatingVector::new(1.0, 0.0));
+    let sideways = FloatingVector::new(-outwards[1], outwards[0]);
+    let speed = auto_constant("splitter_separation_speed", 1.0) * TILE_WIDTH as f64;
+    for &sign in &[-1.0, 1.0] {
+      context.game.create_mover(Mover {
+        trajectory_base_time: now,
+        position_at_base_time: position + sideways * (sign * this.radius * 0.5),
+        velocity: this.velocity + sideways * (sign * speed),
+        radius: this.radius * 0.7,
+        mover_type: MoverType::Monster,
+        behavior: MoverBehavior::Splitter(Splitter {
+          basics: MonsterBasics {
+            next_wake: now + MONSTER_WAKE_DELAY,
+            ..self.basics.clone()
+          },
+          splits_left: self.splits_left - 1,
+        }),
+      });
     }
+    context.destroy_this();
+  }
+
+  fn draw(&self, context: MoverImmutableContext, draw: &mut dyn Draw) {
+    let this = context.this();
+    draw.rectangle_on_map(
+      10,
+      this.position(context.game.physics_time),
+      FloatingVector::new(this.radius * 2.0, this.radius * 2.0),
+      "#040",
+    );
+  }
+}
 
-    let relative_target = target - context.this().position(context.game.physics_time);
-
-    let acceleration = auto_constant("monster_acceleration", 4.0) * TILE_WIDTH as f64;
-    let max_speed = auto_constant("monster_max_speed", 1.6) * TILE_WIDTH as f64;
-    // Account for stopping distance:
-    // we might overshoot by an average speed of acceleration*MONSTER_WAKE_DELAY/2 due to not updating more frequently,
-    // so saturating-subtract that much
-    let target_speed = max_speed
-      .min(
-        (2.0 * relative_target.magnitude() * acceleration).sqrt()
-          - acceleration * MONSTER_WAKE_DELAY * 0.5,
-      )
-      .max(0.0);
-    //debug!("{:?}", relative_target);
+/// A monster that keeps its distance from the player and shoots at them.
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct Archer {
+  pub basics: MonsterBasics,
+  pub next_shot: Time,
+}
 
+impl MoverBehaviorTrait for Archer {
+  fn wake(&self, mut context: MoverUpdateContext) {
     let now = context.game.physics_time;
-    context.mutate_this(|mut this: MoverView| {
-      let target_velocity = relative_target
+    let position = context.this().position(now);
+    let player_position = context.game.player.position;
+    let range = auto_constant("archer_range", 6.0) * TILE_WIDTH as f64;
+    let active = self.basics.is_active(&context);
+    let target = self.basics.target(&context, || {
+      let away = (position - player_position)
         .try_normalize(EPSILON)
-        .map_or(FloatingVector::zeros(), |target_direction| {
-          target_direction * target_speed
-        });
-      this
-        .velocity
-        .move_towards(target_velocity, acceleration * MONSTER_WAKE_DELAY);
+        .unwrap_or_else(|| FloatingVector::new(1.0, 0.0));
+      player_position + away * (range * 0.8)
+    });
+    let velocity = steer_towards(&context, target);
+
+    let mut next_shot = self.next_shot;
+    let difference = player_position - position;
+    let distance = difference.magnitude();
+    if active && now >= self.next_shot && distance <= range && distance > EPSILON {
+      let speed = auto_constant("monster_shot_speed", 4.0) * TILE_WIDTH as f64;
+      context.game.create_mover(Mover {
+        trajectory_base_time: now,
+        position_at_base_time: position,
+        velocity: difference * (speed / distance),
+        mover_type: MoverType::MonsterShot,
+        behavior: MoverBehavior::MonsterShot(MonsterShot {
+          arrival_time: now + distance / speed,
+        }),
+        ..Default::default()
+      });
+      next_shot = now + auto_constant("archer_reload_time", 2.0);
+    }
 
-      this.behavior.assume::<Self>().next_wake = now + MONSTER_WAKE_DELAY;
+    context.mutate_this(|mut this: MoverView| {
+      this.behavior.assume::<Self>().next_shot = next_shot;
     });
+    finish_monster_wake(&mut context, velocity);
   }
 
   fn next_wake(&self, _this: &Mover) -> Option<Time> {
-    Some(self.next_wake)
+    Some(self.basics.next_wake)
+  }
+
+  fn draw(&self, context: MoverImmutableContext, draw: &mut dyn Draw) {
+    draw.rectangle_on_map(
+      10,
+      context.this().position(context.game.physics_time),
+      TILE_SIZE.to_floating() * 0.7,
+      "#006",
+    );
+  }
+}
+
+/// A shot fired by an `Archer`. It's aimed at where the player was when it was fired, and hurts the player if they are still there when it arrives.
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct MonsterShot {
+  pub arrival_time: Time,
+}
+
+impl MoverBehaviorTrait for MonsterShot {
+  fn wake(&self, context: MoverUpdateContext) {
+    let position = context.this().position(context.game.physics_time);
+    let hit_radius = auto_constant("monster_shot_hit_radius", 0.5) * TILE_RADIUS as f64;
+    if (context.game.player.position - position).magnitude() <= hit_radius {
+      context.game.player.health -= auto_constant("monster_shot_damage", 10.0);
+    }
+    context.destroy_this();
+  }
+
+  fn next_wake(&self, _this: &Mover) -> Option<Time> {
+    Some(self.arrival_time)
+  }
+
+  fn draw(&self, context: MoverImmutableContext, draw: &mut dyn Draw) {
+    draw.rectangle_on_map(
+      10,
+      context.this().position(context.game.physics_time),
+      TILE_SIZE.to_floating() * 0.2,
+      "#f44",
+    );
+  }
+}
+
+/// A monster that walks around mechanisms instead of through them, by searching the grid for a path.
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct Pathfinder {
+  pub basics: MonsterBasics,
+}
+
+/// The shortest path of tiles from `from` to `to`, including both, that doesn't go through any mechanisms other than one at `to`. Returns None if there is no such path, or if either end is outside the grid.
+pub fn path_around_mechanisms(
+  game: &Game,
+  from: GridVector,
+  to: GridVector,
+) -> Option<Vec<GridVector>> {
+  game.grid.get(from)?;
+  game.grid.get(to)?;
+  let mut came_from = HashMap::new();
+  let mut frontier = VecDeque::new();
+  came_from.insert(from, from);
+  frontier.push_back(from);
+  while let Some(position) = frontier.pop_front() {
+    if position == to {
+      let mut path = vec![to];
+      let mut current = to;
+      while current != from {
+        current = came_from[&current];
+        path.push(current);
+      }
+      path.reverse();
+      return Some(path);
+    }
+    for facing in Facing::ALL_FACINGS {
+      let neighbor = position + facing.unit_vector() * TILE_WIDTH;
+      if came_from.contains_key(&neighbor) || game.grid.get(neighbor).is_none() {
+        continue;
+      }
+      if neighbor != to && game.mechanism(neighbor).is_some() {
+        continue;
+      }
+      came_from.insert(neighbor, position);
+      frontier.push_back(neighbor);
+    }
+  }
+  None
+}
+
+impl MoverBehaviorTrait for Pathfinder {
+  fn wake(&self, mut context: MoverUpdateContext) {
+    let position = context.this().position(context.game.physics_time);
+    let target = self.basics.target(&context, FloatingVector::zeros);
+    // head for the next tile along the path; if there's no path (e.g. we're off the grid), go straight there
+    let waypoint = match path_around_mechanisms(
+      context.game,
+      position.containing_tile(),
+      target.containing_tile(),
+    ) {
+      Some(path) if path.len() > 2 => path[1].to_floating(),
+      _ => target,
+    };
+    let velocity = steer_towards(&context, waypoint);
+    finish_monster_wake(&mut context, velocity);
+  }
+
+  fn next_wake(&self, _this: &Mover) -> Option<Time> {
+    Some(self.basics.next_wake)
   }
 
   fn draw(&self, context: MoverImmutableContext, draw: &mut dyn Draw) {
@@ -283,7 +612,7 @@ impl MoverBehaviorTrait for Monster {
       10,
       context.this().position(context.game.physics_time),
       TILE_SIZE.to_floating() * 0.8,
-      "#000",
+      "#420",
     );
   }
 }
@@ -312,6 +641,15 @@ impl MoverBehaviorTrait for Projectile {
         let direction = target.position(now);
         target.velocity += direction.normalize() * impact;
       });
+      let other_id = context.other_id;
+      context
+        .other()
+        .behavior
+        .clone()
+        .hit_by_projectile(MoverUpdateContext {
+          id: other_id,
+          game: &mut *context.game,
+        });
       context.destroy_this();
     }
   }
@@ -326,6 +664,67 @@ impl MoverBehaviorTrait for Projectile {
   }
 }
 
+/// Knock a monster that ran into something back the way it came, so it has to run in again to do more damage.
+fn knock_back_other(context: &mut MoverCollideContext) {
+  let now = context.game.physics_time;
+  let position = context.this().position(now);
+  let knockback = auto_constant("monster_contact_knockback", 3.0) * TILE_WIDTH as f64;
+  context.mutate_other(|monster| {
+    let away = (monster.position(now) - position)
+      .try_normalize(EPSILON)
+      .unwrap_or_else(|| FloatingVector::new(1.0, 0.0));
+    monster.velocity = away * knockback;
+  });
+}
+
+/// The player's presence in the mover system, so that monsters can run into them. `Game::update_physics` moves it along with the player every update.
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct PlayerBody;
+
+impl MoverBehaviorTrait for PlayerBody {
+  fn collide(&self, mut context: MoverCollideContext) {
+    if context.other().mover_type == MoverType::Monster && context.touching() {
+      context.game.player.health -= auto_constant("monster_contact_damage", 15.0);
+      if context.game.player.health <= 0.0 {
+        context.game.game_over = Some(GameOverCause::PlayerDied);
+      }
+      knock_back_other(&mut context);
+    }
+  }
+
+  // the player is drawn by `Game::draw`
+  fn draw(&self, _context: MoverImmutableContext, _draw: &mut dyn Draw) {}
+}
+
+/// The body of the `Deck` mechanism at `deck_position`, so that monsters can run into it.
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct DeckBody {
+  pub deck_position: GridVector,
+}
+
+impl MoverBehaviorTrait for DeckBody {
+  fn collide(&self, mut context: MoverCollideContext) {
+    if context.other().mover_type == MoverType::Monster && context.touching() {
+      let damage = auto_constant("monster_deck_damage", 10.0);
+      let destroyed = context
+        .game
+        .mutate_mechanism(self.deck_position, |mechanism| {
+          let deck = mechanism.mechanism_type.assume::<Deck>();
+          deck.health -= damage;
+          deck.health <= 0.0
+        })
+        .unwrap();
+      if destroyed {
+        context.game.game_over = Some(GameOverCause::DeckDestroyed);
+      }
+      knock_back_other(&mut context);
+    }
+  }
+
+  // the deck is drawn by its mechanism
+  fn draw(&self, _context: MoverImmutableContext, _draw: &mut dyn Draw) {}
+}
+
 #[derive(Clone, PartialEq, Serialize, Deserialize, Debug, Default)]
 pub struct Material {
   pub perpendicular_position: f64,
@@ -379,3 +778,211 @@ impl MoverBehaviorTrait for Material {
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::game::GameOverCause;
+  use crate::mechanisms::BuildTower;
+  use crate::test_harness::Scenario;
+
+  const ALWAYS: Range<f64> = 0.0..1.0;
+
+  #[test]
+  fn hunter_goes_for_the_nearest_mechanism() {
+    let mut scenario = Scenario::new();
+    let tower = GridVector::new(14, 14);
+    scenario.place(tower, BuildTower);
+    let start = FloatingVector::new(8.0, 8.0);
+    let hunter = scenario.spawn_monster_with_behavior(
+      start,
+      MoverBehavior::Hunter(Hunter {
+        basics: MonsterBasics {
+          home: start,
+          active_time: ALWAYS,
+          next_wake: 0.0,
+        },
+      }),
+    );
+    scenario.advance(3.0);
+    let position = scenario
+      .game
+      .mover(hunter)
+      .unwrap()
+      .position(scenario.game.physics_time);
+    assert!(
+      (position - tower.to_floating()).magnitude() < (start - tower.to_floating()).magnitude()
+    );
+    assert!(position.magnitude() > start.magnitude());
+  }
+
+  #[test]
+  fn hunter_waits_next_to_the_mechanism_it_reaches() {
+    let mut scenario = Scenario::new();
+    let conveyor = GridVector::new(20, 20);
+    scenario.place_conveyor(conveyor, Facing::from_index(2), Facing::from_index(0));
+    let start = FloatingVector::new(14.0, 14.0);
+    let hunter = scenario.spawn_monster_with_behavior(
+      start,
+      MoverBehavior::Hunter(Hunter {
+        basics: MonsterBasics {
+          home: start,
+          active_time: ALWAYS,
+          next_wake: 0.0,
+        },
+      }),
+    );
+    let deck_health = |game: &Game| {
+      game
+        .mechanism(GridVector::zeros())
+        .unwrap()
+        .mechanism_type
+        .assume_ref::<Deck>()
+        .health
+    };
+    let initial_deck_health = deck_health(&scenario.game);
+    let near_conveyor = |game: &Game| {
+      (game.mover(hunter).unwrap().position(game.physics_time) - conveyor.to_floating()).magnitude()
+        < 2.0 * TILE_WIDTH as f64
+    };
+    assert!(scenario.advance_until(10.0, near_conveyor));
+
+    // it stays there, and nothing gets hurt
+    scenario.advance(5.0);
+    assert!(near_conveyor(&scenario.game));
+    assert!(matches!(
+      scenario.game.mechanism(conveyor).unwrap().mechanism_type,
+      MechanismType::Conveyor(_)
+    ));
+    assert_eq!(deck_health(&scenario.game), initial_deck_health);
+  }
+
+  #[test]
+  fn splitter_splits_when_shot() {
+    let mut scenario = Scenario::new();
+    scenario.place(GridVector::new(4, 0), BuildTower);
+    let position = FloatingVector::new(10.0, 0.0);
+    scenario.spawn_monster_with_behavior(
+      position,
+      MoverBehavior::Splitter(Splitter {
+        basics: MonsterBasics {
+          home: position,
+          active_time: 0.0..0.0,
+          next_wake: 0.0,
+        },
+        splits_left: 1,
+      }),
+    );
+    assert!(scenario.advance_until(10.0, |game| game
+      .movers()
+      .filter(|(_, mover)| mover.mover_type == MoverType::Monster)
+      .count()
+      == 2));
+    for (_, mut mover) in scenario.movers_of_type(MoverType::Monster) {
+      let splitter = mover.behavior.assume::<Splitter>();
+      assert_eq!(splitter.splits_left, 0);
+    }
+  }
+
+  #[test]
+  fn archer_shoots_the_player() {
+    let mut scenario = Scenario::new();
+    let position = FloatingVector::new(8.0, 0.0);
+    scenario.spawn_monster_with_behavior(
+      position,
+      MoverBehavior::Archer(Archer {
+        basics: MonsterBasics {
+          home: position,
+          active_time: ALWAYS,
+          next_wake: 0.0,
+        },
+        next_shot: 0.0,
+      }),
+    );
+    assert!(scenario.advance_until(1.0, |game| game
+      .movers()
+      .any(|(_, mover)| mover.mover_type == MoverType::MonsterShot)));
+    let maximum_health = scenario.game.player.maximum_health as f64;
+    assert!(scenario.advance_until(3.0, |game| game.player.health < maximum_health - 5.0));
+  }
+
+  #[test]
+  fn monsters_hurt_the_player_by_running_into_them() {
+    let mut scenario = Scenario::new();
+    let player_position = FloatingVector::new(-10.0, 0.0);
+    scenario.game.player.position = player_position;
+    let start = FloatingVector::new(-10.0, 6.0);
+    let monster = scenario.spawn_monster_with_behavior(
+      start,
+      MoverBehavior::Monster(Monster {
+        basics: MonsterBasics {
+          home: player_position,
+          active_time: 0.0..0.0,
+          next_wake: 0.0,
+        },
+      }),
+    );
+    let maximum_health = scenario.game.player.maximum_health as f64;
+    assert!(scenario.advance_until(5.0, |game| game.player.health < maximum_health - 10.0));
+    // the monster bounced off
+    let velocity = scenario.game.mover(monster).unwrap().velocity;
+    assert!(velocity[1] > 0.0, "{:?}", velocity);
+  }
+
+  #[test]
+  fn destroying_the_deck_ends_the_game() {
+    let mut scenario = Scenario::new();
+    scenario.game.player.position = FloatingVector::new(-10.0, 0.0);
+    scenario
+      .game
+      .mutate_mechanism(GridVector::zeros(), |mechanism| {
+        mechanism.mechanism_type.assume::<Deck>().health = 5.0;
+      });
+    scenario.spawn_monster_with_behavior(
+      FloatingVector::new(0.0, 8.0),
+      MoverBehavior::Monster(Monster {
+        basics: MonsterBasics {
+          home: FloatingVector::zeros(),
+          active_time: 0.0..0.0,
+          next_wake: 0.0,
+        },
+      }),
+    );
+    assert!(scenario.advance_until(10.0, |game| game.game_over.is_some()));
+    assert_eq!(scenario.game.game_over, Some(GameOverCause::DeckDestroyed));
+
+    // nothing happens after the game is over
+    let physics_time = scenario.game.physics_time;
+    scenario.advance(1.0);
+    assert_eq!(scenario.game.physics_time, physics_time);
+  }
+
+  #[test]
+  fn paths_go_around_mechanisms() {
+    let mut scenario = Scenario::new();
+    // a wall across the middle of the map, with the deck in the middle of it
+    for y in (-6..=6).step_by(2) {
+      if y != 0 {
+        scenario.place(GridVector::new(0, y), BuildTower);
+      }
+    }
+    let from = GridVector::new(-10, 0);
+    let to = GridVector::new(10, 0);
+    let path = path_around_mechanisms(&scenario.game, from, to).unwrap();
+    assert_eq!(path.first(), Some(&from));
+    assert_eq!(path.last(), Some(&to));
+    // straight across would be 11 tiles, and going around the wall needs at least 8 more
+    assert!(path.len() >= 19, "{:?}", path);
+    for pair in path.windows(2) {
+      assert_eq!((pair[1] - pair[0]).abs().sum(), TILE_WIDTH);
+    }
+    for &position in &path {
+      assert!(scenario.game.mechanism(position).is_none());
+    }
+
+    assert_eq!(
+      path_around_mechanisms(&scenario.game, from, GridVector::new(0, 4)).map(|path| path.len()),
+      Some(8)
+    );
+  }
+}
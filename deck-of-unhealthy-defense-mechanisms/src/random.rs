@@ -0,0 +1,112 @@
+use rand::{Error, RngCore, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+const MULTIPLIER: u64 = 6364136223846793005;
+
+/** The random number generator that lives inside `Game`.
+
+Everything random that happens during an update has to draw from this, rather than `thread_rng()`, so that the game state (which is fully serializable) determines everything that happens next. Given the same seed and the same inputs, two games will always end up in identical states.
+
+This is PCG32 (XSH RR), which is small, fast, and easy to serialize, since its whole state is two integers.
+*/
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Debug)]
+pub struct GameRng {
+  state: u64,
+  increment: u64,
+}
+
+impl GameRng {
+  fn step(&mut self) {
+    self.state = self
+      .state
+      .wrapping_mul(MULTIPLIER)
+      .wrapping_add(self.increment);
+  }
+}
+
+impl RngCore for GameRng {
+  fn next_u32(&mut self) -> u32 {
+    let old = self.state;
+    self.step();
+    let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+    let rotation = (old >> 59) as u32;
+    xorshifted.rotate_right(rotation)
+  }
+
+  fn next_u64(&mut self) -> u64 {
+    let low = self.next_u32() as u64;
+    let high = self.next_u32() as u64;
+    (high << 32) | low
+  }
+
+  fn fill_bytes(&mut self, dest: &mut [u8]) {
+    for chunk in dest.chunks_mut(4) {
+      let bytes = self.next_u32().to_le_bytes();
+      chunk.copy_from_slice(&bytes[..chunk.len()]);
+    }
+  }
+
+  fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+    self.fill_bytes(dest);
+    Ok(())
+  }
+}
+
+impl SeedableRng for GameRng {
+  type Seed = [u8; 16];
+
+  fn from_seed(seed: [u8; 16]) -> Self {
+    let mut state = [0; 8];
+    let mut stream = [0; 8];
+    state.copy_from_slice(&seed[..8]);
+    stream.copy_from_slice(&seed[8..]);
+    // the standard PCG initialization sequence
+    let mut result = GameRng {
+      state: 0,
+      increment: (u64::from_le_bytes(stream) << 1) | 1,
+    };
+    result.step();
+    result.state = result.state.wrapping_add(u64::from_le_bytes(state));
+    result.step();
+    result
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::game::{Game, OngoingIntent, WhichInteraction};
+  use crate::geometry::FloatingVector;
+
+  fn seed(state: u64, stream: u64) -> [u8; 16] {
+    let mut seed = [0; 16];
+    seed[..8].copy_from_slice(&state.to_le_bytes());
+    seed[8..].copy_from_slice(&stream.to_le_bytes());
+    seed
+  }
+
+  #[test]
+  fn matches_the_pcg32_reference_output() {
+    // the first outputs of `pcg32_srandom_r(&rng, 42, 54)` in the PCG reference implementation's demo
+    let mut rng = GameRng::from_seed(seed(42, 54));
+    let outputs: Vec<u32> = (0..6).map(|_| rng.next_u32()).collect();
+    assert_eq!(
+      outputs,
+      vec![0xa15c02b7, 0x7b47f409, 0xba1d3330, 0x83d2f293, 0xbfa4784b, 0xcbed606e]
+    );
+  }
+
+  #[test]
+  fn the_same_seed_and_inputs_make_the_same_game() {
+    let play = |seed: u64| {
+      let mut game = Game::with_seed(seed);
+      game.update_until(3.0, OngoingIntent::Move(FloatingVector::new(-1.0, 0.3)));
+      game.initiate_interaction(WhichInteraction::PlayCard);
+      game.update_until(40.0, OngoingIntent::Interact(WhichInteraction::PlayCard));
+      game
+    };
+    let game = play(11);
+    assert_eq!(game, play(11));
+    assert_ne!(game, play(12));
+  }
+}
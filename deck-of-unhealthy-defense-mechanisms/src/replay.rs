@@ -0,0 +1,136 @@
+use crate::game::{Game, OngoingIntent, WhichInteraction};
+use serde::{Deserialize, Serialize};
+
+/** The inputs that the frontend gave the game during a single frame, or during a run of frames where nothing changed.
+
+Applying the same sequence of these to a game with the same seed reproduces the same game, which makes an `InputLog` a complete bug report.
+*/
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct FrameInput {
+  /// the timestamp the frontend reported for this frame (or the last frame of the run), kept for debugging; it doesn't affect the game
+  pub frame_time: f64,
+  /// the time the game was updated until during this frame
+  pub game_time: f64,
+  pub ongoing_intent: OngoingIntent,
+  pub initiated_interaction: Option<WhichInteraction>,
+  /// how many cards to move the selection by, before anything else happens this frame
+  #[serde(default)]
+  pub card_rotations: i32,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct InputLog {
+  pub seed: u64,
+  pub frames: Vec<FrameInput>,
+  /// the state the game was in after the last frame, if it was recorded, for `InputLog::check` to compare against
+  pub final_game: Option<Game>,
+}
+
+impl FrameInput {
+  fn continues(&self, previous: &FrameInput) -> bool {
+    self.ongoing_intent == previous.ongoing_intent
+      && self.initiated_interaction.is_none()
+      && self.card_rotations == 0
+  }
+
+  pub fn apply(&self, game: &mut Game) {
+    game.rotate_card_selection(self.card_rotations);
+    if let Some(which) = self.initiated_interaction {
+      game.initiate_interaction(which);
+    }
+    game.update_until(self.game_time, self.ongoing_intent);
+  }
+}
+
+impl InputLog {
+  pub fn new(seed: u64) -> InputLog {
+    InputLog {
+      seed,
+      frames: Vec::new(),
+      final_game: None,
+    }
+  }
+
+  /** Record a frame. If it's just a continuation of the last recorded frame – the same ongoing intent, and nothing newly initiated – extend that one instead, so the log only grows when the input changes.
+
+  That's equivalent because `Game::update_until` behaves the same whether it's called once or in several steps.
+  */
+  pub fn record(&mut self, frame: FrameInput) {
+    if let Some(last) = self.frames.last_mut() {
+      if frame.continues(last) {
+        last.frame_time = frame.frame_time;
+        last.game_time = frame.game_time;
+        return;
+      }
+    }
+    self.frames.push(frame);
+  }
+
+  /// Play back the recorded frames, starting from the game that `Game::new()` made when the recording started.
+  pub fn replay(&self) -> Game {
+    let mut game = Game::with_seed(self.seed);
+    for frame in &self.frames {
+      frame.apply(&mut game);
+    }
+    game
+  }
+
+  /** Replay the log and check that it ends in the recorded final state.
+
+  Note that this can only succeed if the auto-constants have the same values during the replay as they did during the recording.
+  */
+  pub fn check(&self) -> Result<Game, String> {
+    let game = self.replay();
+    match &self.final_game {
+      Some(expected) if *expected != game => Err(format!(
+        "replay diverged from the recording after {} frames:\nexpected {:?}\ngot {:?}",
+        self.frames.len(),
+        expected,
+        game
+      )),
+      _ => Ok(game),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::geometry::FloatingVector;
+
+  #[test]
+  fn replaying_a_log_reproduces_the_game() {
+    let mut log = InputLog::new(7);
+    let mut game = Game::with_seed(7);
+    for index in 0..200 {
+      let frame = FrameInput {
+        frame_time: index as f64 * 0.5,
+        game_time: index as f64 / 30.0,
+        ongoing_intent: if index < 100 {
+          OngoingIntent::Move(FloatingVector::new(-1.0, 0.5))
+        } else {
+          OngoingIntent::Interact(WhichInteraction::PlayCard)
+        },
+        initiated_interaction: if index == 110 {
+          Some(WhichInteraction::PlayCard)
+        } else {
+          None
+        },
+        card_rotations: if index == 105 { 1 } else { 0 },
+      };
+      frame.apply(&mut game);
+      log.record(frame);
+    }
+    // the log only keeps a frame for each change of input
+    assert_eq!(log.frames.len(), 4);
+    log.final_game = Some(game);
+    log.check().unwrap();
+
+    // logs are saved as JSON, so that has to preserve every bit of the game state
+    let json = serde_json::to_string(&log).unwrap();
+    serde_json::from_str::<InputLog>(&json)
+      .unwrap()
+      .check()
+      .unwrap();
+  }
+}
@@ -0,0 +1,299 @@
+/*!
+
+Changing the deck between days.
+
+Whenever a day ends, the world pauses and you're offered a few choices, picked using the game's RNG: a new card from the `CardPool`, an upgrade to one of your cards, or removing one of your cards. You can also skip the reward. While the reward is being offered, PlayCard takes the highlighted choice and ActivateMechanism highlights the next one.
+
+While the reward is being offered, all of your cards are gathered into the draw pile, and once you've chosen, the draw pile is shuffled and you draw a fresh hand for the new day.
+
+*/
+use crate::cards::CardInstance;
+use crate::game::{Game, PlayerActionState, WhichInteraction};
+use crate::geometry::FloatingVector;
+use crate::ui_glue::Draw;
+use crate::utils::auto_constant;
+use guard::guard;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct CardPoolEntry {
+  pub card: CardInstance,
+  /// how likely this card is to be offered, relative to the other entries
+  pub weight: f64,
+  /// the first day whose reward can offer this card
+  pub first_day: i32,
+}
+
+/// All the cards that rewards can offer.
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug, Default)]
+pub struct CardPool {
+  pub entries: Vec<CardPoolEntry>,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub enum RewardOption {
+  AddCard(CardInstance),
+  /// an index into `Cards::draw_pile`, which holds all of your cards while a reward is offered
+  UpgradeCard(usize),
+  /// an index into `Cards::draw_pile`, like `UpgradeCard`
+  RemoveCard(usize),
+  Skip,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct RewardOffer {
+  pub options: Vec<RewardOption>,
+  pub highlighted: usize,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug, Default)]
+pub struct Rewards {
+  pub pool: CardPool,
+  pub offer: Option<RewardOffer>,
+  /// the day when the last reward was offered
+  rewarded_day: i32,
+}
+
+impl CardPool {
+  pub fn choose(&self, day: i32, rng: &mut impl Rng) -> Option<CardInstance> {
+    let available: Vec<&CardPoolEntry> = self
+      .entries
+      .iter()
+      .filter(|entry| entry.first_day <= day)
+      .collect();
+    available
+      .choose_weighted(rng, |entry| entry.weight)
+      .ok()
+      .map(|entry| entry.card.clone())
+  }
+}
+
+impl Rewards {
+  /// `day` is the day the game starts on, which doesn't get a reward.
+  pub fn new(pool: CardPool, day: i32) -> Rewards {
+    Rewards {
+      pool,
+      offer: None,
+      rewarded_day: day,
+    }
+  }
+}
+
+impl RewardOption {
+  fn description(&self, game: &Game) -> String {
+    let name = |index: usize| game.cards.draw_pile[index].action.display_info().name;
+    match self {
+      RewardOption::AddCard(card) => format!("Add {}", card.action.display_info().name),
+      RewardOption::UpgradeCard(index) => format!("Upgrade {}", name(*index)),
+      RewardOption::RemoveCard(index) => format!("Remove {}", name(*index)),
+      RewardOption::Skip => "Skip".to_string(),
+    }
+  }
+}
+
+impl RewardOffer {
+  pub fn rotate_highlight(&mut self, steps: i32) {
+    let length = self.options.len() as i32;
+    self.highlighted = (self.highlighted as i32 + steps).rem_euclid(length) as usize;
+  }
+
+  pub fn draw(&self, game: &Game, draw: &mut impl Draw) {
+    draw.text(
+      FloatingVector::new(0.35, 0.25),
+      32.0,
+      "#fff",
+      &format!("Day {} begins. Choose a reward:", game.day),
+    );
+    for (index, option) in self.options.iter().enumerate() {
+      let color = if index == self.highlighted {
+        "#cc0"
+      } else {
+        "#aaa"
+      };
+      draw.text(
+        FloatingVector::new(0.37, 0.32 + index as f64 * 0.05),
+        24.0,
+        color,
+        &option.description(game),
+      );
+    }
+  }
+}
+
+impl Game {
+  /// Offer a reward if a day has ended since the last one. If you're in the middle of doing something, the reward waits until you're done.
+  pub fn offer_reward_if_due(&mut self) {
+    if self.day <= self.rewards.rewarded_day
+      || self.rewards.offer.is_some()
+      || !matches!(self.player.action_state, PlayerActionState::Moving { .. })
+    {
+      return;
+    }
+    self.rewards.rewarded_day = self.day;
+    self.cards.gather();
+    let options = self.reward_options();
+    self.rewards.offer = Some(RewardOffer {
+      options,
+      highlighted: 0,
+    });
+  }
+
+  fn reward_options(&mut self) -> Vec<RewardOption> {
+    let mut options = Vec::new();
+    for _ in 0..auto_constant("reward_options", 3.0) as usize {
+      // don't offer to upgrade or remove the same card twice
+      let unoffered = |options: &Vec<RewardOption>, index: usize| {
+        !options.iter().any(|option| {
+          matches!(option, RewardOption::UpgradeCard(i) | RewardOption::RemoveCard(i) if *i == index)
+        })
+      };
+      let upgradable: Vec<usize> = (0..self.cards.draw_pile.len())
+        .filter(|&index| !self.cards.draw_pile[index].upgraded && unoffered(&options, index))
+        .collect();
+      // never let the deck shrink below a full hand
+      let removable: Vec<usize> = if self.cards.draw_pile.len() > self.cards.hand_size {
+        (0..self.cards.draw_pile.len())
+          .filter(|&index| unoffered(&options, index))
+          .collect()
+      } else {
+        Vec::new()
+      };
+
+      let roll = self.rng.gen_range(0.0..1.0);
+      let upgrade_chance = auto_constant("reward_upgrade_chance", 0.25);
+      let remove_chance = auto_constant("reward_remove_chance", 0.15);
+      let option = if roll < upgrade_chance && !upgradable.is_empty() {
+        RewardOption::UpgradeCard(*upgradable.choose(&mut self.rng).unwrap())
+      } else if roll < upgrade_chance + remove_chance && !removable.is_empty() {
+        RewardOption::RemoveCard(*removable.choose(&mut self.rng).unwrap())
+      } else {
+        guard!(let Some(card) = self.rewards.pool.choose(self.day, &mut self.rng) else { continue });
+        RewardOption::AddCard(card)
+      };
+      options.push(option);
+    }
+    options.push(RewardOption::Skip);
+    options
+  }
+
+  /// Handle an interaction while a reward is being offered.
+  pub fn reward_interaction(&mut self, which: WhichInteraction) {
+    let offer = self.rewards.offer.as_mut().unwrap();
+    match which {
+      WhichInteraction::PlayCard => {
+        let option = offer.options[offer.highlighted].clone();
+        self.take_reward(option);
+      }
+      WhichInteraction::ActivateMechanism => offer.rotate_highlight(1),
+    }
+  }
+
+  fn take_reward(&mut self, option: RewardOption) {
+    match option {
+      RewardOption::AddCard(card) => self.cards.draw_pile.push(card),
+      RewardOption::UpgradeCard(index) => self.cards.draw_pile[index].upgrade(),
+      RewardOption::RemoveCard(index) => {
+        self.cards.draw_pile.remove(index);
+      }
+      RewardOption::Skip => {}
+    }
+    self.rewards.offer = None;
+    let Game { cards, rng, .. } = self;
+    cards.reshuffle(rng);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::actions::Cost;
+  use crate::card_definitions::CardDefinitions;
+  use crate::test_harness::Scenario;
+
+  fn end_day(scenario: &mut Scenario) {
+    scenario.game.day_progress = 0.999;
+    scenario.advance(1.0);
+  }
+
+  #[test]
+  fn rewards_are_offered_at_the_end_of_the_day() {
+    let mut scenario = Scenario::new();
+    let deck_size = scenario.game.cards.owned().count();
+    end_day(&mut scenario);
+    assert_eq!(scenario.game.day, 2);
+    let offer = scenario.game.rewards.offer.clone().unwrap();
+    assert_eq!(offer.options.last(), Some(&RewardOption::Skip));
+    assert!(scenario.game.cards.hand.is_empty());
+
+    // the world waits for you to choose
+    let physics_time = scenario.game.physics_time;
+    scenario.advance(1.0);
+    assert_eq!(scenario.game.physics_time, physics_time);
+
+    scenario
+      .game
+      .initiate_interaction(WhichInteraction::ActivateMechanism);
+    assert_eq!(scenario.game.rewards.offer.as_ref().unwrap().highlighted, 1);
+    let expected_size = match &offer.options[1] {
+      RewardOption::AddCard(_) => deck_size + 1,
+      RewardOption::RemoveCard(_) => deck_size - 1,
+      _ => deck_size,
+    };
+    scenario
+      .game
+      .initiate_interaction(WhichInteraction::PlayCard);
+    assert_eq!(scenario.game.rewards.offer, None);
+    assert_eq!(scenario.game.cards.owned().count(), expected_size);
+    assert_eq!(
+      scenario.game.cards.hand.len(),
+      scenario.game.cards.hand_size
+    );
+
+    scenario.advance(1.0);
+    assert!(scenario.game.physics_time > physics_time);
+  }
+
+  #[test]
+  fn upgrades_make_cards_cheaper() {
+    let mut card = CardDefinitions::standard().instance("tower");
+    card.upgrade();
+    let info = card.action.display_info();
+    assert!(card.upgraded);
+    assert_eq!(info.name, "Defensive Tower+");
+    assert_eq!(info.health_cost, Cost::Fixed(24));
+  }
+
+  #[test]
+  fn rewards_only_offer_available_cards() {
+    let mut scenario = Scenario::new();
+    let mine = CardDefinitions::standard().instance("mine");
+    let game = &mut scenario.game;
+    game.rewards = Rewards::new(
+      CardPool {
+        entries: vec![CardPoolEntry {
+          card: mine.clone(),
+          weight: 1.0,
+          first_day: 5,
+        }],
+      },
+      1,
+    );
+    // leave nothing that could be upgraded or removed
+    game.cards.gather();
+    game.cards.draw_pile.truncate(game.cards.hand_size);
+    for card in &mut game.cards.draw_pile {
+      card.upgrade();
+    }
+
+    game.day = 2;
+    assert_eq!(game.reward_options(), vec![RewardOption::Skip]);
+    game.day = 5;
+    let options = game.reward_options();
+    assert_eq!(options.last(), Some(&RewardOption::Skip));
+    assert!(options[..options.len() - 1]
+      .iter()
+      .all(|option| *option == RewardOption::AddCard(mine.clone())));
+  }
+}
@@ -0,0 +1,182 @@
+/*!
+
+Helpers for running scripted scenarios against a `Game` natively, under `cargo test`.
+
+A `Scenario` starts from the usual starting map and deck, with a fixed seed and no waves. Since no monsters show up on their own, the tests only have to set up the things they care about: place some mechanisms, spawn some monsters, and advance time, then look at the game state to see what happened.
+
+*/
+use crate::game::{Game, OngoingIntent, Time, UPDATE_DURATION};
+use crate::geometry::{Facing, FloatingVector, GridVector, TILE_RADIUS};
+use crate::mechanisms::{BuildMechanismTrait, Conveyor, ConveyorSide, Mechanism, MechanismType};
+use crate::movers::{Monster, MonsterBasics, Mover, MoverBehavior, MoverId, MoverType};
+use crate::waves::WaveDirector;
+
+pub struct Scenario {
+  pub game: Game,
+}
+
+pub const IDLE: OngoingIntent = OngoingIntent::Move(FloatingVector::new(0.0, 0.0));
+
+impl Scenario {
+  pub fn new() -> Scenario {
+    let mut game = Game::with_seed(0);
+    game.wave_director = WaveDirector::default();
+    Scenario { game }
+  }
+
+  pub fn place(&mut self, position: GridVector, build: impl BuildMechanismTrait) -> &mut Self {
+    let mechanism = build.mechanism(&self.game);
+    self.game.create_mechanism(position, mechanism);
+    self
+  }
+
+  pub fn place_conveyor(
+    &mut self,
+    position: GridVector,
+    input: Facing,
+    output: Facing,
+  ) -> &mut Self {
+    let mut sides = [ConveyorSide::Disconnected; 4];
+    sides[input.as_index()] = ConveyorSide::Input;
+    sides[output.as_index()] = ConveyorSide::Output;
+    self.game.create_mechanism(
+      position,
+      Mechanism {
+        mechanism_type: MechanismType::Conveyor(Conveyor {
+          sides,
+          last_sent: input,
+        }),
+      },
+    );
+    self
+  }
+
+  /// A monster that stays at `position` until something pushes it, because it's never active and its home is where it starts.
+  pub fn spawn_monster(&mut self, position: FloatingVector) -> MoverId {
+    let behavior = MoverBehavior::Monster(Monster {
+      basics: MonsterBasics {
+        home: position,
+        active_time: 0.0..0.0,
+        next_wake: self.game.physics_time,
+      },
+    });
+    self.spawn_monster_with_behavior(position, behavior)
+  }
+
+  pub fn spawn_monster_with_behavior(
+    &mut self,
+    position: FloatingVector,
+    behavior: MoverBehavior,
+  ) -> MoverId {
+    self.game.create_mover(Mover {
+      trajectory_base_time: self.game.physics_time,
+      position_at_base_time: position,
+      radius: 0.8 * TILE_RADIUS as f64,
+      mover_type: MoverType::Monster,
+      behavior,
+      ..Default::default()
+    })
+  }
+
+  pub fn advance(&mut self, duration: Time) {
+    self.advance_with_intent(duration, IDLE);
+  }
+
+  pub fn advance_with_intent(&mut self, duration: Time, intent: OngoingIntent) {
+    let end = self.game.ui_time + duration;
+    self.game.update_until(end, intent);
+  }
+
+  /// Advance one update at a time until `condition` holds, giving up after `max_duration`. Returns whether the condition was met.
+  pub fn advance_until(
+    &mut self,
+    max_duration: Time,
+    mut condition: impl FnMut(&Game) -> bool,
+  ) -> bool {
+    let end = self.game.ui_time + max_duration;
+    while self.game.ui_time < end {
+      if condition(&self.game) {
+        return true;
+      }
+      let next = self.game.ui_time + UPDATE_DURATION;
+      self.game.update_until(next, IDLE);
+    }
+    condition(&self.game)
+  }
+
+  pub fn movers_of_type(&self, mover_type: MoverType) -> Vec<(MoverId, Mover)> {
+    self
+      .game
+      .movers()
+      .filter(|(_, mover)| mover.mover_type == mover_type)
+      .map(|(id, mover)| (id, mover.clone()))
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::game::WhichInteraction;
+  use crate::geometry::FloatingVectorExtension;
+  use crate::mechanisms::{BuildMine, BuildTower};
+  use crate::movers::Projectile;
+  use crate::utils::Assume;
+
+  #[test]
+  fn tower_shoots_a_nearby_monster() {
+    let mut scenario = Scenario::new();
+    scenario.place(GridVector::new(4, 0), BuildTower);
+    let monster = scenario.spawn_monster(FloatingVector::new(10.0, 0.0));
+
+    assert!(scenario.advance_until(10.0, |game| game
+      .movers()
+      .any(|(_, mover)| mover.mover_type == MoverType::Projectile)));
+    let (projectile, mut mover) = scenario.movers_of_type(MoverType::Projectile).remove(0);
+    let disappear_time = mover.behavior.assume::<Projectile>().disappear_time;
+
+    assert!(scenario.advance_until(2.0, |game| game.mover(projectile).is_none()));
+    assert!(
+      scenario.game.physics_time < disappear_time,
+      "the projectile should have hit the monster, not run out of range"
+    );
+    assert!(scenario.game.mover(monster).unwrap().velocity[0] > 0.0);
+  }
+
+  #[test]
+  fn mined_material_is_carried_by_conveyors() {
+    let mut scenario = Scenario::new();
+    let mine = GridVector::new(10, 10);
+    let conveyor = GridVector::new(12, 10);
+    let tower = GridVector::new(14, 10);
+    scenario
+      .place(mine, BuildMine)
+      .place_conveyor(conveyor, Facing::from_index(2), Facing::from_index(0))
+      .place(tower, BuildTower);
+    assert!(
+      scenario.advance_until(60.0, |game| game.movers().any(|(_, mover)| {
+        mover.mover_type == MoverType::Material
+          && mover.position(game.physics_time).containing_tile() == tower
+      }))
+    );
+  }
+
+  #[test]
+  fn playing_a_card_costs_health() {
+    let mut scenario = Scenario::new();
+    let position = GridVector::new(-6, 0);
+    scenario.game.player.position = FloatingVector::new(-6.0, 0.0);
+    scenario
+      .game
+      .initiate_interaction(WhichInteraction::PlayCard);
+    scenario.advance_with_intent(6.0, OngoingIntent::Interact(WhichInteraction::PlayCard));
+
+    // the first card is a mine, which costs 40 health, partly offset by regeneration
+    assert!(matches!(
+      scenario.game.mechanism(position).unwrap().mechanism_type,
+      MechanismType::Mine(_)
+    ));
+    let health = scenario.game.player.health;
+    assert!(health > 55.0 && health < 85.0, "health was {}", health);
+  }
+}
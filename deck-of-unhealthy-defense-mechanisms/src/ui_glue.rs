@@ -1,5 +1,6 @@
 use crate::game::{Game, OngoingIntent, WhichInteraction};
 use crate::geometry::FloatingVector;
+use crate::replay::{FrameInput, InputLog};
 use serde::Deserialize;
 use std::cell::RefCell;
 use std::panic;
@@ -21,14 +22,17 @@ pub mod js {
 
 struct State {
   game: Game,
+  input_log: InputLog,
   last_frame_time: Option<f64>,
   accumulated_game_time: f64,
 }
 
 thread_local! {
   static STATE: RefCell<State> = {
+    let seed = rand::random();
     RefCell::new(State {
-      game : Game::new(),
+      game : Game::with_seed(seed),
+      input_log: InputLog::new(seed),
       last_frame_time: None,
     accumulated_game_time:0.0,
     })
@@ -55,10 +59,22 @@ pub fn rust_init() {
   //with_state(|state| {});
 }
 
+/// The inputs recorded since the game started, along with the current state, as JSON that the native `replay` binary can check.
+#[wasm_bindgen]
+pub fn rust_input_log() -> String {
+  with_state(|state| {
+    state.input_log.final_game = Some(state.game.clone());
+    let json = serde_json::to_string(&state.input_log).unwrap();
+    state.input_log.final_game = None;
+    json
+  })
+}
+
 #[derive(Clone, Deserialize)]
 pub struct StateFromJs {
   pub ongoing_intent: OngoingIntent,
   pub initiated_interaction: Option<WhichInteraction>,
+  pub card_rotations_since_last_frame: i32,
   pub canvas_physical_size: FloatingVector,
   pub canvas_css_size: FloatingVector,
 }
@@ -103,6 +119,7 @@ pub fn rust_do_frame(frame_time: f64, state_from_js: JsValue) {
   let StateFromJs {
     ongoing_intent: intent,
     initiated_interaction,
+    card_rotations_since_last_frame,
     canvas_physical_size,
     canvas_css_size: _,
   } = &state_from_js;
@@ -116,13 +133,15 @@ pub fn rust_do_frame(frame_time: f64, state_from_js: JsValue) {
     }
     state.last_frame_time = Some(frame_time);
 
-    if let &Some(which) = initiated_interaction {
-      state.game.initiate_interaction(which);
-    }
-
-    state
-      .game
-      .update_until(state.accumulated_game_time, *intent);
+    let frame = FrameInput {
+      frame_time,
+      game_time: state.accumulated_game_time,
+      ongoing_intent: *intent,
+      initiated_interaction: *initiated_interaction,
+      card_rotations: *card_rotations_since_last_frame,
+    };
+    frame.apply(&mut state.game);
+    state.input_log.record(frame);
 
     let mut draw = ProvisionalDraw::default();
     state.game.draw(&mut draw);
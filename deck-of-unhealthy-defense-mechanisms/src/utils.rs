@@ -1,6 +1,27 @@
 // Similar to the crate `trait_enum`, but with some improvements and adding From/TryFrom impls
 
 use std::convert::{TryFrom, TryInto};
+
+/// Tunable constants: in the browser, these can be adjusted live from JS.
+#[cfg(target_arch = "wasm32")]
+pub use eliduprees_web_games_lib::auto_constant;
+
+/// The types that the JS version of `auto_constant` can read back from the page, so that native builds reject the same types that wasm builds do.
+#[cfg(not(target_arch = "wasm32"))]
+pub trait AutoConstantValue {}
+#[cfg(not(target_arch = "wasm32"))]
+impl AutoConstantValue for f64 {}
+#[cfg(not(target_arch = "wasm32"))]
+impl AutoConstantValue for bool {}
+#[cfg(not(target_arch = "wasm32"))]
+impl AutoConstantValue for String {}
+
+/// Tunable constants: natively (e.g. under `cargo test`) there's no JS to adjust them, so they always have their default values.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn auto_constant<T: AutoConstantValue>(_name: &str, default: T) -> T {
+  default
+}
+
 macro_rules! trait_enum {
     (
         $(#[$attr:meta])*
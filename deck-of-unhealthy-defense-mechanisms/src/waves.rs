@@ -0,0 +1,319 @@
+/*!
+
+Spawning monsters as the days go by.
+
+Each `WaveDefinition` says when during the day a wave arrives, which days it happens on, and how many monsters of which kind it brings, with the count growing each day. The monsters appear at the horizon (or `monster_spawn_distance` tiles away, if the horizon is farther than that) and each one gets its own home and active time, picked from the ranges in the definition using the game's RNG.
+
+The standard waves are loaded from `data/waves.json`, so they can be rebalanced without touching the code. They're checked when they're loaded, so a mistake in the file shows up as an error naming the wave, rather than as a panic in the middle of a game.
+
+*/
+use crate::game::Game;
+use crate::geometry::{FloatingVector, TILE_RADIUS, TILE_WIDTH};
+use crate::movers::{
+  Archer, Hunter, Monster, MonsterBasics, Mover, MoverBehavior, MoverType, Pathfinder, Splitter,
+};
+use crate::utils::auto_constant;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::f64::consts::TAU;
+use std::ops::Range;
+
+#[derive(Copy, Clone, PartialEq, Eq, Serialize, Deserialize, Debug)]
+pub enum MonsterKind {
+  Chaser,
+  Hunter,
+  Splitter,
+  Archer,
+  Pathfinder,
+}
+
+impl Default for MonsterKind {
+  fn default() -> Self {
+    MonsterKind::Chaser
+  }
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct WaveDefinition {
+  #[serde(default)]
+  pub kind: MonsterKind,
+  pub first_day: i32,
+  pub last_day: Option<i32>,
+  /// how far through the day the wave arrives, from 0.0 to 1.0
+  pub day_progress: f64,
+  pub base_count: f64,
+  /// how many more monsters the wave brings for each day after `first_day`
+  pub extra_per_day: f64,
+  /// how far from the deck each monster's home is, in tiles
+  pub home_distance: Range<f64>,
+  /// the day progress when each monster starts attacking
+  pub active_start: Range<f64>,
+  /// how much of the day each monster keeps attacking for
+  pub active_duration: Range<f64>,
+  /// how many times each monster splits in two when it's shot, for `Splitter` waves
+  #[serde(default)]
+  pub splits: u32,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug, Default)]
+pub struct WaveDirector {
+  pub waves: Vec<WaveDefinition>,
+  /// the day that `spawned` refers to
+  day: i32,
+  /// indices of the waves that have already arrived on `day`
+  spawned: Vec<usize>,
+}
+
+impl WaveDefinition {
+  pub fn happens_on(&self, day: i32) -> bool {
+    day >= self.first_day && self.last_day.map_or(true, |last_day| day <= last_day)
+  }
+
+  pub fn validate(&self) -> Result<(), String> {
+    let ranges = [
+      ("home_distance", &self.home_distance),
+      ("active_start", &self.active_start),
+      ("active_duration", &self.active_duration),
+    ];
+    for (name, range) in ranges.iter() {
+      // `gen_range` panics on empty ranges
+      if range.is_empty() {
+        return Err(format!(
+          "{} is {:?}, but it must be a nonempty range",
+          name, range
+        ));
+      }
+    }
+    if let Some(last_day) = self.last_day {
+      if last_day < self.first_day {
+        return Err(format!(
+          "last_day is {}, which is before first_day ({})",
+          last_day, self.first_day
+        ));
+      }
+    }
+    if !(0.0..=1.0).contains(&self.day_progress) {
+      return Err(format!(
+        "day_progress is {}, but it must be between 0 and 1",
+        self.day_progress
+      ));
+    }
+    if !self.base_count.is_finite() || self.base_count < 0.0 {
+      return Err(format!(
+        "base_count is {}, but it must be a nonnegative number",
+        self.base_count
+      ));
+    }
+    if !self.extra_per_day.is_finite() {
+      return Err(format!(
+        "extra_per_day is {}, but it must be a number",
+        self.extra_per_day
+      ));
+    }
+    Ok(())
+  }
+
+  pub fn count(&self, day: i32) -> usize {
+    (self.base_count + self.extra_per_day * (day - self.first_day) as f64)
+      .floor()
+      .max(0.0) as usize
+  }
+}
+
+impl WaveDirector {
+  pub fn new(waves: Vec<WaveDefinition>) -> WaveDirector {
+    WaveDirector {
+      waves,
+      day: 0,
+      spawned: Vec::new(),
+    }
+  }
+}
+
+/// Parse and validate a list of wave definitions in JSON.
+pub fn load_waves(json: &str) -> Result<Vec<WaveDefinition>, String> {
+  let waves: Vec<WaveDefinition> =
+    serde_json::from_str(json).map_err(|error| format!("invalid wave definitions: {}", error))?;
+  for (index, wave) in waves.iter().enumerate() {
+    wave
+      .validate()
+      .map_err(|problem| format!("wave {} ({:?}): {}", index, wave.kind, problem))?;
+  }
+  Ok(waves)
+}
+
+pub fn standard_waves() -> Vec<WaveDefinition> {
+  load_waves(include_str!("../data/waves.json")).unwrap()
+}
+
+impl Game {
+  /// Spawn every wave that is due and hasn't arrived yet today.
+  pub fn spawn_waves(&mut self) {
+    let director = &mut self.wave_director;
+    if director.day != self.day {
+      director.day = self.day;
+      director.spawned.clear();
+    }
+    let mut due = Vec::new();
+    for (index, wave) in director.waves.iter().enumerate() {
+      if wave.happens_on(self.day)
+        && self.day_progress >= wave.day_progress
+        && !director.spawned.contains(&index)
+      {
+        director.spawned.push(index);
+        due.push(wave.clone());
+      }
+    }
+
+    for wave in due {
+      for _ in 0..wave.count(self.day) {
+        self.spawn_wave_monster(&wave);
+      }
+    }
+  }
+
+  fn spawn_wave_monster(&mut self, wave: &WaveDefinition) {
+    let angle = self.rng.gen_range(0.0..TAU);
+    let direction = FloatingVector::new(angle.cos(), angle.sin());
+    let home_distance = self.rng.gen_range(wave.home_distance.clone()) * TILE_WIDTH as f64;
+    let active_start = self.rng.gen_range(wave.active_start.clone());
+    let active_duration = self.rng.gen_range(wave.active_duration.clone());
+    let spawn_distance = self
+      .horizon
+      .min(auto_constant("monster_spawn_distance", 15.0) * TILE_WIDTH as f64)
+      .max(home_distance);
+
+    let basics = MonsterBasics {
+      home: direction * home_distance,
+      active_time: active_start..(active_start + active_duration).min(1.0),
+      next_wake: self.physics_time,
+    };
+    let behavior = match wave.kind {
+      MonsterKind::Chaser => MoverBehavior::Monster(Monster { basics }),
+      MonsterKind::Hunter => MoverBehavior::Hunter(Hunter { basics }),
+      MonsterKind::Splitter => MoverBehavior::Splitter(Splitter {
+        basics,
+        splits_left: wave.splits,
+      }),
+      MonsterKind::Archer => MoverBehavior::Archer(Archer {
+        next_shot: basics.next_wake,
+        basics,
+      }),
+      MonsterKind::Pathfinder => MoverBehavior::Pathfinder(Pathfinder { basics }),
+    };
+
+    self.create_mover(Mover {
+      trajectory_base_time: self.physics_time,
+      position_at_base_time: direction * spawn_distance,
+      radius: 0.8 * TILE_RADIUS as f64,
+      mover_type: MoverType::Monster,
+      behavior,
+      ..Default::default()
+    });
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::rewards::{CardPool, Rewards};
+  use crate::test_harness::Scenario;
+
+  fn monster_count(scenario: &Scenario) -> usize {
+    scenario.movers_of_type(MoverType::Monster).len()
+  }
+
+  #[test]
+  fn standard_waves_load() {
+    let waves = standard_waves();
+    assert!(!waves.is_empty());
+    assert!(waves.iter().any(|wave| wave.happens_on(1)));
+  }
+
+  #[test]
+  fn invalid_waves_are_rejected() {
+    let wave = WaveDefinition {
+      kind: MonsterKind::Chaser,
+      first_day: 2,
+      last_day: None,
+      day_progress: 0.5,
+      base_count: 1.0,
+      extra_per_day: 1.0,
+      home_distance: 5.0..6.0,
+      active_start: 0.4..0.5,
+      active_duration: 0.2..0.3,
+      splits: 0,
+    };
+    let load = |wave: &WaveDefinition| load_waves(&serde_json::to_string(&[wave]).unwrap());
+    assert_eq!(load(&wave), Ok(vec![wave.clone()]));
+
+    let invalid = [
+      WaveDefinition {
+        home_distance: 5.0..5.0,
+        ..wave.clone()
+      },
+      WaveDefinition {
+        active_duration: 0.3..0.2,
+        ..wave.clone()
+      },
+      WaveDefinition {
+        last_day: Some(1),
+        ..wave.clone()
+      },
+      WaveDefinition {
+        day_progress: 1.5,
+        ..wave.clone()
+      },
+      WaveDefinition {
+        base_count: -1.0,
+        ..wave.clone()
+      },
+    ];
+    for wave in invalid.iter() {
+      assert!(load(wave).is_err(), "{:?} should be invalid", wave);
+    }
+    assert!(load_waves("[{}]")
+      .unwrap_err()
+      .contains("invalid wave definitions"));
+  }
+
+  #[test]
+  fn waves_grow_each_day() {
+    let wave = WaveDefinition {
+      kind: MonsterKind::Chaser,
+      first_day: 2,
+      last_day: Some(5),
+      day_progress: 0.0,
+      base_count: 2.0,
+      extra_per_day: 1.5,
+      home_distance: 5.0..6.0,
+      active_start: 0.4..0.5,
+      active_duration: 0.2..0.3,
+      splits: 0,
+    };
+    assert!(!wave.happens_on(1));
+    assert!(!wave.happens_on(6));
+    assert_eq!(
+      (2..=5).map(|day| wave.count(day)).collect::<Vec<_>>(),
+      vec![2, 3, 5, 6]
+    );
+
+    let mut scenario = Scenario::new();
+    scenario.game.wave_director = WaveDirector::new(vec![wave]);
+    // jumping straight to later days shouldn't stop the world to offer rewards
+    scenario.game.rewards = Rewards::new(CardPool::default(), i32::MAX);
+    scenario.advance(1.0);
+    assert_eq!(monster_count(&scenario), 0);
+
+    scenario.game.day = 3;
+    scenario.advance(1.0);
+    assert_eq!(monster_count(&scenario), 3);
+    // the wave only arrives once per day
+    scenario.advance(1.0);
+    assert_eq!(monster_count(&scenario), 3);
+
+    scenario.game.day = 4;
+    scenario.advance(1.0);
+    assert_eq!(monster_count(&scenario), 8);
+  }
+}
@@ -1,6 +1,6 @@
 "use strict";
 
-import init, { rust_init, rust_do_frame, }
+import init, { rust_init, rust_do_frame, rust_input_log, }
   from '/deck-of-unhealthy-defense-mechanisms/pkg/deck_of_unhealthy_defense_mechanisms.js';
 
 const canvas = document.getElementById("canvas");
@@ -10,8 +10,9 @@ const action_keys = {
   KeyZ: "PlayCard",
   KeyX: "ActivateMechanism",
 };
+const download_input_log_key = "KeyL";
 const rotate_keys = {
-  KeyX: -1,
+  KeyC: -1,
   KeyV: 1,
 };
 const direction_keys = {
@@ -65,6 +66,16 @@ window.draw_text = (x, y, size, color, text) => {
   context.fillText(text, x, y);
 };
 
+// saves everything you did this session, so the native `replay` binary can reproduce it
+const download_input_log = () => {
+  const blob = new Blob([rust_input_log()], {type: "application/json"});
+  const link = document.createElement("a");
+  link.href = URL.createObjectURL(blob);
+  link.download = "deck-of-unhealthy-defense-mechanisms-input-log.json";
+  link.click();
+  URL.revokeObjectURL(link.href);
+};
+
 window.debug = message => {
   document.getElementById("debug").textContent += message;
 }
@@ -98,6 +109,10 @@ document.body.addEventListener("keydown", (event) => {
     event.preventDefault();
     card_rotations_since_last_frame += rotate_keys[key];
   }
+  if (key === download_input_log_key) {
+    event.preventDefault();
+    download_input_log();
+  }
 });
 document.body.addEventListener("keyup", (event) => {
   const key = event.code;
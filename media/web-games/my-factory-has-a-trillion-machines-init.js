@@ -1,6 +1,6 @@
 "use strict";
 
-import init, { MouseCssPositionOnMap, ClickType, rust_init, do_frame, rust_mousedown, rust_mousemove, rust_mouseup, }
+import init, { MouseCssPositionOnMap, ClickType, KeyPress, rust_init, do_frame, rust_mousedown, rust_mousemove, rust_mouseup, rust_keydown, rust_exit_module_to_depth, rust_restore_session, rust_discard_saved_session, }
   from '/my-factory-has-a-trillion-machines-web-frontend/pkg/my_factory_has_a_trillion_machines_web_frontend.js';
 
 async function run() {
@@ -9,6 +9,10 @@ async function run() {
 const leaflet = L;
 const canvas = document.getElementById("canvas");
 const context = canvas.getContext('2d');
+// machines and other things that don't move get drawn here, and only redrawn when the game or the view changes
+const static_canvas = document.createElement("canvas");
+const static_context = static_canvas.getContext('2d');
+let draw_context = context;
 
 var roundUpToPowerOfTwo = (n) => {
   console.assert(n >= 1 && n <= (1 << 30), "roundUpToPowerOfTwo range error");
@@ -136,6 +140,10 @@ leaflet.marker ([0, 0]).addTo (leaflet_map).bindPopup ("thingy thingy").openPopu
 
 const app_element = document.getElementById("app");
 const inventory_element = document.getElementById("inventory");
+const goals_element = document.getElementById("goals");
+const placement_problems_element = document.getElementById("placement_problems");
+const breadcrumbs_element = document.getElementById("breadcrumbs");
+const sidebar_element = document.getElementById("sidebar");
 
 
 function mousedown_callback(event) {
@@ -155,6 +163,15 @@ leaflet_map.on("mousedown", function(event) { mousedown_callback(event.originalE
 //window.leaflet_map.on("contextmenu", function(e) {e.preventDefault()});
 document.body.addEventListener("mouseup", mouse_callback (rust_mouseup));
 document.body.addEventListener("mousemove", mouse_callback (rust_mousemove));
+document.body.addEventListener("keydown", function(event) {
+  // don't steal keys from text fields
+  if (event.target instanceof HTMLTextAreaElement || (event.target instanceof HTMLInputElement && event.target.type === "text")) {
+    return;
+  }
+  if (rust_keydown(new KeyPress({key: event.key, ctrl: event.ctrlKey || event.metaKey, shift: event.shiftKey, alt: event.altKey}))) {
+    event.preventDefault();
+  }
+});
 
 
 window.init_machine_type = function (name) {
@@ -180,6 +197,80 @@ window.init_machine_type = function (name) {
     console.log(clear_canvas);
 };
 
+window.select_machine_type = function (name) {
+  // clicking also runs the radio button's handler, which toggles map dragging
+  document.getElementById(`machine_choice_${name}`).click();
+};
+
+window.set_map_center = function (x, y) {
+  leaflet_map.panTo([y, x], {animate: false});
+};
+
+window.update_breadcrumbs = function (names) {
+  breadcrumbs_element.textContent = "";
+  names.forEach((name, depth) => {
+    if (depth > 0) {
+      breadcrumbs_element.appendChild(document.createTextNode(" › "));
+    }
+    const crumb = document.createElement("a");
+    crumb.href = "#";
+    crumb.textContent = name;
+    crumb.addEventListener("click", (event) => {
+      event.preventDefault();
+      rust_exit_module_to_depth(depth);
+    });
+    breadcrumbs_element.appendChild(crumb);
+  });
+};
+
+window.local_storage_get = function (key) {
+  try {
+    return window.localStorage.getItem(key) ?? undefined;
+  } catch (error) {
+    console.warn("couldn't read localStorage: ", error);
+    return undefined;
+  }
+};
+
+window.local_storage_set = function (key, value) {
+  try {
+    window.localStorage.setItem(key, value);
+    return true;
+  } catch (error) {
+    return false;
+  }
+};
+
+window.local_storage_remove = function (key) {
+  try {
+    window.localStorage.removeItem(key);
+  } catch (error) {
+    console.warn("couldn't write localStorage: ", error);
+  }
+};
+
+window.console_warn = function (message) {
+  console.warn(message);
+};
+
+window.offer_restore = function () {
+  const offer = document.createElement("div");
+  offer.id = "restore_offer";
+  offer.textContent = "Restore your previous session? ";
+  const choice = (label, callback) => {
+    const button = document.createElement("button");
+    button.textContent = label;
+    button.addEventListener("click", () => {
+      offer.remove();
+      callback();
+    });
+    offer.appendChild(button);
+  };
+  choice("Restore", rust_restore_session);
+  choice("Start over", rust_discard_saved_session);
+  sidebar_element.prepend(offer);
+};
+
 window.gather_dom_samples = function () {
   var map_zoom = leaflet_map.getZoom();
   var offset = canvas.getBoundingClientRect();
@@ -199,9 +290,25 @@ window.clear_canvas = function () {
   context.fillRect(0, 0, context.canvas.width, context.canvas.height);
 };
 
+window.begin_static_layer = function () {
+  // resizing also clears the canvas
+  static_canvas.width = canvas.width;
+  static_canvas.height = canvas.height;
+  draw_context = static_context;
+};
+
+window.end_static_layer = function () {
+  draw_context = context;
+};
+
+window.draw_static_layer = function () {
+  context.drawImage(static_canvas, 0, 0);
+};
+
 window.draw_sprite = function (
   sprite, cx, cy, sx, sy, quarter_turns_from_posx_towards_posy,
 ) {
+  const context = draw_context;
   context.save();
   //context.scale(context.canvas.width, context.canvas.height);
   context.translate (cx, cy);
@@ -233,6 +340,19 @@ window.update_inventory = function (inventory) {
   }
 };
 
+window.update_goals = function (goals) {
+  goals_element.textContent = "";
+  for (const [name, fraction] of goals) {
+    const element = document.createElement("div");
+    element.textContent = `${name}: ${Math.floor(fraction * 100)}%`;
+    goals_element.appendChild(element);
+  }
+};
+
+window.show_placement_problems = function (descriptions) {
+  placement_problems_element.textContent = descriptions.length === 0 ? "" : `Can't build here: ${descriptions.join("; ")}`;
+};
+
 
   rust_init();
 
@@ -1,26 +1,34 @@
 use nalgebra::Vector2;
 use num::Integer;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::cell::RefCell;
 use std::cmp::max;
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
 use wasm_bindgen::prelude::*;
 
 use eliduprees_web_games_lib::now;
 
+use my_factory_has_a_trillion_machines::autosave::{Autosaver, SessionSnapshot, SnapshotStorage};
 use my_factory_has_a_trillion_machines::geometry::{
-  Facing, GridIsomorphism, Number, Rotate, Rotation, Vector, VectorExtension,
+  Facing, GridIsomorphism, Number, Rotate, Rotation, TransformedBy, Vector, VectorExtension,
 };
 use my_factory_has_a_trillion_machines::graph_algorithms::{
   BaseAspect, FutureAspect, GameFuture, GameView, SelectedAspect, WorldRegionView,
 };
+use my_factory_has_a_trillion_machines::keymap::{KeyChord, Keymap, UiCommand};
 use my_factory_has_a_trillion_machines::machine_data::{
-  Game, MachineState, MachineType, MachineTypeId, MachineTypeTrait, MachineTypes, Material,
-  PlatonicMachine, PlatonicRegionContents, WorldMachinesMap, TIME_TO_MOVE_MATERIAL,
+  Game, GlobalMachine, MachineState, MachineTypeId, MachineTypeRef, MachineTypeTrait, MachineTypes,
+  Material, PlatonicMachine, PlatonicRegionContents, WorldMachinesMap, TIME_TO_MOVE_MATERIAL,
+};
+use my_factory_has_a_trillion_machines::placement::PlacementProblem;
+use my_factory_has_a_trillion_machines::primitive_machines;
+use my_factory_has_a_trillion_machines::progression::{self, Progression};
+use my_factory_has_a_trillion_machines::ui::{selection_flip, selection_rotation, turn_about};
+use my_factory_has_a_trillion_machines::undo_history::{
+  AddRemoveMachines, AddRemoveMachinesError, EditModule, EditModuleError, InsufficientMaterials,
 };
-use my_factory_has_a_trillion_machines::undo_history::AddRemoveMachines;
-use my_factory_has_a_trillion_machines::{modules, primitive_machines};
 //use misc;
 //use modules::{self, Module};
 
@@ -41,26 +49,28 @@ mod js {
       sy: f32,
       quarter_turns_from_posx_towards_posy: u8,
     );
+    pub fn begin_static_layer();
+    pub fn end_static_layer();
+    pub fn draw_static_layer();
     pub fn update_inventory(inventory: JsValue);
+    pub fn update_goals(goals: JsValue);
+    pub fn show_placement_problems(descriptions: JsValue);
+    pub fn select_machine_type(machine_type_name: String);
+    pub fn set_map_center(x: f64, y: f64);
+    pub fn update_breadcrumbs(names: JsValue);
+    pub fn local_storage_get(key: &str) -> Option<String>;
+    /// returns false if the value couldn't be stored, e.g. because storage is full
+    pub fn local_storage_set(key: &str, value: &str) -> bool;
+    pub fn local_storage_remove(key: &str);
+    pub fn offer_restore();
+    pub fn console_warn(message: String);
   }
 }
 
 thread_local! {
   static STATE: RefCell<State> = {
-    let mut game = Game {
-      global_region: PlatonicRegionContents {
-        machines: Vec::new(),
-      },
-      last_change_time: 0,
-      inventory_before_last_change: Default::default(),
-      undo_stack: Vec::new(),
-      machine_types: MachineTypes {
-        presets: machine_presets(),
-        custom_modules: Vec::new(),
-      },
-      last_disturbed_times: WorldMachinesMap::default(),
-      redo_stack: Vec::new(),
-    };
+    let mut game = Game::new(progression::starting_presets(), Vec::new());
+    game.progression = Progression::new(progression::standard_goals());
     game
       .inventory_before_last_change
       .insert(Material::Iron, 1000);
@@ -74,10 +84,52 @@ thread_local! {
       current_game_time: 0,
       mouse: Default::default(),
       queued_mouse_moves: VecDeque::new(),
+      static_sprites: Default::default(),
+      static_layer_key: None,
+      keymap: Keymap::default(),
+      module_path: Vec::new(),
+      autosaver: Autosaver::new(LocalStorage, AUTOSAVE_INTERVAL),
     })
   }
 }
 
+/// Set by the panic hook. After a panic, the state can't be trusted, so we stop autosaving
+/// and leave the last snapshot from before the panic in place.
+static PANICKED: AtomicBool = AtomicBool::new(false);
+
+/// seconds between autosaves
+const AUTOSAVE_INTERVAL: f64 = 10.0;
+
+struct LocalStorage;
+
+#[derive(Debug)]
+struct LocalStorageFull;
+
+impl SnapshotStorage for LocalStorage {
+  type Error = LocalStorageFull;
+  fn load(&self, key: &str) -> Result<Option<String>, LocalStorageFull> {
+    Ok(js::local_storage_get(key))
+  }
+  fn store(&mut self, key: &str, value: &str) -> Result<(), LocalStorageFull> {
+    if js::local_storage_set(key, value) {
+      Ok(())
+    } else {
+      Err(LocalStorageFull)
+    }
+  }
+  fn remove(&mut self, key: &str) -> Result<(), LocalStorageFull> {
+    js::local_storage_remove(key);
+    Ok(())
+  }
+}
+
+/// The parts of `State`, other than the game itself, that are worth keeping between sessions.
+#[derive(Serialize, Deserialize)]
+struct SavedUiData {
+  keymap: Keymap,
+  module_path: Vec<GridIsomorphism>,
+}
+
 fn with_state<R>(f: impl FnOnce(&mut State) -> R) -> R {
   STATE.with(|state| {
     let mut guard = state.borrow_mut();
@@ -159,6 +211,65 @@ struct State {
   current_game_time: Number,
   mouse: MouseState,
   queued_mouse_moves: VecDeque<MouseCssPositionOnMap>,
+  static_sprites: StaticSpritesCache,
+  /// the view the static layer was last drawn for, or None if it needs to be redrawn regardless
+  static_layer_key: Option<StaticLayerKey>,
+  keymap: Keymap,
+  /// The world positions of the module instances we've entered, outermost first.
+  /// While this is nonempty, the map shows the innermost one in its own frame,
+  /// and building or deleting machines edits its module type.
+  module_path: Vec<GridIsomorphism>,
+  autosaver: Autosaver<LocalStorage>,
+}
+
+/// One step of `State::module_path`, resolved against the current game.
+struct EnteredModule {
+  type_id: MachineTypeId,
+  isomorphism: GridIsomorphism,
+  name: String,
+}
+
+impl State {
+  /// Resolves `module_path`, or returns None if it no longer matches the game.
+  fn entered_modules(&self) -> Option<Vec<EnteredModule>> {
+    let mut result: Vec<EnteredModule> = Vec::with_capacity(self.module_path.len());
+    let mut region = &self.game.global_region;
+    for &isomorphism in &self.module_path {
+      let frame = result
+        .last()
+        .map_or_else(GridIsomorphism::default, |parent| parent.isomorphism);
+      let machine = region
+        .machines
+        .iter()
+        .find(|machine| machine.state.position * frame == isomorphism)?;
+      let module = match self.game.machine_types.get(machine.type_id) {
+        MachineTypeRef::Module(module) => module,
+        _ => return None,
+      };
+      region = &module.region;
+      result.push(EnteredModule {
+        type_id: machine.type_id,
+        isomorphism,
+        name: module.module_type.info.name.clone(),
+      });
+    }
+    Some(result)
+  }
+
+  /// The machines of the region currently being edited, positioned relative to `current_frame()`.
+  fn current_region(&self) -> &PlatonicRegionContents {
+    match self
+      .entered_modules()
+      .and_then(|modules| modules.last().map(|m| m.type_id))
+    {
+      Some(type_id) => &self.game.machine_types.get_module(type_id).region,
+      None => &self.game.global_region,
+    }
+  }
+
+  fn current_frame(&self) -> GridIsomorphism {
+    self.module_path.last().copied().unwrap_or_default()
+  }
 }
 
 type StateViewAspects = (BaseAspect, SelectedAspect, FutureAspect);
@@ -188,14 +299,47 @@ impl DomSamples {
   }
 }
 
-fn machine_presets() -> Vec<MachineType> {
-  vec![
-    primitive_machines::conveyor(),
-    primitive_machines::splitter(),
-    primitive_machines::iron_smelter(),
-    primitive_machines::iron_mine(),
-    modules::basic_module(),
-  ]
+/// The part of the world that is currently visible on the canvas, in world coordinates.
+#[derive(Copy, Clone, Debug)]
+struct Viewport {
+  min: Vector2<f64>,
+  max: Vector2<f64>,
+}
+
+impl Viewport {
+  fn new(samples: &DomSamples) -> Viewport {
+    let half_size = samples.canvas_backing_size * (0.5 / samples.map_backing_scale());
+    Viewport {
+      min: samples.map_world_center - half_size,
+      max: samples.map_world_center + half_size,
+    }
+  }
+  fn overlaps(&self, center: Vector2<f64>, half_extent: f64) -> bool {
+    center[0] + half_extent >= self.min[0]
+      && center[0] - half_extent <= self.max[0]
+      && center[1] + half_extent >= self.min[1]
+      && center[1] - half_extent <= self.max[1]
+  }
+}
+
+/// The parts of the DomSamples that determine where static sprites end up on the canvas.
+#[derive(Clone, PartialEq, Debug)]
+struct StaticLayerKey {
+  map_css_scale: f64,
+  map_world_center: Vector2<f64>,
+  canvas_backing_size: Vector2<f64>,
+  device_pixel_ratio: f64,
+}
+
+impl StaticLayerKey {
+  fn new(samples: &DomSamples) -> StaticLayerKey {
+    StaticLayerKey {
+      map_css_scale: samples.map_css_scale,
+      map_world_center: samples.map_world_center,
+      canvas_backing_size: samples.canvas_backing_size,
+      device_pixel_ratio: samples.device_pixel_ratio,
+    }
+  }
 }
 
 fn canvas_position(samples: &DomSamples, position: Vector) -> Vector2<f32> {
@@ -254,7 +398,10 @@ fn inside_machine(
 
 #[wasm_bindgen]
 pub fn rust_init() {
-  std::panic::set_hook(Box::new(console_error_panic_hook::hook));
+  std::panic::set_hook(Box::new(|info| {
+    PANICKED.store(true, Ordering::SeqCst);
+    console_error_panic_hook::hook(info);
+  }));
   live_prop_test::initialize();
 
   // let json_callback = {
@@ -286,7 +433,129 @@ pub fn rust_init() {
     {
       js::init_machine_type(name);
     }
+    module_path_changed(state);
+  });
+
+  // this may call back into Rust, so it must happen outside `with_state`
+  if with_state(|state| state.autosaver.awaiting_restore_decision()) {
+    js::offer_restore();
+  }
+}
+
+#[wasm_bindgen]
+pub fn rust_restore_session() {
+  with_state(|state| {
+    if let Some(snapshot) = state.autosaver.take_saved_session() {
+      restore_session(state, snapshot);
+    }
+  })
+}
+
+#[wasm_bindgen]
+pub fn rust_discard_saved_session() {
+  with_state(|state| {
+    if let Err(error) = state.autosaver.discard_saved_session() {
+      js::console_warn(format!("Failed to discard saved session: {:?}", error));
+    }
+  })
+}
+
+fn restore_session(state: &mut State, snapshot: SessionSnapshot<SavedUiData>) {
+  let num_presets_shown = state.game.machine_types.presets.len();
+  state.game = snapshot.game;
+  // the saved game may have unlocked more presets than a new game starts with
+  for machine_type in state
+    .game
+    .machine_types
+    .presets
+    .iter()
+    .skip(num_presets_shown)
+  {
+    js::init_machine_type(machine_type.as_ref().name().to_owned());
+  }
+  state.selected = WorldMachinesMap::default();
+  state.future = state.game.future();
+  state.start_ui_time = now();
+  state.start_game_time = snapshot.current_game_time;
+  state.current_game_time = snapshot.current_game_time;
+  state.mouse = Default::default();
+  state.static_sprites = Default::default();
+  state.static_layer_key = None;
+  state.keymap = snapshot.ui.keymap;
+  state.module_path = snapshot.ui.module_path;
+  if state.entered_modules().is_none() {
+    state.module_path.clear();
+  }
+  module_path_changed(state);
+}
+
+fn autosave(state: &mut State) {
+  if PANICKED.load(Ordering::SeqCst) {
+    state.autosaver.stop();
+    return;
+  }
+  let State {
+    autosaver,
+    game,
+    current_game_time,
+    keymap,
+    module_path,
+    ..
+  } = state;
+  let result = autosaver.maybe_save(now(), || SessionSnapshot {
+    game: game.clone(),
+    current_game_time: *current_game_time,
+    ui: SavedUiData {
+      keymap: keymap.clone(),
+      module_path: module_path.clone(),
+    },
   });
+  if let Err(error) = result {
+    js::console_warn(format!("Autosave failed: {:?}", error));
+  }
+}
+
+#[wasm_bindgen]
+#[derive(Clone, Deserialize)]
+pub struct KeyPress {
+  key: String,
+  ctrl: bool,
+  shift: bool,
+  alt: bool,
+}
+
+#[wasm_bindgen]
+impl KeyPress {
+  #[wasm_bindgen(constructor)]
+  pub fn from_js_value(v: JsValue) -> Self {
+    v.into_serde().unwrap()
+  }
+}
+
+/// Returns whether the key was bound to anything, so the JS side knows whether to prevent the default action.
+#[wasm_bindgen]
+pub fn rust_keydown(key_press: KeyPress) -> bool {
+  with_state(|state| {
+    let chord = KeyChord::new(
+      &key_press.key,
+      key_press.ctrl,
+      key_press.shift,
+      key_press.alt,
+    );
+    match state.keymap.command_for(&chord) {
+      Some(command) => {
+        execute_command(state, command);
+        true
+      }
+      None => false,
+    }
+  })
+}
+
+/// Called when the player clicks a breadcrumb; `depth` is the number of modules that stay entered.
+#[wasm_bindgen]
+pub fn rust_exit_module_to_depth(depth: usize) {
+  with_state(|state| exit_module(state, depth))
 }
 
 #[wasm_bindgen]
@@ -346,69 +615,344 @@ fn with_smallest_region_containing<F: FnOnce(WorldRegionView<StateViewAspects>)
   recurse(state.view().global_region(), (position, radius), callback)
 }
 
-fn build_machine(state: &mut State, machine_type_id: MachineTypeId, position: GridIsomorphism) {
-  let machine_type = state.game.machine_types.get(machine_type_id);
+/// Tell the player why they can't build, or clear the previous explanation if they can.
+/// Returns whether the placement is fine.
+fn show_placement_problems(problems: &[PlacementProblem]) -> bool {
+  let descriptions: Vec<String> = problems.iter().map(ToString::to_string).collect();
+  js::show_placement_problems(JsValue::from_serde(&descriptions).unwrap());
+  problems.is_empty()
+}
 
-  let inventory = state.view().inventory_at(state.current_game_time);
-  for (amount, material) in machine_type.cost() {
-    if inventory
-      .get(&material)
-      .map_or(true, |storage| storage < amount)
-    {
-      // can't build – you can't afford it
+fn report_insufficient_materials(result: Result<(), InsufficientMaterials>) {
+  if let Err(error) = result {
+    let descriptions: Vec<String> = error
+      .shortfall
+      .iter()
+      .map(|(amount, material)| format!("needs {} more {:?}", amount, material))
+      .collect();
+    js::show_placement_problems(JsValue::from_serde(&descriptions).unwrap());
+  }
+}
+
+fn report_add_remove_machines_error(result: Result<(), AddRemoveMachinesError>) {
+  match result {
+    Ok(()) => {}
+    Err(AddRemoveMachinesError::InsufficientMaterials(error)) => {
+      report_insufficient_materials(Err(error))
+    }
+    Err(error) => {
+      js::show_placement_problems(JsValue::from_serde(&[error.to_string()]).unwrap());
+    }
+  }
+}
+
+fn report_edit_module_error(result: Result<(), EditModuleError>) {
+  match result {
+    Ok(()) => {}
+    Err(EditModuleError::InsufficientMaterials(error)) => report_insufficient_materials(Err(error)),
+    Err(error) => {
+      js::show_placement_problems(JsValue::from_serde(&[error.to_string()]).unwrap());
+    }
+  }
+}
+
+fn build_machine(state: &mut State, machine_type_id: MachineTypeId, position: GridIsomorphism) {
+  if let Some(modules) = state.entered_modules() {
+    if let Some(entered) = modules.last() {
+      build_machine_in_module(state, entered.type_id, machine_type_id, position);
       return;
     }
   }
 
-  let obstructed = with_smallest_region_containing(
-    state,
-    (position.translation, machine_type.radius()),
-    |region| {
-      region.machines().any(|machine| {
-        let radius = machine.machine_type().radius() + machine_type.radius();
-        let offset = (position / machine.isomorphism()).translation;
-        offset[0].abs() < radius && offset[1].abs() < radius
-      })
+  let problems = state.game.placement_problems(
+    &state.future,
+    state.current_game_time,
+    machine_type_id,
+    position,
+  );
+  if !show_placement_problems(&problems) {
+    return;
+  }
+
+  let result = state.game.add_remove_machines(
+    AddRemoveMachines {
+      added: vec![PlatonicMachine {
+        type_id: machine_type_id,
+        state: MachineState {
+          position,
+          parameters: Default::default(),
+        },
+      }],
+      removed: vec![],
     },
+    &mut state.selected,
+    &state.future,
+    state.current_game_time,
   );
+  report_add_remove_machines_error(result);
 
-  if obstructed {
-    // can't build – something is in the way
-    return;
+  recalculate_future(state);
+}
+
+fn execute_command(state: &mut State, command: UiCommand) {
+  match command {
+    UiCommand::RotateClockwise => turn_hovered_machine(state, selection_rotation(true)),
+    UiCommand::RotateCounterclockwise => turn_hovered_machine(state, selection_rotation(false)),
+    UiCommand::Flip => turn_hovered_machine(state, selection_flip()),
+    UiCommand::Delete => replace_hovered_machine(state, |_| None),
+    UiCommand::Undo => {
+      cancel_drag(state);
+      let result = state
+        .game
+        .undo(&mut state.selected, &state.future, state.current_game_time);
+      report_insufficient_materials(result);
+      recalculate_future(state);
+    }
+    UiCommand::Redo => {
+      cancel_drag(state);
+      let result = state
+        .game
+        .redo(&mut state.selected, &state.future, state.current_game_time);
+      report_insufficient_materials(result);
+      recalculate_future(state);
+    }
+    UiCommand::SelectPreset(index) => {
+      if let Some(machine_type) = state.game.machine_types.presets.get(index) {
+        js::select_machine_type(machine_type.as_ref().name().to_owned());
+      }
+    }
+    UiCommand::Cancel => cancel_drag(state),
+    UiCommand::EnterModule => enter_hovered_module(state),
+    UiCommand::ExitModule => {
+      if let Some(depth) = state.module_path.len().checked_sub(1) {
+        exit_module(state, depth);
+      }
+    }
   }
+}
 
-  //let machine_type = state.game.machine_types.get(machine_type_id);
-  /*for (amount, material) in machine_type.cost() {
-    *state
-      .game
-      .inventory_before_last_change
-      .get_mut(&material)
-      .unwrap() -= amount;
-  }*/
+/// Abandon any drag in progress, so that releasing the mouse doesn't build anything.
+fn cancel_drag(state: &mut State) {
+  if state.mouse.drag.take().is_some() {
+    state.game.undo_history.end_gesture();
+  }
+}
 
-  state.game.add_remove_machines(
-    AddRemoveMachines {
+/// Rotate or flip the machine under the cursor in place, using the same turns as `UiState::click_rotate_selection` and `UiState::click_flip_selection`.
+fn turn_hovered_machine(state: &mut State, turn: GridIsomorphism) {
+  replace_hovered_machine(state, |machine| {
+    let mut machine = machine.clone();
+    machine.state.position =
+      machine.state.position * turn_about(machine.state.position.translation, turn);
+    Some(machine)
+  });
+}
+
+/// Replace the machine under the cursor with whatever `replacement` makes of it (which is positioned in the same frame), or delete it if `replacement` returns None.
+fn replace_hovered_machine(
+  state: &mut State,
+  replacement: impl FnOnce(&PlatonicMachine) -> Option<PlatonicMachine>,
+) {
+  let position = match state.mouse.position {
+    Some(position) => position.tile_center,
+    None => return,
+  };
+  if let Some(modules) = state.entered_modules() {
+    if let Some(entered) = modules.last() {
+      let machine_types = &state.game.machine_types;
+      let hovered = machine_types
+        .get_module(entered.type_id)
+        .region
+        .machines
+        .iter()
+        .find(|machine| inside_machine(machine_types, position, machine))
+        .cloned();
+      if let Some(machine) = hovered {
+        let result = state.game.edit_module(
+          EditModule {
+            module: entered.type_id,
+            added: replacement(&machine).into_iter().collect(),
+            removed: vec![machine],
+            restore_preset: None,
+          },
+          &mut state.selected,
+          &state.future,
+          state.current_game_time,
+        );
+        report_edit_module_error(result);
+        recalculate_future(state);
+      }
+      return;
+    }
+  }
+  let hovered = with_smallest_region_containing(state, (position, 1), |region| {
+    region
+      .machines()
+      .find(|machine| {
+        let radius = machine.machine_type().radius();
+        let offset = machine.isomorphism().translation - position;
+        offset[0].abs() < radius && offset[1].abs() < radius
+      })
+      .map(|machine| {
+        GlobalMachine(PlatonicMachine {
+          type_id: machine.platonic().type_id,
+          state: MachineState {
+            position: machine.isomorphism(),
+            parameters: machine.platonic().state.parameters.clone(),
+          },
+        })
+      })
+  });
+
+  if let Some(machine) = hovered {
+    let result = state.game.add_remove_machines(
+      AddRemoveMachines {
+        added: replacement(&machine)
+          .map(GlobalMachine)
+          .into_iter()
+          .collect(),
+        removed: vec![machine],
+      },
+      &mut state.selected,
+      &state.future,
+      state.current_game_time,
+    );
+    report_add_remove_machines_error(result);
+    recalculate_future(state);
+  }
+}
+
+/// Like `build_machine`, but for when we've entered a module; `position` is in the module's frame.
+fn build_machine_in_module(
+  state: &mut State,
+  module_type_id: MachineTypeId,
+  machine_type_id: MachineTypeId,
+  position: GridIsomorphism,
+) {
+  let problems = state.game.placement_problems_in_module(
+    &state.future,
+    state.current_game_time,
+    module_type_id,
+    machine_type_id,
+    position,
+  );
+  if !show_placement_problems(&problems) {
+    return;
+  }
+
+  let result = state.game.edit_module(
+    EditModule {
+      module: module_type_id,
       added: vec![PlatonicMachine {
         type_id: machine_type_id,
-        state: MachineState { position },
+        state: MachineState {
+          position,
+          parameters: Default::default(),
+        },
       }],
       removed: vec![],
+      restore_preset: None,
     },
     &mut state.selected,
     &state.future,
     state.current_game_time,
   );
+  report_edit_module_error(result);
 
   recalculate_future(state);
 }
 
+fn enter_hovered_module(state: &mut State) {
+  let position = match state.mouse.position {
+    Some(position) => position.tile_center,
+    None => return,
+  };
+  let frame = state.current_frame();
+  let machine_types = &state.game.machine_types;
+  let hovered = state.current_region().machines.iter().find(|machine| {
+    matches!(
+      machine_types.get(machine.type_id),
+      MachineTypeRef::Module(_)
+    ) && inside_machine(machine_types, position, machine)
+  });
+  if let Some(machine) = hovered {
+    let isomorphism = machine.state.position * frame;
+    state.module_path.push(isomorphism);
+    // the module's own frame puts its center at the origin
+    js::set_map_center(0.0, 0.0);
+    module_path_changed(state);
+  }
+}
+
+fn exit_module(state: &mut State, depth: usize) {
+  if depth < state.module_path.len() {
+    // keep looking at the same place, but in the outer frame
+    let exited = state.module_path[depth];
+    state.module_path.truncate(depth);
+    let center = exited
+      .translation
+      .transformed_by(state.current_frame().inverse());
+    js::set_map_center(center[0] as f64, center[1] as f64);
+    module_path_changed(state);
+  }
+}
+
+fn module_path_changed(state: &mut State) {
+  cancel_drag(state);
+  state.static_layer_key = None;
+  let names: Vec<String> = std::iter::once("World".to_owned())
+    .chain(
+      state
+        .entered_modules()
+        .into_iter()
+        .flatten()
+        .map(|module| module.name),
+    )
+    .collect();
+  js::update_breadcrumbs(JsValue::from_serde(&names).unwrap());
+}
+
+fn update_progression(state: &mut State) {
+  let newly_reached = state
+    .game
+    .update_progression(&state.future, state.current_game_time);
+  for goal in newly_reached {
+    for machine_type in &state.game.progression.goals[goal].unlocks {
+      js::init_machine_type(machine_type.as_ref().name().to_owned());
+    }
+  }
+  let goals: Vec<_> = state
+    .game
+    .goal_progress(&state.future, state.current_game_time)
+    .into_iter()
+    .map(|progress| {
+      (
+        state.game.progression.goals[progress.goal].name.clone(),
+        progress.fraction(),
+      )
+    })
+    .collect();
+  js::update_goals(JsValue::from_serde(&goals).unwrap());
+}
+
 fn recalculate_future(state: &mut State) {
+  state.game.progression.delivered_before_last_change = state
+    .game
+    .delivered_at(&state.future, state.current_game_time);
   state.game.inventory_before_last_change = state.view().inventory_at(state.current_game_time);
   state.game.last_change_time = state.current_game_time;
 
   state.game.canonicalize();
 
   state.future = state.game.future();
+  state.static_sprites = Default::default();
+  state.static_layer_key = None;
+
+  if state.entered_modules().is_none() {
+    // whatever we were editing is gone, e.g. because of an undo
+    state.module_path.clear();
+    module_path_changed(state);
+  }
 
   /*js!{
     $("#json").val (@{serde_json::to_string_pretty (&state.game).unwrap()});
@@ -527,6 +1071,7 @@ fn mouse_move(state: &mut State, samples: &DomSamples, position: MouseGridPositi
 }
 
 fn mouse_down(state: &mut State, samples: &DomSamples, click_type: ClickType) {
+  state.game.undo_history.begin_gesture();
   state.mouse.drag = Some(DragState {
     original_position: state.mouse.position.unwrap(),
     click_type,
@@ -626,92 +1171,240 @@ fn mouse_up(state: &mut State, samples: &DomSamples) {
     }
   }
   state.mouse.drag = None;
+  state.game.undo_history.end_gesture();
 }
 
-fn draw_region(
-  samples: &DomSamples,
-  region: WorldRegionView<StateViewAspects>,
-  absolute_time: Number,
-) {
-  for machine in region.machines() {
-    let radius = machine.machine_type().radius();
-    let size = Vector2::new(
-      tile_canvas_size(samples)[0] * (radius * 2) as f32 / 2.0,
-      tile_canvas_size(samples)[1] * (radius * 2) as f32 / 2.0,
-    );
-    draw_rectangle(
-      canvas_position(samples, machine.isomorphism().translation),
-      size,
-      "rounded-rectangle-transparent",
-      Rotation::default(),
-    );
-    draw_rectangle(
-      canvas_position(samples, machine.isomorphism().translation),
-      size,
-      machine.machine_type().icon(),
-      machine.isomorphism().rotation,
-    );
+/// A sprite that doesn't change over time, positioned relative to the region containing it.
+struct StaticSprite {
+  position: GridIsomorphism,
+  /// the width of the sprite in tiles, which is also half its width in world units
+  size: f32,
+  sprite: String,
+  rotates: bool,
+}
+
+struct StaticChildModule {
+  position: GridIsomorphism,
+  type_id: MachineTypeId,
+  radius: Number,
+}
+
+/// Everything that needs to be drawn for a region, except for the materials moving through it.
+#[derive(Default)]
+struct RegionStaticSprites {
+  sprites: Vec<StaticSprite>,
+  modules: Vec<StaticChildModule>,
+}
+
+impl RegionStaticSprites {
+  fn new(machine_types: &MachineTypes, region: &PlatonicRegionContents) -> RegionStaticSprites {
+    let mut result = RegionStaticSprites::default();
+    for machine in &region.machines {
+      let machine_type = machine_types.get(machine.type_id);
+      let radius = machine_type.radius();
+      let position = machine.state.position;
+      result.sprites.push(StaticSprite {
+        position,
+        size: radius as f32,
+        sprite: "rounded-rectangle-transparent".to_owned(),
+        rotates: false,
+      });
+      result.sprites.push(StaticSprite {
+        position,
+        size: radius as f32,
+        sprite: machine_type.icon().to_owned(),
+        rotates: true,
+      });
+      if let MachineTypeRef::Module(_) = machine_type {
+        result.modules.push(StaticChildModule {
+          position,
+          type_id: machine.type_id,
+          radius,
+        });
+      }
+    }
+    for machine in &region.machines {
+      let machine_type = machine_types.get(machine.type_id);
+      if machine_type.radius() > 1 {
+        for (input_location, expected_material) in machine_type
+          .input_locations(machine.state.position)
+          .zip(machine_type.input_materials())
+        {
+          let position = GridIsomorphism {
+            translation: input_location.position + input_location.facing.unit_vector(),
+            rotation: input_location.facing - Facing::default(),
+            flip: false,
+          };
+          result.sprites.push(StaticSprite {
+            position,
+            size: 1.0,
+            sprite: "input".to_owned(),
+            rotates: true,
+          });
+          if let Some(material) = expected_material {
+            result.sprites.push(StaticSprite {
+              position,
+              size: 0.8,
+              sprite: material.icon().to_owned(),
+              rotates: false,
+            });
+          }
+        }
+      }
+    }
+    for machine in &region.machines {
+      let machine_type = machine_types.get(machine.type_id);
+      if machine_type.radius() > 1 {
+        for output_location in machine_type.output_locations(machine.state.position) {
+          result.sprites.push(StaticSprite {
+            position: GridIsomorphism {
+              translation: output_location.position - output_location.facing.unit_vector(),
+              rotation: output_location.facing.rotate_90(2) - Facing::default(),
+              flip: false,
+            },
+            size: 1.0,
+            sprite: "input".to_owned(),
+            rotates: true,
+          });
+        }
+      }
+    }
+    result
   }
-  for machine in region.machines() {
-    if machine.machine_type().radius() > 1 {
-      for (input_location, expected_material) in machine
-        .input_locations()
-        .into_iter()
-        .zip(machine.machine_type().input_materials())
-      {
-        let pos = canvas_position(
-          samples,
-          input_location.position + input_location.facing.unit_vector(),
-        );
-        draw_rectangle(
-          pos,
-          tile_canvas_size(samples),
-          "input",
-          input_location.facing - Facing::default(),
-        );
-        if let Some(material) = expected_material {
-          draw_rectangle(
-            pos,
-            tile_canvas_size(samples) * 0.8,
-            material.icon(),
-            Rotation::default(),
-          );
+}
+
+/// Static sprites for the global region, and for the inner region of each module type.
+///
+/// Every instance of a module type shares the same entry, so a deeply nested factory only
+/// costs as much to prepare as its distinct module types. The whole cache is discarded
+/// whenever the game changes.
+#[derive(Default)]
+struct StaticSpritesCache {
+  global_region: Option<RegionStaticSprites>,
+  module_regions: HashMap<MachineTypeId, RegionStaticSprites>,
+}
+
+impl StaticSpritesCache {
+  fn prepare(&mut self, game: &Game) {
+    if self.global_region.is_none() {
+      let sprites = RegionStaticSprites::new(&game.machine_types, &game.global_region);
+      self.prepare_children(&game.machine_types, &sprites);
+      self.global_region = Some(sprites);
+    }
+  }
+  fn prepare_children(&mut self, machine_types: &MachineTypes, sprites: &RegionStaticSprites) {
+    for child in &sprites.modules {
+      if !self.module_regions.contains_key(&child.type_id) {
+        if let MachineTypeRef::Module(module) = machine_types.get(child.type_id) {
+          let child_sprites = RegionStaticSprites::new(machine_types, &module.region);
+          self.prepare_children(machine_types, &child_sprites);
+          self.module_regions.insert(child.type_id, child_sprites);
         }
       }
     }
   }
-  for machine in region.machines() {
-    if machine.machine_type().radius() > 1 {
-      for output_location in machine.output_locations() {
-        draw_rectangle(
-          canvas_position(
-            samples,
-            output_location.position - output_location.facing.unit_vector(),
-          ),
-          tile_canvas_size(samples),
-          "input",
-          output_location.facing.rotate_90(2) - Facing::default(),
-        );
+}
+
+fn draw_static_region(
+  samples: &DomSamples,
+  viewport: &Viewport,
+  cache: &StaticSpritesCache,
+  sprites: &RegionStaticSprites,
+  isomorphism: GridIsomorphism,
+) {
+  for sprite in &sprites.sprites {
+    let position = sprite.position * isomorphism;
+    if viewport.overlaps(position.translation.to_f64(), sprite.size as f64) {
+      draw_rectangle(
+        canvas_position(samples, position.translation),
+        tile_canvas_size(samples) * sprite.size,
+        &sprite.sprite,
+        if sprite.rotates {
+          position.rotation
+        } else {
+          Rotation::default()
+        },
+      );
+    }
+  }
+  for module in &sprites.modules {
+    let position = module.position * isomorphism;
+    if viewport.overlaps(position.translation.to_f64(), module.radius as f64) {
+      if let Some(inner_sprites) = cache.module_regions.get(&module.type_id) {
+        draw_static_region(samples, viewport, cache, inner_sprites, position);
       }
     }
   }
+}
 
+/// `to_frame` maps world positions to the frame the map is currently showing.
+fn draw_materials(
+  samples: &DomSamples,
+  viewport: &Viewport,
+  region: WorldRegionView<StateViewAspects>,
+  absolute_time: Number,
+  to_frame: GridIsomorphism,
+) {
   for machine in region.machines() {
+    // materials can stick out a little past the edge of the machine that's carrying them
+    let half_extent = (machine.machine_type().radius() + 1) as f64;
+    let center = machine.isomorphism().translation.transformed_by(to_frame);
+    if !viewport.overlaps(center.to_f64(), half_extent) {
+      continue;
+    }
     if let Some(visuals) = machine.momentary_visuals(absolute_time) {
       for (position, material) in visuals.materials {
         draw_rectangle(
-          canvas_position_from_f64(samples, position),
+          canvas_position_from_f64(samples, position.transformed_by(to_frame)),
           tile_canvas_size(samples) * 0.6,
           material.icon(),
           Rotation::default(),
         );
       }
     }
+    if let Some(module) = machine.as_module() {
+      draw_materials(
+        samples,
+        viewport,
+        module.inner_region(),
+        absolute_time,
+        to_frame,
+      );
+    }
   }
+}
 
-  for machine in region.machines() {
-    if let Some(module) = machine.as_module() {
-      draw_region(samples, module.inner_region(), absolute_time);
+/// Draw the materials inside the module instance at the end of `path`, in that instance's frame.
+fn draw_entered_module_materials(
+  samples: &DomSamples,
+  viewport: &Viewport,
+  region: WorldRegionView<StateViewAspects>,
+  path: &[GridIsomorphism],
+  absolute_time: Number,
+) {
+  if let Some((&first, rest)) = path.split_first() {
+    for machine in region.machines() {
+      if machine.isomorphism() == first {
+        if let Some(module) = machine.as_module() {
+          if rest.is_empty() {
+            draw_materials(
+              samples,
+              viewport,
+              module.inner_region(),
+              absolute_time,
+              first.inverse(),
+            );
+          } else {
+            draw_entered_module_materials(
+              samples,
+              viewport,
+              module.inner_region(),
+              rest,
+              absolute_time,
+            );
+          }
+        }
+      }
     }
   }
 }
@@ -743,17 +1436,59 @@ pub fn do_frame() {
       + (now() - state.start_ui_time) * TIME_TO_MOVE_MATERIAL as f64 * 2.0;
     state.current_game_time = fractional_time as Number;
 
+    update_progression(state);
+
+    let viewport = Viewport::new(&samples);
+    let static_layer_key = StaticLayerKey::new(&samples);
+    if state.static_layer_key.as_ref() != Some(&static_layer_key) {
+      state.static_sprites.prepare(&state.game);
+      js::begin_static_layer();
+      let sprites = match state
+        .entered_modules()
+        .and_then(|modules| modules.last().map(|module| module.type_id))
+      {
+        Some(type_id) => state.static_sprites.module_regions.get(&type_id),
+        None => state.static_sprites.global_region.as_ref(),
+      };
+      if let Some(sprites) = sprites {
+        draw_static_region(
+          &samples,
+          &viewport,
+          &state.static_sprites,
+          sprites,
+          GridIsomorphism::default(),
+        );
+      }
+      js::end_static_layer();
+      state.static_layer_key = Some(static_layer_key);
+    }
+
     js::clear_canvas();
+    js::draw_static_layer();
 
     //target.clear_color(1.0, 1.0, 1.0, 1.0);
-    draw_region(
-      &samples,
-      state.view().global_region(),
-      state.current_game_time,
-    );
+    if state.module_path.is_empty() {
+      draw_materials(
+        &samples,
+        &viewport,
+        state.view().global_region(),
+        state.current_game_time,
+        GridIsomorphism::default(),
+      );
+    } else {
+      draw_entered_module_materials(
+        &samples,
+        &viewport,
+        state.view().global_region(),
+        &state.module_path,
+        state.current_game_time,
+      );
+    }
 
     js::update_inventory(
       JsValue::from_serde(&state.view().inventory_at(state.current_game_time)).unwrap(),
     );
+
+    autosave(state);
   })
 }
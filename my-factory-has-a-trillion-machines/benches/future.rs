@@ -0,0 +1,228 @@
+/*!
+
+Benchmarks for the parts of the game that get recomputed after every change: `Game::future()`, `PlatonicRegionContents::output_edges`, and `Game::canonicalize`.
+
+Each scenario is built procedurally at a few sizes, so it's easy to see how the cost grows. A single region can hold at most `MAX_COMPONENTS` machines, so the flat scenarios (conveyor chains and splitter trees) top out there; the nested-module scenario is where the world gets really big, because each module level multiplies the number of instances.
+
+Run with `cargo bench --bench future`.
+
+*/
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use my_factory_has_a_trillion_machines::geometry::{
+  GridIsomorphism, Number, Vector, VectorExtension,
+};
+use my_factory_has_a_trillion_machines::machine_data::{
+  Game, InputLocation, MachineTypeId, PlatonicMachine, PlatonicRegionContents, StandardMachineInfo,
+  MAX_COMPONENTS,
+};
+use my_factory_has_a_trillion_machines::modules::{ModuleInput, ModuleType, PlatonicModule};
+use my_factory_has_a_trillion_machines::primitive_machines::{conveyor, iron_mine, splitter};
+
+const CONVEYOR: MachineTypeId = MachineTypeId::Preset(0);
+const IRON_MINE: MachineTypeId = MachineTypeId::Preset(1);
+const SPLITTER: MachineTypeId = MachineTypeId::Preset(2);
+
+fn at(x: Number, y: Number) -> GridIsomorphism {
+  GridIsomorphism {
+    translation: Vector::new(x, y),
+    ..Default::default()
+  }
+}
+
+/// A conveyor at (x, y) that carries materials in direction `direction`, instead of +x.
+fn turned(x: Number, y: Number, direction: Vector) -> GridIsomorphism {
+  at(x, y).with_rotation_changed_to_make_facing_transform_to(
+    Vector::new(1, 0).exact_facing().unwrap(),
+    direction.exact_facing().unwrap(),
+  )
+}
+
+fn game(machines: Vec<PlatonicMachine>, custom_modules: Vec<PlatonicModule>) -> Game {
+  let mut game = Game::new(vec![conveyor(), iron_mine(), splitter()], machines);
+  game.machine_types.custom_modules = custom_modules;
+  game.canonicalize();
+  game
+}
+
+/// A mine followed by `length` conveyors in a straight line.
+fn conveyor_chain(length: usize) -> Game {
+  let mut machines = vec![PlatonicMachine::new(IRON_MINE, at(0, 0))];
+  machines.extend(
+    (0..length as Number).map(|index| PlatonicMachine::new(CONVEYOR, at(4 + 2 * index, 0))),
+  );
+  game(machines, Vec::new())
+}
+
+/// A mine feeding a complete binary tree of splitters, `depth` splitters deep.
+///
+/// Each splitter's outputs go up and down, so each branch is a vertical run of conveyors, then one more conveyor to turn back toward +x. The runs get shorter at each level so that the subtrees never overlap.
+fn splitter_tree(depth: u32) -> Game {
+  fn subtree(machines: &mut Vec<PlatonicMachine>, x: Number, y: Number, levels_left: u32) {
+    machines.push(PlatonicMachine::new(SPLITTER, at(x, y)));
+    if levels_left == 1 {
+      return;
+    }
+    let offset = 1 << (levels_left - 1);
+    for &direction in &[1, -1] {
+      for step in 1..offset / 2 {
+        machines.push(PlatonicMachine::new(
+          CONVEYOR,
+          turned(x, y + direction * 2 * step, Vector::new(0, direction)),
+        ));
+      }
+      machines.push(PlatonicMachine::new(
+        CONVEYOR,
+        at(x, y + direction * offset),
+      ));
+      subtree(machines, x + 2, y + direction * offset, levels_left - 1);
+    }
+  }
+
+  let mut machines = vec![PlatonicMachine::new(IRON_MINE, at(-4, 0))];
+  subtree(&mut machines, 0, 0, depth);
+  assert!(machines.len() <= MAX_COMPONENTS);
+  game(machines, Vec::new())
+}
+
+/// `depth` levels of custom modules, each containing `instances_per_level` instances of the one below it, with a mine at the bottom.
+///
+/// The first instance at each level is connected to its module's output, so materials flow all the way out to the global region; the rest have their outputs lost inside. The world contains `instances_per_level.pow(depth)` mines in total.
+fn nested_modules(depth: usize, instances_per_level: usize) -> Game {
+  fn module(
+    name: String,
+    radius: Number,
+    inner_radius: Number,
+    machines: Vec<PlatonicMachine>,
+  ) -> PlatonicModule {
+    PlatonicModule {
+      module_type: ModuleType {
+        info: StandardMachineInfo::new(name, "rounded-rectangle-solid", radius, Vec::new()),
+        inner_radius,
+        inputs: Default::default(),
+        outputs: std::iter::once(ModuleInput {
+          outer_location: InputLocation::output(radius, 0),
+          inner_location: InputLocation::output(inner_radius, 0),
+        })
+        .collect(),
+      },
+      cost: Vec::new(),
+      region: PlatonicRegionContents { machines },
+      parameters: Vec::new(),
+    }
+  }
+
+  let mut modules = vec![module(
+    "Nested module 0".to_owned(),
+    5,
+    3,
+    vec![PlatonicMachine::new(IRON_MINE, at(0, 0))],
+  )];
+  for level in 1..depth {
+    let child_radius = modules[level - 1].module_type.info.radius;
+    // stack the children in a column against the right side, alternating above and below the first one, so the first one's output is exactly the module's output
+    let inner_radius = child_radius * (instances_per_level as Number / 2 * 2 + 1);
+    let child_x = inner_radius - child_radius;
+    let machines = (0..instances_per_level as Number)
+      .map(|index| {
+        let y = (if index % 2 == 0 { index } else { -(index + 1) }) * child_radius;
+        PlatonicMachine::new(MachineTypeId::Module(level - 1), at(child_x, y))
+      })
+      .collect();
+    modules.push(module(
+      format!("Nested module {}", level),
+      inner_radius + 2,
+      inner_radius,
+      machines,
+    ));
+  }
+
+  game(
+    vec![PlatonicMachine::new(
+      MachineTypeId::Module(depth - 1),
+      at(0, 0),
+    )],
+    modules,
+  )
+}
+
+fn bench_scenarios(c: &mut Criterion, group_name: &str, scenarios: Vec<(String, Game)>) {
+  let mut group = c.benchmark_group(group_name);
+  for (parameter, game) in &scenarios {
+    group.bench_with_input(BenchmarkId::new("future", parameter), game, |b, game| {
+      b.iter(|| game.future())
+    });
+    group.bench_with_input(
+      BenchmarkId::new("output_edges", parameter),
+      game,
+      |b, game| {
+        b.iter(|| {
+          game
+            .platonic_regions()
+            .map(|region| region.output_edges(&game.machine_types))
+            .collect::<Vec<_>>()
+        })
+      },
+    );
+    group.bench_with_input(
+      BenchmarkId::new("canonicalize", parameter),
+      game,
+      |b, game| {
+        b.iter_batched(
+          || game.clone(),
+          |mut game| {
+            game.canonicalize();
+            game
+          },
+          BatchSize::SmallInput,
+        )
+      },
+    );
+  }
+  group.finish();
+}
+
+fn conveyor_chains(c: &mut Criterion) {
+  let lengths = [16, 64, MAX_COMPONENTS - 1];
+  bench_scenarios(
+    c,
+    "conveyor_chain",
+    lengths
+      .iter()
+      .map(|&length| (length.to_string(), conveyor_chain(length)))
+      .collect(),
+  );
+}
+
+fn splitter_trees(c: &mut Criterion) {
+  bench_scenarios(
+    c,
+    "splitter_tree",
+    (4..=6)
+      .map(|depth| (depth.to_string(), splitter_tree(depth)))
+      .collect(),
+  );
+}
+
+fn nested_module_trees(c: &mut Criterion) {
+  bench_scenarios(
+    c,
+    "nested_modules",
+    [(5, 3), (5, 8), (10, 3), (10, 5)]
+      .iter()
+      .map(|&(depth, instances)| {
+        (
+          format!("depth {}, {} instances", depth, instances),
+          nested_modules(depth, instances),
+        )
+      })
+      .collect(),
+  );
+}
+
+criterion_group!(
+  benches,
+  conveyor_chains,
+  splitter_trees,
+  nested_module_trees
+);
+criterion_main!(benches);
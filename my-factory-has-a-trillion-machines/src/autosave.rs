@@ -0,0 +1,208 @@
+/**
+
+Periodic saving of the player's session, so that it survives reloads and crashes.
+
+Where the snapshots actually go is up to the frontend, through the `SnapshotStorage` trait. The web frontend uses the browser's localStorage; `FileStorage` keeps them in a directory instead, which is mainly useful for testing this off-browser.
+
+Snapshots are only written if the game passes its invariant checks, so the stored snapshot is always the last known-good one. After a panic, the frontend should call `Autosaver::stop`, because whatever state is left over can't be trusted anymore.
+
+*/
+use crate::geometry::Number;
+use crate::machine_data::Game;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fmt::Debug;
+use std::io;
+use std::path::PathBuf;
+
+pub const AUTOSAVE_KEY: &str = "my-factory-has-a-trillion-machines-autosave";
+
+pub trait SnapshotStorage {
+  type Error: Debug;
+  fn load(&self, key: &str) -> Result<Option<String>, Self::Error>;
+  fn store(&mut self, key: &str, value: &str) -> Result<(), Self::Error>;
+  fn remove(&mut self, key: &str) -> Result<(), Self::Error>;
+}
+
+/// Stores each key as a JSON file in a directory.
+#[derive(Clone, Debug)]
+pub struct FileStorage {
+  directory: PathBuf,
+}
+
+impl FileStorage {
+  pub fn new(directory: impl Into<PathBuf>) -> FileStorage {
+    FileStorage {
+      directory: directory.into(),
+    }
+  }
+  fn path(&self, key: &str) -> PathBuf {
+    self.directory.join(format!("{}.json", key))
+  }
+}
+
+impl SnapshotStorage for FileStorage {
+  type Error = io::Error;
+  fn load(&self, key: &str) -> io::Result<Option<String>> {
+    match std::fs::read_to_string(self.path(key)) {
+      Ok(contents) => Ok(Some(contents)),
+      Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+      Err(error) => Err(error),
+    }
+  }
+  fn store(&mut self, key: &str, value: &str) -> io::Result<()> {
+    std::fs::create_dir_all(&self.directory)?;
+    // write the whole thing first, so that a crash partway through can't clobber the previous snapshot
+    let temporary_path = self.path(&format!("{}.partial", key));
+    std::fs::write(&temporary_path, value)?;
+    std::fs::rename(temporary_path, self.path(key))
+  }
+  fn remove(&mut self, key: &str) -> io::Result<()> {
+    match std::fs::remove_file(self.path(key)) {
+      Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+      _ => Ok(()),
+    }
+  }
+}
+
+/// Everything needed to resume a session. `U` is whatever UI data the frontend wants to keep.
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct SessionSnapshot<U> {
+  pub game: Game,
+  pub current_game_time: Number,
+  pub ui: U,
+}
+
+#[derive(Debug)]
+pub enum AutosaveError<E> {
+  Storage(E),
+  Serialization(serde_json::Error),
+}
+
+pub struct Autosaver<S: SnapshotStorage> {
+  storage: S,
+  /// in the same units as the `now` passed to `maybe_save`
+  interval: f64,
+  last_save_time: Option<f64>,
+  awaiting_restore_decision: bool,
+  stopped: bool,
+}
+
+impl<S: SnapshotStorage> Autosaver<S> {
+  /// If `storage` already holds a snapshot, nothing is saved until the player decides whether to
+  /// restore it (`take_saved_session`) or not (`discard_saved_session`).
+  pub fn new(storage: S, interval: f64) -> Autosaver<S> {
+    let awaiting_restore_decision = matches!(storage.load(AUTOSAVE_KEY), Ok(Some(_)));
+    Autosaver {
+      storage,
+      interval,
+      last_save_time: None,
+      awaiting_restore_decision,
+      stopped: false,
+    }
+  }
+
+  pub fn awaiting_restore_decision(&self) -> bool {
+    self.awaiting_restore_decision
+  }
+
+  /// Returns the saved snapshot, if there is one and it can still be read, and resumes autosaving.
+  pub fn take_saved_session<U: DeserializeOwned>(&mut self) -> Option<SessionSnapshot<U>> {
+    self.awaiting_restore_decision = false;
+    let serialized = self.storage.load(AUTOSAVE_KEY).ok()??;
+    serde_json::from_str(&serialized).ok()
+  }
+
+  pub fn discard_saved_session(&mut self) -> Result<(), AutosaveError<S::Error>> {
+    self.awaiting_restore_decision = false;
+    self
+      .storage
+      .remove(AUTOSAVE_KEY)
+      .map_err(AutosaveError::Storage)
+  }
+
+  /// Stop saving for good, leaving the most recent snapshot in place.
+  pub fn stop(&mut self) {
+    self.stopped = true;
+  }
+
+  /// Saves a snapshot if at least `interval` has passed since the last one.
+  ///
+  /// `snapshot` is only called if we're actually going to save, because serializing the game isn't free.
+  /// Returns whether anything was saved.
+  pub fn maybe_save<U: Serialize>(
+    &mut self,
+    now: f64,
+    snapshot: impl FnOnce() -> SessionSnapshot<U>,
+  ) -> Result<bool, AutosaveError<S::Error>> {
+    if self.stopped || self.awaiting_restore_decision {
+      return Ok(false);
+    }
+    if let Some(last_save_time) = self.last_save_time {
+      if now < last_save_time + self.interval {
+        return Ok(false);
+      }
+    }
+    self.last_save_time = Some(now);
+    let snapshot = snapshot();
+    if snapshot.game.check_invariants().is_err() {
+      // keep the previous snapshot, which was fine
+      return Ok(false);
+    }
+    let serialized = serde_json::to_string(&snapshot).map_err(AutosaveError::Serialization)?;
+    self
+      .storage
+      .store(AUTOSAVE_KEY, &serialized)
+      .map_err(AutosaveError::Storage)?;
+    Ok(true)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn snapshot(time: Number) -> SessionSnapshot<String> {
+    SessionSnapshot {
+      game: Game::new(Vec::new(), Vec::new()),
+      current_game_time: time,
+      ui: "ui".to_owned(),
+    }
+  }
+
+  fn temporary_storage(name: &str) -> FileStorage {
+    let directory = std::env::temp_dir().join(format!(
+      "my-factory-autosave-test-{}-{}",
+      name,
+      std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&directory);
+    FileStorage::new(directory)
+  }
+
+  #[test]
+  fn autosave_round_trip_through_files() {
+    let storage = temporary_storage("round-trip");
+    let mut autosaver = Autosaver::new(storage.clone(), 10.0);
+    assert!(!autosaver.awaiting_restore_decision());
+    assert!(autosaver.maybe_save(0.0, || snapshot(5)).unwrap());
+    assert!(!autosaver.maybe_save(3.0, || snapshot(8)).unwrap());
+
+    let mut restarted = Autosaver::new(storage, 10.0);
+    assert!(restarted.awaiting_restore_decision());
+    assert!(!restarted.maybe_save(100.0, || snapshot(9)).unwrap());
+    assert_eq!(restarted.take_saved_session(), Some(snapshot(5)));
+  }
+
+  #[test]
+  fn stopped_autosaver_keeps_last_snapshot() {
+    let storage = temporary_storage("stopped");
+    let mut autosaver = Autosaver::new(storage.clone(), 1.0);
+    assert!(autosaver.maybe_save(0.0, || snapshot(1)).unwrap());
+    autosaver.stop();
+    assert!(!autosaver.maybe_save(5.0, || snapshot(2)).unwrap());
+
+    let mut restarted = Autosaver::new(storage, 1.0);
+    assert_eq!(restarted.take_saved_session(), Some(snapshot(1)));
+  }
+}
@@ -0,0 +1,29 @@
+//! Draws a saved session (an autosave snapshot, like the ones `FileStorage` writes) as an SVG, with the materials where they were when it was saved.
+//!
+//! Usage: `cargo run --bin export_svg -- path/to/save.json path/to/output.svg`
+
+use my_factory_has_a_trillion_machines::autosave::SessionSnapshot;
+use my_factory_has_a_trillion_machines::svg_export::{export_svg, SvgExportOptions};
+use std::fs;
+
+fn main() {
+  let usage = "usage: export_svg path/to/save.json path/to/output.svg";
+  let mut args = std::env::args().skip(1);
+  let save_path = args.next().expect(usage);
+  let output_path = args.next().expect(usage);
+
+  // the UI data depends on the frontend, and isn't needed to draw the factory
+  let snapshot: SessionSnapshot<serde_json::Value> =
+    serde_json::from_str(&fs::read_to_string(&save_path).unwrap()).unwrap();
+  let game = snapshot.game;
+  let future = game.future();
+  let svg = export_svg(
+    &game,
+    &future,
+    &SvgExportOptions {
+      time: Some(snapshot.current_game_time),
+      ..Default::default()
+    },
+  );
+  fs::write(&output_path, svg).unwrap();
+}
@@ -62,9 +62,10 @@ impl Rotation {
   }
 }
 impl Facing {
-  // private method only used by GridIsomorphism to represent the canonical flip
+  // private method only used by GridIsomorphism to represent the canonical flip,
+  // which negates the x coordinate, so +x and -x swap while +y and -y stay put
   fn canonical_flip(self) -> Facing {
-    Facing((4 - self.0) % 4)
+    Facing((6 - self.0) % 4)
   }
   pub fn unit_vector(self) -> Vector {
     Vector::new(1, 0).rotate_90(self.0)
@@ -246,6 +247,10 @@ mod tests {
     fn randomly_test_grid_isomorphism_transforms_translation_like_vector (isomorphism in arbitrary_isomorphism(), transformed_isomorphism in arbitrary_isomorphism()) {
       prop_assert_eq! ((transformed_isomorphism * isomorphism).translation, transformed_isomorphism.translation.transformed_by(isomorphism));
     }
+    #[test]
+    fn randomly_test_grid_isomorphism_transforms_facing_like_unit_vector (isomorphism in arbitrary_isomorphism(), facing in arbitrary_facing()) {
+      prop_assert_eq! (facing.unit_vector().transformed_by(isomorphism) - isomorphism.translation, facing.transformed_by(isomorphism).unit_vector());
+    }
   }
 }
 
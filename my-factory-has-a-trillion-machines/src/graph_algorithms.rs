@@ -11,7 +11,7 @@ use crate::machine_data::{
   MachineTypeRef, MachineTypeTrait, MachineTypes, Material, PlatonicMachine,
   PlatonicRegionContents, WorldMachinesMap, MAX_COMPONENTS,
 };
-use crate::modules::{CanonicalModuleInputs, PlatonicModule};
+use crate::modules::{CanonicalModuleInputs, ModuleParameterValues, PlatonicModule};
 
 pub type OutputEdges = ArrayVec<[Inputs<Option<(usize, usize)>>; MAX_COMPONENTS]>;
 
@@ -33,7 +33,9 @@ pub struct MachineAndInputsFuture {
 }
 
 pub type UndisturbedModuleFutures = HashMap<CanonicalModuleInputs, RegionFuture>;
-pub type UndisturbedModulesFutures = HashMap<MachineTypeId, UndisturbedModuleFutures>;
+/// Keyed by the module type and the instance's parameter values, because instances with different parameters behave differently even with the same inputs.
+pub type UndisturbedModulesFutures =
+  HashMap<(MachineTypeId, ModuleParameterValues), UndisturbedModuleFutures>;
 
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub struct GameFuture {
@@ -296,7 +298,10 @@ impl<'a> GameFutureBuilder<'a> {
           .last_disturbed_time()
           .map_or(0, |t| t - region_start_time),
       };
-      let future = machine.machine_type().future(inputs);
+      let mut future = machine.machine_type().future(inputs);
+      if let Ok(MachineFuture::Module(module_machine_future)) = &mut future {
+        module_machine_future.parameters = machine.platonic().state.parameters.clone();
+      }
 
       let outputs = match (machine.as_module(), &future) {
         (Some(module), Ok(MachineFuture::Module(module_machine_future))) => {
@@ -337,8 +342,12 @@ impl<'a> GameFutureBuilder<'a> {
               .or_insert(inner_future) // should always insert, but doing it this way to get a reference back
           } else {
             // Undisturbed - deduplicate the future
+            let variation_key = (
+              machine.platonic().type_id,
+              module_machine_future.parameters.clone(),
+            );
             let platonic_module_futures = undisturbed_modules_futures
-              .entry(machine.platonic().type_id)
+              .entry(variation_key.clone())
               .or_default();
 
             match platonic_module_futures.get(&module_machine_future.canonical_inputs) {
@@ -352,16 +361,26 @@ impl<'a> GameFutureBuilder<'a> {
                   &fiat_inputs,
                 );
 
-                match undisturbed_modules_futures.get_mut(&machine.platonic().type_id).unwrap().entry(module_machine_future.canonical_inputs.clone()) {
+                match undisturbed_modules_futures.get_mut(&variation_key).unwrap().entry(module_machine_future.canonical_inputs.clone()) {
                   hash_map::Entry::Occupied(_) => unreachable!("A module's future was modified during calculation of its submodules' futures. Did a module get put inside itself somehow?"),
                   hash_map::Entry::Vacant(entry) => entry.insert(inner_future)
                 }
               }
             }
           };
-          module
-            .platonic()
-            .module_output_flows(inputs, module_machine_future, variation)
+          let mut outputs =
+            module
+              .platonic()
+              .module_output_flows(inputs, module_machine_future, variation);
+          for (index, output) in outputs.iter_mut().enumerate() {
+            if !module
+              .platonic()
+              .output_enabled(&module_machine_future.parameters, index)
+            {
+              *output = None;
+            }
+          }
+          outputs
         }
         (_, Ok(future)) => machine.machine_type().output_flows(inputs, future),
         (_, Err(_)) => inputs![],
@@ -929,7 +948,15 @@ pub mod base_view_aspect {
       WorldMachineView {
         game: region.game,
         platonic: machine,
-        machine_type: region.game.machine_types.get(machine.type_id),
+        machine_type: region
+          .game
+          .machine_types
+          .get(match region.containing_module {
+            Some(module) => module
+              .platonic
+              .parameterized_type_id(&module.as_machine.platonic.state.parameters, machine),
+            None => machine.type_id,
+          }),
         isomorphism: machine.state.position * region.isomorphism,
         parent: region,
         index_within_parent: ids.index,
@@ -1023,9 +1050,9 @@ pub mod base_view_aspect {
   }
 
   impl<'a, T: GetSubaspect<BaseAspect>> super::WorldMachineView<'a, T> {
+    /// The type this machine behaves as, which can differ from its platonic type if the containing module instance has parameters.
     pub fn machine_type(&self) -> MachineTypeRef {
-      let aspect = self.get_aspect::<BaseAspect>();
-      aspect.game.machine_types.get(aspect.platonic.type_id)
+      self.get_aspect::<BaseAspect>().machine_type
     }
   }
 
@@ -1094,6 +1121,7 @@ pub mod base_mut_view_aspect {
   impl BaseMutAspect {
     #[allow(clippy::new_ret_no_self)]
     pub fn new<'a>(game: &'a mut Game, change_time: Number, future: &GameFuture) -> GameView<'a> {
+      game.progression.delivered_before_last_change = game.delivered_at(future, change_time);
       let view = super::GameView::<(BaseAspect, FutureAspect)>::new(game, future);
       game.inventory_before_last_change = view.inventory_at(change_time);
       game.last_change_time = change_time;
@@ -1477,6 +1505,18 @@ pub mod base_mut_view_aspect {
     }
   }
 
+  impl<'a, T: WorldViewAspectGetMut + GetSubaspectMut<BaseMutAspect>> super::WorldMachineView<'a, T> {
+    /// Mark this machine, and everything downstream of it, as disturbed at the change time.
+    ///
+    /// Removing and inserting machines takes care of this automatically; this is for when a machine
+    /// changes in place, such as when the contents of its module type are edited.
+    pub fn disturb(&mut self) {
+      let aspect = self.get_aspect_mut::<BaseMutAspect>();
+      let index = aspect.index_within_parent;
+      aspect.parent.disturb_downstream(index, true);
+    }
+  }
+
   impl<'a, T: WorldViewAspectGetMut + GetSubaspect<BaseMutAspect>> super::WorldModuleView<'a, T> {
     pub fn contains_global_machine(&self, queried_machine: &GlobalMachine) -> bool {
       let aspect = self.get_aspect::<BaseMutAspect>();
@@ -1599,7 +1639,7 @@ pub mod future_view_aspect {
                     machine
                         .game
                         .undisturbed_modules
-                        .get(& machine.ids.type_id)
+                        .get(&(machine.ids.type_id, module_machine_future.parameters.clone()))
                         .expect("there shouldn't be a ModuleMachineFuture if there isn't a corresponding ModuleFuture")
                         .get(&module_machine_future.canonical_inputs)
                         .expect("there shouldn't be a ModuleMachineFuture if there isn't a corresponding future-variation")),
@@ -0,0 +1,203 @@
+/**
+
+Keyboard bindings for UI commands.
+
+Like the rest of the UI layer, this is agnostic about what frontend is used. The frontend reports each key press as a `KeyChord`, looks it up in the `Keymap`, and carries out the resulting `UiCommand` however makes sense for it.
+
+Keys are named the same way as the `key` property of a browser KeyboardEvent ("r", "Delete", "Escape", ...), except that single-character keys are lowercased, so that holding Shift doesn't change which key was pressed.
+
+*/
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Debug)]
+pub struct KeyChord {
+  pub key: String,
+  pub ctrl: bool,
+  pub shift: bool,
+  pub alt: bool,
+}
+
+impl KeyChord {
+  pub fn new(key: &str, ctrl: bool, shift: bool, alt: bool) -> KeyChord {
+    let key = if key.chars().count() == 1 {
+      key.to_lowercase()
+    } else {
+      key.to_owned()
+    };
+    KeyChord {
+      key,
+      ctrl,
+      shift,
+      alt,
+    }
+  }
+  pub fn plain(key: &str) -> KeyChord {
+    KeyChord::new(key, false, false, false)
+  }
+  pub fn ctrl(key: &str) -> KeyChord {
+    KeyChord::new(key, true, false, false)
+  }
+  pub fn shift(key: &str) -> KeyChord {
+    KeyChord::new(key, false, true, false)
+  }
+  pub fn alt(key: &str) -> KeyChord {
+    KeyChord::new(key, false, false, true)
+  }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Debug)]
+pub enum UiCommand {
+  RotateClockwise,
+  RotateCounterclockwise,
+  Flip,
+  Delete,
+  Undo,
+  Redo,
+  /// Switch to building the preset with this index in `MachineTypes::presets`.
+  SelectPreset(usize),
+  /// Give up on whatever is currently hovering, like a click on empty map would.
+  Cancel,
+  /// Start editing the inner region of the module under the cursor.
+  EnterModule,
+  /// Go back to editing the region containing the current module.
+  ExitModule,
+}
+
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Debug)]
+pub struct KeyBinding {
+  pub chord: KeyChord,
+  pub command: UiCommand,
+}
+
+/// A list of key bindings. At most one binding exists for each chord.
+///
+/// Stored as a list rather than a map so that it can be serialized as JSON.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Debug)]
+pub struct Keymap {
+  bindings: Vec<KeyBinding>,
+}
+
+impl Default for Keymap {
+  fn default() -> Self {
+    let mut result = Keymap::empty();
+    result.bind(KeyChord::plain("r"), UiCommand::RotateClockwise);
+    result.bind(KeyChord::shift("r"), UiCommand::RotateCounterclockwise);
+    result.bind(KeyChord::plain("f"), UiCommand::Flip);
+    result.bind(KeyChord::plain("Delete"), UiCommand::Delete);
+    // laptop keyboards often have no Delete key
+    result.bind(KeyChord::plain("Backspace"), UiCommand::Delete);
+    result.bind(KeyChord::ctrl("z"), UiCommand::Undo);
+    result.bind(KeyChord::ctrl("y"), UiCommand::Redo);
+    result.bind(KeyChord::new("z", true, true, false), UiCommand::Redo);
+    result.bind(KeyChord::plain("Escape"), UiCommand::Cancel);
+    result.bind(KeyChord::plain("Enter"), UiCommand::EnterModule);
+    // like going up a folder in a file browser
+    result.bind(KeyChord::alt("ArrowUp"), UiCommand::ExitModule);
+    for index in 0..9 {
+      result.bind(
+        KeyChord::plain(&(index + 1).to_string()),
+        UiCommand::SelectPreset(index),
+      );
+    }
+    result
+  }
+}
+
+impl Keymap {
+  pub fn empty() -> Keymap {
+    Keymap {
+      bindings: Vec::new(),
+    }
+  }
+
+  pub fn bindings(&self) -> &[KeyBinding] {
+    &self.bindings
+  }
+
+  pub fn command_for(&self, chord: &KeyChord) -> Option<UiCommand> {
+    self
+      .bindings
+      .iter()
+      .find(|binding| &binding.chord == chord)
+      .map(|binding| binding.command)
+  }
+
+  /// Bind `chord` to `command`, replacing any existing binding for that chord.
+  pub fn bind(&mut self, chord: KeyChord, command: UiCommand) {
+    self.unbind(&chord);
+    self.bindings.push(KeyBinding { chord, command });
+  }
+
+  pub fn unbind(&mut self, chord: &KeyChord) {
+    self.bindings.retain(|binding| &binding.chord != chord);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn default_keymap_ignores_case_of_letter_keys() {
+    let keymap = Keymap::default();
+    assert_eq!(
+      keymap.command_for(&KeyChord::new("R", false, true, false)),
+      Some(UiCommand::RotateCounterclockwise)
+    );
+    assert_eq!(
+      keymap.command_for(&KeyChord::new("Z", true, false, false)),
+      Some(UiCommand::Undo)
+    );
+    assert_eq!(
+      keymap.command_for(&KeyChord::plain("3")),
+      Some(UiCommand::SelectPreset(2))
+    );
+  }
+
+  #[test]
+  fn default_keymap_has_a_binding_for_every_command() {
+    let keymap = Keymap::default();
+    for command in &[
+      UiCommand::RotateClockwise,
+      UiCommand::RotateCounterclockwise,
+      UiCommand::Flip,
+      UiCommand::Delete,
+      UiCommand::Undo,
+      UiCommand::Redo,
+      UiCommand::Cancel,
+      UiCommand::EnterModule,
+      UiCommand::ExitModule,
+    ] {
+      assert!(
+        keymap
+          .bindings()
+          .iter()
+          .any(|binding| binding.command == *command),
+        "{:?} has no binding",
+        command
+      );
+    }
+    assert_eq!(
+      keymap.command_for(&KeyChord::plain("Backspace")),
+      Some(UiCommand::Delete)
+    );
+  }
+
+  #[test]
+  fn rebinding_replaces_previous_binding() {
+    let mut keymap = Keymap::default();
+    keymap.bind(KeyChord::plain("r"), UiCommand::Flip);
+    assert_eq!(
+      keymap.command_for(&KeyChord::plain("r")),
+      Some(UiCommand::Flip)
+    );
+    assert_eq!(
+      keymap
+        .bindings()
+        .iter()
+        .filter(|binding| binding.chord == KeyChord::plain("r"))
+        .count(),
+      1
+    );
+  }
+}
@@ -10,11 +10,19 @@ pub mod machine_data;
 #[macro_use]
 pub mod graph_algorithms;
 // hack-ish: modules marked pub to suppress dead code warnings from builds with different conditional compilation
+pub mod autosave;
 pub mod flow_pattern;
 pub mod geometry;
+pub mod keymap;
+pub mod material_trace;
 pub mod misc;
 pub mod modules;
+pub mod placement;
 pub mod primitive_machines;
+pub mod progression;
+pub mod routing;
+pub mod statistics;
+pub mod svg_export;
 pub mod ui;
 pub mod undo_history;
 
@@ -13,9 +13,10 @@ use crate::flow_pattern::{Flow, MaterialFlow};
 use crate::geometry::{
   Facing, GridIsomorphism, Number, Rotate, TransformedBy, Vector, VectorExtension,
 };
-use crate::modules::PlatonicModule;
+use crate::modules::{ModuleParameterValues, PlatonicModule};
 use crate::primitive_machines::{Assembler, Distributor};
-use crate::undo_history::AddRemoveMachines;
+use crate::progression::Progression;
+use crate::undo_history::UndoHistory;
 use std::ops::{Deref, DerefMut};
 
 pub const MAX_COMPONENTS: usize = 256;
@@ -36,6 +37,9 @@ pub enum MachineTypeId {
 #[derive(Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Debug)]
 pub struct MachineState {
   pub position: GridIsomorphism,
+  /// Only used by modules; see `ModuleParameter`.
+  #[serde(default)]
+  pub parameters: ModuleParameterValues,
 }
 
 #[derive(Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Debug)]
@@ -67,6 +71,28 @@ impl DerefMut for GlobalMachine {
 pub type MachineIdWithinPlatonicRegion = (Number, Number);
 
 impl PlatonicMachine {
+  pub fn new(type_id: MachineTypeId, position: GridIsomorphism) -> PlatonicMachine {
+    PlatonicMachine {
+      type_id,
+      state: MachineState {
+        position,
+        parameters: Default::default(),
+      },
+    }
+  }
+
+  /// An unrotated instance of preset `type_index` at (x, y), for building test worlds.
+  #[cfg(test)]
+  pub fn for_tests(type_index: usize, x: Number, y: Number) -> PlatonicMachine {
+    PlatonicMachine::new(
+      MachineTypeId::Preset(type_index),
+      GridIsomorphism {
+        translation: Vector::new(x, y),
+        ..Default::default()
+      },
+    )
+  }
+
   /// An ID that is guaranteed to be unique within its region.
   ///
   /// note: this must NOT include a module index,
@@ -529,6 +555,81 @@ impl MachineTypes {
           .map(|(index, module)| (MachineTypeId::Module(index), module)),
       )
   }
+
+  /// All module types whose regions contain `id`, directly or indirectly, including `id` itself.
+  pub fn modules_containing(&self, id: MachineTypeId) -> HashSet<MachineTypeId> {
+    let mut result = HashSet::new();
+    result.insert(id);
+    loop {
+      let num_found = result.len();
+      for (module_id, module) in self.modules() {
+        if !result.contains(&module_id)
+          && module
+            .region
+            .machines
+            .iter()
+            .any(|machine| result.contains(&machine.type_id))
+        {
+          result.insert(module_id);
+        }
+      }
+      if result.len() == num_found {
+        return result;
+      }
+    }
+  }
+
+  /// The total materials it takes to build a machine of type `id`. For modules, this is the cost
+  /// of the module itself plus the cost of everything inside it.
+  pub fn build_cost(&self, id: MachineTypeId) -> HashMap<Material, Number> {
+    self.build_cost_memoized(id, &mut HashMap::new())
+  }
+
+  /// The total build cost of a collection of machines.
+  pub fn total_build_cost<'a>(
+    &self,
+    machines: impl IntoIterator<Item = &'a PlatonicMachine>,
+  ) -> HashMap<Material, Number> {
+    let mut memo = HashMap::new();
+    let mut result = HashMap::new();
+    for machine in machines {
+      for (material, amount) in self.build_cost_memoized(machine.type_id, &mut memo) {
+        *result.entry(material).or_default() += amount;
+      }
+    }
+    result
+  }
+
+  // memoized because the same module type can appear many times, at many levels of nesting
+  fn build_cost_memoized(
+    &self,
+    id: MachineTypeId,
+    memo: &mut HashMap<MachineTypeId, HashMap<Material, Number>>,
+  ) -> HashMap<Material, Number> {
+    if let Some(cost) = memo.get(&id) {
+      return cost.clone();
+    }
+    let mut result: HashMap<Material, Number> = HashMap::new();
+    match self.get(id) {
+      MachineTypeRef::Module(module) => {
+        for &(amount, material) in &module.cost {
+          *result.entry(material).or_default() += amount;
+        }
+        for machine in &module.region.machines {
+          for (material, amount) in self.build_cost_memoized(machine.type_id, memo) {
+            *result.entry(material).or_default() += amount;
+          }
+        }
+      }
+      machine_type => {
+        for &(amount, material) in machine_type.cost() {
+          *result.entry(material).or_default() += amount;
+        }
+      }
+    }
+    memo.insert(id, result.clone());
+    result
+  }
 }
 
 #[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Debug, Derivative)]
@@ -545,11 +646,29 @@ pub struct Game {
   pub last_disturbed_times: WorldMachinesMap<Number>,
   pub last_change_time: Number,
   pub inventory_before_last_change: HashMap<Material, Number>,
-  pub undo_stack: Vec<AddRemoveMachines>,
-  pub redo_stack: Vec<AddRemoveMachines>,
+  #[serde(default, serialize_with = "UndoHistory::serialize_with_game")]
+  pub undo_history: UndoHistory,
+  #[serde(default)]
+  pub progression: Progression,
 }
 
 impl Game {
+  /// A game at time 0 with `machines` in the global region, `presets` as its only machine types, and an empty inventory, undo history, and progression.
+  pub fn new(presets: Vec<MachineType>, machines: Vec<PlatonicMachine>) -> Game {
+    Game {
+      global_region: PlatonicRegionContents { machines },
+      machine_types: MachineTypes {
+        presets,
+        custom_modules: Vec::new(),
+      },
+      last_disturbed_times: WorldMachinesMap::default(),
+      last_change_time: 0,
+      inventory_before_last_change: Default::default(),
+      undo_history: Default::default(),
+      progression: Default::default(),
+    }
+  }
+
   pub fn platonic_regions(&self) -> impl Iterator<Item = &PlatonicRegionContents> {
     std::iter::once(&self.global_region).chain(
       self
@@ -569,4 +688,43 @@ impl Game {
       )
       .flat_map(|region| &mut region.machines)
   }
+
+  /// How many instances of machine type `id` exist in the world, counting each instance of every module containing it.
+  pub fn num_instances(&self, id: MachineTypeId) -> Number {
+    fn count_in(
+      game: &Game,
+      region: &PlatonicRegionContents,
+      id: MachineTypeId,
+      memo: &mut HashMap<MachineTypeId, Number>,
+    ) -> Number {
+      region
+        .machines
+        .iter()
+        .map(|machine| {
+          if machine.type_id == id {
+            return 1;
+          }
+          match game.machine_types.get(machine.type_id) {
+            MachineTypeRef::Module(module) => match memo.get(&machine.type_id) {
+              Some(&count) => count,
+              None => {
+                let count = count_in(game, &module.region, id, memo);
+                memo.insert(machine.type_id, count);
+                count
+              }
+            },
+            _ => 0,
+          }
+        })
+        .sum()
+    }
+    count_in(self, &self.global_region, id, &mut HashMap::new())
+  }
+
+  /// The materials that went into building everything in the world.
+  pub fn embodied_materials(&self) -> HashMap<Material, Number> {
+    self
+      .machine_types
+      .total_build_cost(&self.global_region.machines)
+  }
 }
@@ -0,0 +1,536 @@
+/**
+
+Following a single unit of material through the factory, to answer questions like "where does the 57th iron ore from this mine go?"
+
+The future only describes flows, not individual units, so each machine's inputs are paired up with its outputs the same way its momentary visuals pair them, which means a trace always agrees with what the player sees on the map. Units are followed into and out of modules. A trace ends when an assembler consumes the unit, when it's dumped, or when a machine drops it without passing it on.
+
+All locations and times in a trace are global, even for machines inside modules.
+
+*/
+use crate::flow_pattern::{Flow, MaterialFlow};
+use crate::geometry::{GridIsomorphism, Number, TransformedBy};
+use crate::graph_algorithms::{GameFuture, OutputEdges, RegionFuture};
+use crate::machine_data::{
+  Game, InputLocation, MachineFuture, MachineIdWithinPlatonicRegion, MachineObservedInputs,
+  MachineTypeRef, Material, PlatonicRegionContents, WorldMachinesMap, TIME_TO_MOVE_MATERIAL,
+};
+use crate::modules::{ModuleParameterValues, PlatonicModule};
+use serde::{Deserialize, Serialize};
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Debug)]
+pub enum TracePointKind {
+  Input(usize),
+  Output(usize),
+}
+
+/// The unit of material passing through one of a machine's inputs or outputs.
+#[derive(Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Debug)]
+pub struct TracePoint {
+  /// The ids of the module instances containing the machine, starting from the global region, followed by the id of the machine itself.
+  pub machine: Vec<MachineIdWithinPlatonicRegion>,
+  pub kind: TracePointKind,
+  pub location: InputLocation,
+  pub time: Number,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Debug)]
+pub enum TraceEnd {
+  /// Used up by the assembly that starts at `time`, becoming part of that assembly's outputs.
+  Consumed { time: Number },
+  /// Dumped into the player's inventory.
+  Dumped {
+    location: InputLocation,
+    time: Number,
+  },
+  /// Dumped inside a module, somewhere other than one of its enabled outputs.
+  Lost {
+    location: InputLocation,
+    time: Number,
+  },
+  /// The machine at the last trace point never passes the unit on. This happens when the machine isn't operating, or when it receives more input than it can use.
+  Discarded,
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Debug)]
+pub struct MaterialTrace {
+  pub material: Material,
+  pub points: Vec<TracePoint>,
+  pub end: TraceEnd,
+}
+
+/// One region on the way from the global region down to where the unit currently is.
+struct Frame<'a> {
+  region: &'a PlatonicRegionContents,
+  future: &'a RegionFuture,
+  containing_module: Option<(&'a PlatonicModule, &'a ModuleParameterValues)>,
+  output_edges: OutputEdges,
+  /// The global time when this region's inner time is 0.
+  start_time: Number,
+  isomorphism: GridIsomorphism,
+  last_disturbed_times: Option<&'a WorldMachinesMap<Number>>,
+  /// The index of this region's module instance within the containing region.
+  index_in_parent: usize,
+}
+
+impl<'a> Frame<'a> {
+  fn index_of(&self, id: MachineIdWithinPlatonicRegion) -> Option<usize> {
+    self
+      .region
+      .machines
+      .iter()
+      .position(|machine| machine.id_within_region() == id)
+  }
+}
+
+/// Where the unit is, in the innermost frame's inner time.
+#[derive(Copy, Clone, Debug)]
+enum Step {
+  Leave {
+    machine: usize,
+    output: usize,
+    time: Number,
+  },
+  Arrive {
+    machine: usize,
+    input: usize,
+    time: Number,
+  },
+}
+
+struct Tracer<'a> {
+  game: &'a Game,
+  future: &'a GameFuture,
+  frames: Vec<Frame<'a>>,
+  points: Vec<TracePoint>,
+}
+
+impl<'a> Tracer<'a> {
+  fn frame(&self) -> &Frame<'a> {
+    self.frames.last().unwrap()
+  }
+
+  fn machine_type(&self, machine: usize) -> MachineTypeRef<'a> {
+    let frame = self.frame();
+    let platonic = &frame.region.machines[machine];
+    let type_id = match frame.containing_module {
+      Some((module, parameters)) => module.parameterized_type_id(parameters, platonic),
+      None => platonic.type_id,
+    };
+    self.game.machine_types.get(type_id)
+  }
+
+  fn observed_inputs(&self, machine: usize) -> MachineObservedInputs<'a> {
+    let frame = self.frame();
+    let future: &'a RegionFuture = frame.future;
+    let id = frame.region.machines[machine].id_within_region();
+    MachineObservedInputs {
+      input_flows: &future.machines[machine].inputs,
+      start_time: frame
+        .last_disturbed_times
+        .and_then(|times| times.here.get(&id))
+        .map_or(0, |t| t - frame.start_time),
+    }
+  }
+
+  fn output_location(&self, machine: usize, output: usize) -> InputLocation {
+    let frame = self.frame();
+    self
+      .game
+      .machine_types
+      .output_locations(&frame.region.machines[machine])
+      .nth(output)
+      .unwrap()
+  }
+
+  fn output_flow(&self, machine: usize, output: usize) -> Option<MaterialFlow> {
+    let frame = self.frame();
+    match *frame.output_edges[machine].get(output)? {
+      Some((destination, input)) => frame.future.machines[destination].inputs[input],
+      None => {
+        let location = self.output_location(machine, output);
+        frame
+          .future
+          .dumped
+          .iter()
+          .find(|(dumped_location, _)| *dumped_location == location)
+          .map(|&(_, flow)| flow)
+      }
+    }
+  }
+
+  fn push_point(
+    &mut self,
+    machine: usize,
+    kind: TracePointKind,
+    location: InputLocation,
+    time: Number,
+  ) {
+    let mut path: Vec<_> = self
+      .frames
+      .windows(2)
+      .map(|frames| frames[0].region.machines[frames[1].index_in_parent].id_within_region())
+      .collect();
+    let frame = self.frame();
+    path.push(frame.region.machines[machine].id_within_region());
+    let point = TracePoint {
+      machine: path,
+      kind,
+      location: location.transformed_by(frame.isomorphism),
+      time: time + frame.start_time,
+    };
+    self.points.push(point);
+  }
+
+  /// Descend into the inner region of the module instance `machine`. Returns None if it isn't an operating module.
+  fn enter(&mut self, machine: usize) -> Option<()> {
+    let frame = self.frame();
+    let region_future: &'a RegionFuture = frame.future;
+    let platonic = &frame.region.machines[machine];
+    let module = match self.game.machine_types.get(platonic.type_id) {
+      MachineTypeRef::Module(module) => module,
+      _ => return None,
+    };
+    let module_future = match &region_future.machines[machine].future {
+      Ok(MachineFuture::Module(module_future)) => module_future,
+      _ => return None,
+    };
+    let id = platonic.id_within_region();
+    let future = match region_future.disturbed_children.get(&id) {
+      Some(future) => future,
+      None => self
+        .future
+        .undisturbed_modules
+        .get(&(platonic.type_id, module_future.parameters.clone()))?
+        .get(&module_future.canonical_inputs)?,
+    };
+    let inner = Frame {
+      region: &module.region,
+      future,
+      containing_module: Some((module, &module_future.parameters)),
+      output_edges: module.region.output_edges(&self.game.machine_types),
+      start_time: frame.start_time + module_future.start_time,
+      isomorphism: platonic.state.position * frame.isomorphism,
+      last_disturbed_times: frame
+        .last_disturbed_times
+        .and_then(|times| times.children.get(&id)),
+      index_in_parent: machine,
+    };
+    self.frames.push(inner);
+    Some(())
+  }
+
+  /// If the unit was dumped at `location` at one of the containing module's enabled outputs, continue from that output in the containing region.
+  fn exit(&mut self, location: InputLocation, time: Number) -> Option<Step> {
+    let (module, parameters) = self.frame().containing_module?;
+    let output = module
+      .module_type
+      .outputs
+      .iter()
+      .position(|output| output.inner_location == location)?;
+    if !module.output_enabled(parameters, output) {
+      return None;
+    }
+    let inner = self.frames.pop().unwrap();
+    let module_start_time = inner.start_time - self.frame().start_time;
+    Some(Step::Leave {
+      machine: inner.index_in_parent,
+      output,
+      time: time + module_start_time + TIME_TO_MOVE_MATERIAL,
+    })
+  }
+
+  fn trace(&mut self, mut step: Step) -> TraceEnd {
+    loop {
+      step = match step {
+        Step::Leave {
+          machine,
+          output,
+          time,
+        } => {
+          let location = self.output_location(machine, output);
+          self.push_point(machine, TracePointKind::Output(output), location, time);
+          let edge = self.frame().output_edges[machine][output];
+          match edge {
+            Some((machine, input)) => Step::Arrive {
+              machine,
+              input,
+              time,
+            },
+            None => match self.exit(location, time) {
+              Some(step) => step,
+              None => {
+                let frame = self.frame();
+                let location = location.transformed_by(frame.isomorphism);
+                let time = time + frame.start_time;
+                return if frame.containing_module.is_some() {
+                  TraceEnd::Lost { location, time }
+                } else {
+                  TraceEnd::Dumped { location, time }
+                };
+              }
+            },
+          }
+        }
+        Step::Arrive {
+          machine,
+          input,
+          time,
+        } => {
+          let location = self
+            .game
+            .machine_types
+            .input_locations(&self.frame().region.machines[machine])
+            .nth(input)
+            .unwrap();
+          self.push_point(machine, TracePointKind::Input(input), location, time);
+          let inputs = self.observed_inputs(machine);
+          let region_future: &'a RegionFuture = self.frame().future;
+          match (
+            self.machine_type(machine),
+            &region_future.machines[machine].future,
+          ) {
+            (MachineTypeRef::Distributor(distributor), Ok(MachineFuture::Distributor(future))) => {
+              match distributor.output_for_input(inputs, future, input, time) {
+                Some((output, time)) => Step::Leave {
+                  machine,
+                  output,
+                  time,
+                },
+                None => return TraceEnd::Discarded,
+              }
+            }
+            (MachineTypeRef::Assembler(assembler), Ok(MachineFuture::Assembler(future))) => {
+              return match assembler.consuming_assembly_start(inputs, future, input, time) {
+                Some(start) => TraceEnd::Consumed {
+                  time: start + self.frame().start_time,
+                },
+                None => TraceEnd::Discarded,
+              };
+            }
+            (MachineTypeRef::Module(module), Ok(MachineFuture::Module(module_future))) => {
+              let inner_time = match module.inner_arrival_time(inputs, module_future, input, time) {
+                Some(inner_time) => inner_time,
+                None => return TraceEnd::Discarded,
+              };
+              let inner_location = module.module_type.inputs[input].inner_location;
+              if self.enter(machine).is_none() {
+                return TraceEnd::Discarded;
+              }
+              let frame = self.frame();
+              let destination =
+                frame
+                  .region
+                  .machines
+                  .iter()
+                  .enumerate()
+                  .find_map(|(index, inner)| {
+                    self
+                      .game
+                      .machine_types
+                      .input_locations(inner)
+                      .position(|location| location == inner_location)
+                      .map(|input| (index, input))
+                  });
+              match destination {
+                Some((machine, input)) => Step::Arrive {
+                  machine,
+                  input,
+                  time: inner_time,
+                },
+                None => {
+                  return TraceEnd::Lost {
+                    location: inner_location.transformed_by(frame.isomorphism),
+                    time: inner_time + frame.start_time,
+                  }
+                }
+              }
+            }
+            _ => return TraceEnd::Discarded,
+          }
+        }
+      };
+    }
+  }
+}
+
+impl Game {
+  /// Follow the `n`th unit of material (counting from 0) to leave output `output` of `machine`.
+  ///
+  /// `machine` is a path of ids, like `TracePoint::machine`: the module instances to descend through from the global region, followed by the machine itself. `n` counts from the start of the output's flow in `future`, which must be the future of this game, as returned by `Game::future()`. Returns None if the path doesn't lead to a machine, or if nothing leaves that output.
+  pub fn trace_material(
+    &self,
+    future: &GameFuture,
+    machine: &[MachineIdWithinPlatonicRegion],
+    output: usize,
+    n: Number,
+  ) -> Option<MaterialTrace> {
+    let mut tracer = Tracer {
+      game: self,
+      future,
+      frames: vec![Frame {
+        region: &self.global_region,
+        future: &future.global_region,
+        containing_module: None,
+        output_edges: self.global_region.output_edges(&self.machine_types),
+        start_time: 0,
+        isomorphism: GridIsomorphism::default(),
+        last_disturbed_times: Some(&self.last_disturbed_times),
+        index_in_parent: 0,
+      }],
+      points: Vec::new(),
+    };
+    let (&id, containing_modules) = machine.split_last()?;
+    for &module_id in containing_modules {
+      let index = tracer.frame().index_of(module_id)?;
+      tracer.enter(index)?;
+    }
+    let index = tracer.frame().index_of(id)?;
+    let flow = tracer.output_flow(index, output)?;
+    let time = flow.nth_disbursement_time(n)?;
+    let end = tracer.trace(Step::Leave {
+      machine: index,
+      output,
+      time,
+    });
+    Some(MaterialTrace {
+      material: flow.material,
+      points: tracer.points,
+      end,
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::machine_data::{MachineType, PlatonicMachine};
+  use crate::modules::basic_module;
+  use crate::primitive_machines::{conveyor, iron_mine, iron_smelter};
+
+  /// Presets: conveyor, iron mine, iron smelter, and a basic module with a row of conveyors from input 0 to output 0.
+  fn test_game(machines: Vec<PlatonicMachine>) -> Game {
+    let mut module = match basic_module() {
+      MachineType::Module(module) => module,
+      _ => unreachable!(),
+    };
+    module.region.machines.extend(
+      (-17..=17)
+        .step_by(2)
+        .map(|x| PlatonicMachine::for_tests(0, x, -3)),
+    );
+    Game::new(
+      vec![
+        conveyor(),
+        iron_mine(),
+        iron_smelter(),
+        MachineType::Module(module),
+      ],
+      machines,
+    )
+  }
+
+  fn kinds(trace: &MaterialTrace) -> Vec<TracePointKind> {
+    trace.points.iter().map(|point| point.kind).collect()
+  }
+
+  #[test]
+  fn unit_follows_conveyors_until_dumped() {
+    let game = test_game(vec![
+      PlatonicMachine::for_tests(1, 0, 0),
+      PlatonicMachine::for_tests(0, 4, 0),
+      PlatonicMachine::for_tests(0, 6, 0),
+    ]);
+    let future = game.future();
+    let trace = game.trace_material(&future, &[(0, 0)], 0, 56).unwrap();
+    assert_eq!(trace.material, Material::IronOre);
+    assert_eq!(
+      kinds(&trace),
+      vec![
+        TracePointKind::Output(0),
+        TracePointKind::Input(0),
+        TracePointKind::Output(0),
+        TracePointKind::Input(0),
+        TracePointKind::Output(0),
+      ]
+    );
+    assert_eq!(trace.points[1].machine, vec![(4, 0)]);
+    assert_eq!(trace.points[3].machine, vec![(6, 0)]);
+    for pair in trace.points.windows(2) {
+      assert!(pair[0].time <= pair[1].time);
+    }
+    let last = trace.points.last().unwrap();
+    assert_eq!(
+      trace.end,
+      TraceEnd::Dumped {
+        location: InputLocation::output(7, 0),
+        time: last.time,
+      }
+    );
+
+    // consecutive units stay in order
+    let next = game.trace_material(&future, &[(0, 0)], 0, 57).unwrap();
+    assert!(next.points.last().unwrap().time > last.time);
+  }
+
+  #[test]
+  fn smelter_consumes_some_units_and_drops_the_rest() {
+    let game = test_game(vec![
+      PlatonicMachine::for_tests(1, 0, 0),
+      PlatonicMachine::for_tests(2, 6, 0),
+    ]);
+    let future = game.future();
+    let mut consumed_starts = Vec::new();
+    let mut discarded = 0;
+    for n in 0..100 {
+      let trace = game.trace_material(&future, &[(0, 0)], 0, n).unwrap();
+      assert_eq!(trace.points.len(), 2);
+      match trace.end {
+        TraceEnd::Consumed { time } => {
+          assert!(time >= trace.points[1].time + TIME_TO_MOVE_MATERIAL);
+          consumed_starts.push(time);
+        }
+        TraceEnd::Discarded => discarded += 1,
+        end => panic!("unexpected end {:?}", end),
+      }
+    }
+    assert!(!consumed_starts.is_empty());
+    assert!(discarded > 0);
+    // each assembly uses exactly 3 ore, except maybe the last one, which may use ore from after the 100th
+    for start in &consumed_starts[..consumed_starts.len() - 3] {
+      assert_eq!(
+        consumed_starts
+          .iter()
+          .filter(|&other| other == start)
+          .count(),
+        3
+      );
+    }
+  }
+
+  #[test]
+  fn unit_crosses_module_boundaries() {
+    let game = test_game(vec![
+      PlatonicMachine::for_tests(1, -23, -3),
+      PlatonicMachine::for_tests(3, 0, 0),
+    ]);
+    let future = game.future();
+    let trace = game.trace_material(&future, &[(-23, -3)], 0, 10).unwrap();
+    assert_eq!(trace.points[1].machine, vec![(0, 0)]);
+    assert_eq!(trace.points[1].kind, TracePointKind::Input(0));
+    assert_eq!(trace.points[2].machine, vec![(0, 0), (-17, -3)]);
+    assert_eq!(trace.points[2].location, InputLocation::input(-18, -3));
+    let module_output = &trace.points[trace.points.len() - 1];
+    assert_eq!(module_output.machine, vec![(0, 0)]);
+    assert_eq!(module_output.kind, TracePointKind::Output(0));
+    assert_eq!(
+      trace.end,
+      TraceEnd::Dumped {
+        location: InputLocation::output(20, -3),
+        time: module_output.time,
+      }
+    );
+    for pair in trace.points.windows(2) {
+      assert!(pair[0].time <= pair[1].time);
+    }
+  }
+}
@@ -13,10 +13,10 @@ use crate::flow_pattern::{
 use crate::geometry::{Facing, Number, Vector, VectorExtension};
 use crate::graph_algorithms::RegionFuture;
 use crate::machine_data::{
-  Game, InputLocation, Inputs, MachineMomentaryVisuals, MachineObservedInputs,
-  MachineOperatingState, MachineType, MachineTypeId, MachineTypeRef, MachineTypeTrait,
-  MachineTypes, Material, PlatonicMachine, PlatonicRegionContents, StandardMachineInfo,
-  MAX_COMPONENTS, TIME_TO_MOVE_MATERIAL,
+  Game, InputLocation, Inputs, MachineIdWithinPlatonicRegion, MachineMomentaryVisuals,
+  MachineObservedInputs, MachineOperatingState, MachineType, MachineTypeId, MachineTypeRef,
+  MachineTypeTrait, MachineTypes, Material, PlatonicMachine, PlatonicRegionContents,
+  StandardMachineInfo, MAX_COMPONENTS, TIME_TO_MOVE_MATERIAL,
 };
 
 #[derive(Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Debug, Default)]
@@ -38,6 +38,53 @@ pub struct PlatonicModule {
   pub module_type: ModuleType,
   pub cost: Vec<(Number, Material)>,
   pub region: PlatonicRegionContents,
+  /// Settings that can differ between instances of this module, without making them different module types.
+  #[serde(default)]
+  pub parameters: Vec<ModuleParameter>,
+}
+
+/// Something about a module's behavior that each instance chooses for itself.
+///
+/// The instance's choice is stored in `MachineState::parameters`, at the same index as the parameter. A value of 0 always means "behave the same as the module's region says", so instances that don't set anything behave like an unparameterized module.
+#[derive(Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Debug)]
+pub enum ModuleParameter {
+  /// Swap the type of one machine in the region. Value `n > 0` selects `alternatives[n - 1]`.
+  ///
+  /// The alternatives must have the same shape as the original machine (same radius, inputs and outputs), so that the connections in the region don't depend on the choice. This is intended for things like choosing an assembler's recipe.
+  MachineChoice {
+    machine: MachineIdWithinPlatonicRegion,
+    alternatives: Vec<MachineTypeId>,
+  },
+  /// Value 1 turns off one of the module's outputs, and value 0 leaves it on. Materials that reach a disabled output are lost, just like materials dumped anywhere else inside a module.
+  OutputDisabled { output: usize },
+}
+
+/// The values an instance chose for its module's parameters, with unmentioned parameters being 0.
+///
+/// Trailing zeros are never stored, so that equivalent choices are also equal, which lets instances with the same choices share their futures.
+#[derive(Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Debug, Default)]
+#[serde(transparent)]
+pub struct ModuleParameterValues(Vec<usize>);
+
+impl ModuleParameterValues {
+  pub fn get(&self, parameter: usize) -> usize {
+    self.0.get(parameter).copied().unwrap_or(0)
+  }
+  pub fn set(&mut self, parameter: usize, value: usize) {
+    if parameter >= self.0.len() {
+      if value == 0 {
+        return;
+      }
+      self.0.resize(parameter + 1, 0);
+    }
+    self.0[parameter] = value;
+    while self.0.last() == Some(&0) {
+      self.0.pop();
+    }
+  }
+  pub fn is_default(&self) -> bool {
+    self.0.is_empty()
+  }
 }
 
 pub fn basic_module() -> MachineType {
@@ -70,6 +117,7 @@ pub fn basic_module() -> MachineType {
     region: PlatonicRegionContents {
       machines: Vec::new(),
     },
+    parameters: Vec::new(),
   })
 }
 
@@ -78,6 +126,8 @@ pub type CanonicalModuleInputs = Inputs<Option<MaterialFlowRate>>;
 pub struct ModuleMachineFuture {
   pub canonical_inputs: CanonicalModuleInputs,
   pub start_time: Number,
+  /// The instance's parameter values, which (along with the inputs) determine which variation of the inner future it uses.
+  pub parameters: ModuleParameterValues,
 }
 
 pub fn canonical_module_input(input: MaterialFlow) -> Option<MaterialFlowRate> {
@@ -121,6 +171,113 @@ pub fn canonical_module_input(input: MaterialFlow) -> Option<MaterialFlowRate> {
 
 #[live_prop_test]
 impl PlatonicModule {
+  /// The type that `machine`, in this module's region, behaves as in an instance with the given parameter values.
+  ///
+  /// Values that don't select an alternative (which `check_parameter_values` rules out) leave the machine as it is.
+  pub fn parameterized_type_id(
+    &self,
+    values: &ModuleParameterValues,
+    machine: &PlatonicMachine,
+  ) -> MachineTypeId {
+    let id = machine.id_within_region();
+    for (index, parameter) in self.parameters.iter().enumerate() {
+      if let ModuleParameter::MachineChoice {
+        machine: chosen,
+        alternatives,
+      } = parameter
+      {
+        if *chosen == id {
+          if let Some(&alternative) = values
+            .get(index)
+            .checked_sub(1)
+            .and_then(|choice| alternatives.get(choice))
+          {
+            return alternative;
+          }
+        }
+      }
+    }
+    machine.type_id
+  }
+
+  pub fn output_enabled(&self, values: &ModuleParameterValues, output: usize) -> bool {
+    !self
+      .parameters
+      .iter()
+      .enumerate()
+      .any(|(index, parameter)| {
+        *parameter == ModuleParameter::OutputDisabled { output } && values.get(index) != 0
+      })
+  }
+
+  pub fn check_parameters(&self, machine_types: &MachineTypes) -> Result<(), String> {
+    for parameter in &self.parameters {
+      match parameter {
+        ModuleParameter::MachineChoice {
+          machine,
+          alternatives,
+        } => {
+          let original = self
+            .region
+            .machines
+            .iter()
+            .find(|candidate| candidate.id_within_region() == *machine)
+            .ok_or_else(|| format!("parameter refers to missing machine {:?}", machine))?;
+          let original_type = machine_types.get(original.type_id);
+          for &alternative in alternatives {
+            let alternative_type = machine_types.get(alternative);
+            lpt_assert!(
+              !matches!(original_type, MachineTypeRef::Module(_))
+                && !matches!(alternative_type, MachineTypeRef::Module(_)),
+              "modules can't be swapped by parameters: {:?}",
+              parameter
+            );
+            lpt_assert!(
+              alternative_type.radius() == original_type.radius()
+                && alternative_type.relative_input_locations()
+                  == original_type.relative_input_locations()
+                && alternative_type.relative_output_locations()
+                  == original_type.relative_output_locations(),
+              "alternative {:?} doesn't have the same shape as the machine it replaces",
+              alternative
+            );
+          }
+        }
+        ModuleParameter::OutputDisabled { output } => {
+          lpt_assert!(
+            *output < self.module_type.outputs.len(),
+            "parameter refers to missing output {}",
+            output
+          );
+        }
+      }
+    }
+    Ok(())
+  }
+
+  /// Whether an instance can use `values`: no values for parameters that don't exist, or for choices that don't exist.
+  pub fn check_parameter_values(&self, values: &ModuleParameterValues) -> Result<(), String> {
+    lpt_assert!(
+      values.0.len() <= self.parameters.len(),
+      "values {:?} given for only {} parameters",
+      values,
+      self.parameters.len()
+    );
+    for (parameter, &value) in self.parameters.iter().zip(&values.0) {
+      let num_values = match parameter {
+        ModuleParameter::MachineChoice { alternatives, .. } => alternatives.len() + 1,
+        ModuleParameter::OutputDisabled { .. } => 2,
+      };
+      lpt_assert!(
+        value < num_values,
+        "value {} is out of range for {:?}",
+        value,
+        parameter
+      );
+    }
+    Ok(())
+  }
+
   fn internal_outputs(&self, variation: &RegionFuture) -> Inputs<Option<MaterialFlow>> {
     self
       .module_type
@@ -157,6 +314,33 @@ impl PlatonicModule {
       .collect()
   }
 
+  /// The inner time at which the unit of material arriving at outer input `input` at `outer_time` reaches the input's inner location.
+  ///
+  /// Inside, the materials arrive at the canonical rate, which may be slower than the outer flow; this pairs them up the same way as `module_relative_momentary_visuals`. Returns None if the unit never makes it inside.
+  pub fn inner_arrival_time(
+    &self,
+    inputs: MachineObservedInputs,
+    module_machine_future: &ModuleMachineFuture,
+    input: usize,
+    outer_time: Number,
+  ) -> Option<Number> {
+    let outer_input = inputs.input_flows.get(input)?.as_ref()?;
+    let inner_input = module_machine_future
+      .canonical_inputs
+      .get(input)?
+      .as_ref()?;
+    let earliest_inner_time = outer_time + TIME_TO_MOVE_MATERIAL - module_machine_future.start_time;
+    let inner_index = inner_input.num_disbursed_before(max(0, earliest_inner_time));
+    let inner_time = inner_input.nth_disbursement_time(inner_index)?;
+    let paired_outer_time = outer_input.last_disbursement_time_leq(
+      inner_time + module_machine_future.start_time - TIME_TO_MOVE_MATERIAL,
+    );
+    if paired_outer_time != Some(outer_time) {
+      return None;
+    }
+    Some(inner_time)
+  }
+
   pub fn module_relative_momentary_visuals(
     &self,
     inputs: MachineObservedInputs,
@@ -212,6 +396,9 @@ impl PlatonicModule {
     }
 
     for (output_index, inner_output) in self.internal_outputs(variation).iter().enumerate() {
+      if !self.output_enabled(&module_machine_future.parameters, output_index) {
+        continue;
+      }
       if let Some(inner_output) = inner_output {
         if let Some(material_inner_input_time) = inner_output.last_disbursement_time_lt(inner_time)
         {
@@ -393,6 +580,8 @@ impl MachineTypeTrait for PlatonicModule {
         .map(|material_flow| material_flow.and_then(canonical_module_input))
         .collect(),
       start_time: output_availability_start,
+      // filled in by the caller, which knows which instance this is
+      parameters: ModuleParameterValues::default(),
     })
   }
 
@@ -456,6 +645,12 @@ impl Game {
   /// visited.
   #[live_prop_test(postcondition = "self.is_canonical()")]
   pub fn canonicalize(&mut self) {
+    self.canonicalize_reporting_module_indices();
+  }
+
+  /// The same as `canonicalize`, but also reports where each remaining custom module ended up,
+  /// as a map from its old index in `custom_modules` to its new one.
+  pub fn canonicalize_reporting_module_indices(&mut self) -> HashMap<usize, usize> {
     self.global_region.sort_canonically();
     for module in &mut self.machine_types.custom_modules {
       module.region.sort_canonically();
@@ -481,6 +676,8 @@ impl Game {
         *module_index = found_modules[&module_index];
       }
     }
+
+    found_modules
   }
 
   pub fn is_canonical(&self) -> bool {
@@ -494,6 +691,200 @@ impl Game {
     if !self.is_canonical() {
       return Err("was not canonical".to_string());
     }
+    for (_, module) in self.machine_types.modules() {
+      module.check_parameters(&self.machine_types)?;
+    }
+    for machine in self.platonic_regions().flat_map(|region| &region.machines) {
+      match self.machine_types.get(machine.type_id) {
+        MachineTypeRef::Module(module) => {
+          module.check_parameter_values(&machine.state.parameters)?
+        }
+        _ => lpt_assert!(
+          machine.state.parameters.is_default(),
+          "{:?} isn't a module, but has parameter values",
+          machine
+        ),
+      }
+    }
     Ok(())
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::machine_data::{GlobalMachine, WorldMachinesMap};
+  use crate::primitive_machines::{iron_mine, AssemblerOutput};
+  use crate::undo_history::{
+    AddRemoveMachines, AddRemoveMachinesError, EditModule, EditModuleError,
+  };
+
+  /// A module with a mine that can be switched to mining iron directly, connected to output 0, which can be disabled.
+  fn game_with_modules(instance_parameters: &[&[usize]]) -> Game {
+    let mut module = match basic_module() {
+      MachineType::Module(module) => module,
+      _ => unreachable!(),
+    };
+    module
+      .region
+      .machines
+      .push(PlatonicMachine::for_tests(0, 15, -3));
+    module.parameters = vec![
+      ModuleParameter::MachineChoice {
+        machine: (15, -3),
+        alternatives: vec![MachineTypeId::Preset(2)],
+      },
+      ModuleParameter::OutputDisabled { output: 0 },
+    ];
+    let mut iron_source = match iron_mine() {
+      MachineType::Assembler(assembler) => assembler,
+      _ => unreachable!(),
+    };
+    iron_source.outputs = inputs![AssemblerOutput::new(3, 0, Material::Iron, 1)];
+
+    let instances = instance_parameters
+      .iter()
+      .enumerate()
+      .map(|(index, parameters)| {
+        let mut instance = PlatonicMachine::for_tests(1, 0, index as Number * 50);
+        for (index, &value) in parameters.iter().enumerate() {
+          instance.state.parameters.set(index, value);
+        }
+        instance
+      })
+      .collect();
+    Game::new(
+      vec![
+        iron_mine(),
+        MachineType::Module(module),
+        MachineType::Assembler(iron_source),
+      ],
+      instances,
+    )
+  }
+
+  fn dumped_materials(game: &Game) -> Vec<Material> {
+    game
+      .future()
+      .global_region
+      .dumped
+      .iter()
+      .map(|(_, flow)| flow.material)
+      .collect()
+  }
+
+  #[test]
+  fn parameter_values_are_normalized() {
+    let mut values = ModuleParameterValues::default();
+    values.set(2, 1);
+    values.set(2, 0);
+    assert!(values.is_default());
+    values.set(0, 0);
+    assert_eq!(values, ModuleParameterValues::default());
+    values.set(1, 3);
+    assert_eq!(values.get(1), 3);
+    assert_eq!(values.get(5), 0);
+  }
+
+  #[test]
+  fn instances_with_the_same_parameters_share_futures() {
+    let game = game_with_modules(&[&[], &[1], &[], &[0, 1]]);
+    game.check_invariants().unwrap();
+    let future = game.future();
+    assert_eq!(future.undisturbed_modules.len(), 3);
+    for variations in future.undisturbed_modules.values() {
+      assert_eq!(variations.len(), 1);
+    }
+    // the last instance has its output disabled, so only three outputs reach the global region
+    let dumped = dumped_materials(&game);
+    assert_eq!(dumped.len(), 3);
+    assert_eq!(
+      dumped
+        .iter()
+        .filter(|&&material| material == Material::Iron)
+        .count(),
+      1
+    );
+  }
+
+  #[test]
+  fn invalid_parameter_values_are_rejected() {
+    let game = game_with_modules(&[&[2]]);
+    assert!(game.check_invariants().is_err());
+    let game = game_with_modules(&[&[0, 0, 1]]);
+    assert!(game.check_invariants().is_err());
+
+    // even so, a value that doesn't select an alternative doesn't swap anything
+    let module = game.machine_types.get_module(MachineTypeId::Preset(1));
+    let mine = &module.region.machines[module.region.machines.len() - 1];
+    let mut values = ModuleParameterValues::default();
+    values.set(0, 2);
+    assert_eq!(
+      module.parameterized_type_id(&values, mine),
+      MachineTypeId::Preset(0)
+    );
+  }
+
+  #[test]
+  fn module_edits_cant_remove_a_chosen_machine() {
+    let mut game = game_with_modules(&[&[1]]);
+    let mut selected = WorldMachinesMap::default();
+    let future = game.future();
+    let mine = PlatonicMachine::for_tests(0, 15, -3);
+    let edit = |added: Vec<PlatonicMachine>| EditModule {
+      module: MachineTypeId::Preset(1),
+      added,
+      removed: vec![mine.clone()],
+      restore_preset: None,
+    };
+    assert!(matches!(
+      game.edit_module(edit(vec![]), &mut selected, &future, 0),
+      Err(EditModuleError::BreaksParameters(_))
+    ));
+    assert_eq!(game.machine_types.custom_modules.len(), 0);
+    // replacing it with a machine of the same shape is fine, though
+    game
+      .edit_module(
+        edit(vec![PlatonicMachine::for_tests(2, 15, -3)]),
+        &mut selected,
+        &future,
+        0,
+      )
+      .unwrap();
+    game.canonicalize();
+    game.check_invariants().unwrap();
+  }
+
+  #[test]
+  fn removing_a_chosen_machine_from_one_instance_is_refused() {
+    let mut game = game_with_modules(&[&[1], &[1]]);
+    let mut selected = WorldMachinesMap::default();
+    let future = game.future();
+    // the mine inside the second instance, in global coordinates
+    let mine = GlobalMachine(PlatonicMachine::for_tests(0, 15, 47));
+    let change = |added: Vec<GlobalMachine>| AddRemoveMachines {
+      added,
+      removed: vec![mine.clone()],
+    };
+    assert!(matches!(
+      game.add_remove_machines(change(vec![]), &mut selected, &future, 0),
+      Err(AddRemoveMachinesError::BreaksParameters(_))
+    ));
+    assert_eq!(game.machine_types.custom_modules.len(), 0);
+    // replacing it with a machine of the same shape is fine, and only changes that instance
+    game
+      .add_remove_machines(
+        change(vec![GlobalMachine(PlatonicMachine::for_tests(2, 15, 47))]),
+        &mut selected,
+        &future,
+        0,
+      )
+      .unwrap();
+    assert_eq!(game.machine_types.custom_modules.len(), 1);
+    assert_eq!(
+      game.global_region.machines[0].type_id,
+      MachineTypeId::Preset(1)
+    );
+    game.check_invariants().unwrap();
+  }
+}
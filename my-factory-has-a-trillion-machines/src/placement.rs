@@ -0,0 +1,342 @@
+/**
+
+Checking whether a machine can be built in a particular place, and explaining why not.
+
+The checks don't stop at the first problem, so a frontend can show everything that's wrong with a placement at once, and tests can assert on the specific kind of failure.
+
+There are two frames of reference a placement can be given in. `Game::placement_problems` takes a global position, and (like building from the map) puts the machine in the smallest module instance that fully contains it. `Game::placement_problems_in_module` takes a position relative to a module type's inner region, which is how machines are placed while editing a module.
+
+*/
+use crate::geometry::{GridIsomorphism, Number};
+use crate::graph_algorithms::{BaseAspect, FutureAspect, GameFuture, GameView};
+use crate::machine_data::{
+  Game, InputLocation, MachineTypeId, MachineTypeRef, MachineTypeTrait, MachineTypes, Material,
+  PlatonicRegionContents,
+};
+use crate::modules::PlatonicModule;
+use serde::{Deserialize, Serialize};
+use std::cmp::max;
+use std::collections::HashMap;
+use std::fmt;
+
+/// A machine that is in the way, positioned in the same frame as the attempted placement.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Debug)]
+pub struct OverlappingMachine {
+  pub type_id: MachineTypeId,
+  pub position: GridIsomorphism,
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Debug)]
+pub enum PlacementProblem {
+  Overlap {
+    machines: Vec<OverlappingMachine>,
+  },
+  /// The machine would stick out past `inner_radius` of the module it's being placed in.
+  OutsideModule {
+    module: MachineTypeId,
+    inner_radius: Number,
+    /// How far from the module's center the machine would reach.
+    extent: Number,
+  },
+  /// An input or output would end up somewhere other than the middle of a tile edge, so nothing could ever connect to it.
+  MisalignedInputLocation {
+    location: InputLocation,
+  },
+  InsufficientInventory {
+    material: Material,
+    needed: Number,
+    available: Number,
+  },
+}
+
+impl fmt::Display for PlacementProblem {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      PlacementProblem::Overlap { machines } => write!(
+        f,
+        "overlaps {} other machine{}",
+        machines.len(),
+        if machines.len() == 1 { "" } else { "s" }
+      ),
+      PlacementProblem::OutsideModule { .. } => write!(f, "sticks out of the module"),
+      PlacementProblem::MisalignedInputLocation { .. } => {
+        write!(f, "inputs and outputs aren't aligned with the grid")
+      }
+      PlacementProblem::InsufficientInventory {
+        material,
+        needed,
+        available,
+      } => write!(
+        f,
+        "needs {} {:?}, but only {} available",
+        needed, material, available
+      ),
+    }
+  }
+}
+
+/// Where the machine would end up if placed at a global position: the smallest region containing it, that region's isomorphism, and the module it's the inner region of (if any).
+pub(crate) fn smallest_region_containing<'a>(
+  machine_types: &'a MachineTypes,
+  region: &'a PlatonicRegionContents,
+  region_isomorphism: GridIsomorphism,
+  position: GridIsomorphism,
+  radius: Number,
+) -> (
+  &'a PlatonicRegionContents,
+  GridIsomorphism,
+  Option<&'a PlatonicModule>,
+) {
+  for machine in &region.machines {
+    if let MachineTypeRef::Module(module) = machine_types.get(machine.type_id) {
+      let isomorphism = machine.state.position * region_isomorphism;
+      let relative_position = position.translation - isomorphism.translation;
+      let available_radius = module.module_type.inner_radius - radius;
+      if max(relative_position[0].abs(), relative_position[1].abs()) <= available_radius {
+        let (inner_region, inner_isomorphism, inner_module) =
+          smallest_region_containing(machine_types, &module.region, isomorphism, position, radius);
+        return (
+          inner_region,
+          inner_isomorphism,
+          inner_module.or(Some(module)),
+        );
+      }
+    }
+  }
+  (region, region_isomorphism, None)
+}
+
+impl Game {
+  /// Problems with building a machine of type `type_id` at the global `position` at `time`.
+  ///
+  /// `future` must be the future of this game, as returned by `Game::future()`.
+  pub fn placement_problems(
+    &self,
+    future: &GameFuture,
+    time: Number,
+    type_id: MachineTypeId,
+    position: GridIsomorphism,
+  ) -> Vec<PlacementProblem> {
+    let machine_type = self.machine_types.get(type_id);
+    let (region, region_isomorphism, _) = smallest_region_containing(
+      &self.machine_types,
+      &self.global_region,
+      GridIsomorphism::default(),
+      position,
+      machine_type.radius(),
+    );
+    let mut problems = Vec::new();
+    self.push_overlap(
+      &mut problems,
+      region,
+      region_isomorphism,
+      machine_type,
+      position,
+    );
+    push_misaligned(&mut problems, machine_type, position);
+    self.push_inventory(
+      &mut problems,
+      future,
+      time,
+      &self.machine_types.build_cost(type_id),
+    );
+    problems
+  }
+
+  /// Problems with building a machine of type `type_id` at `position` inside the inner region of `module`.
+  pub fn placement_problems_in_module(
+    &self,
+    future: &GameFuture,
+    time: Number,
+    module: MachineTypeId,
+    type_id: MachineTypeId,
+    position: GridIsomorphism,
+  ) -> Vec<PlacementProblem> {
+    let machine_type = self.machine_types.get(type_id);
+    let platonic_module = self.machine_types.get_module(module);
+    let mut problems = Vec::new();
+    let offset = position.translation;
+    let extent = max(offset[0].abs(), offset[1].abs()) + machine_type.radius();
+    let inner_radius = platonic_module.module_type.inner_radius;
+    if extent > inner_radius {
+      problems.push(PlacementProblem::OutsideModule {
+        module,
+        inner_radius,
+        extent,
+      });
+    }
+    self.push_overlap(
+      &mut problems,
+      &platonic_module.region,
+      GridIsomorphism::default(),
+      machine_type,
+      position,
+    );
+    push_misaligned(&mut problems, machine_type, position);
+    // every instance of the module gets a copy
+    let mut cost = self.machine_types.build_cost(type_id);
+    let num_instances = self.num_instances(module);
+    for amount in cost.values_mut() {
+      *amount *= num_instances;
+    }
+    self.push_inventory(&mut problems, future, time, &cost);
+    problems
+  }
+
+  fn push_overlap(
+    &self,
+    problems: &mut Vec<PlacementProblem>,
+    region: &PlatonicRegionContents,
+    region_isomorphism: GridIsomorphism,
+    machine_type: MachineTypeRef,
+    position: GridIsomorphism,
+  ) {
+    let machines: Vec<OverlappingMachine> = region
+      .machines
+      .iter()
+      .filter_map(|machine| {
+        let machine_position = machine.state.position * region_isomorphism;
+        let radius = self.machine_types.get(machine.type_id).radius() + machine_type.radius();
+        let offset = (position / machine_position).translation;
+        if offset[0].abs() < radius && offset[1].abs() < radius {
+          Some(OverlappingMachine {
+            type_id: machine.type_id,
+            position: machine_position,
+          })
+        } else {
+          None
+        }
+      })
+      .collect();
+    if !machines.is_empty() {
+      problems.push(PlacementProblem::Overlap { machines });
+    }
+  }
+
+  fn push_inventory(
+    &self,
+    problems: &mut Vec<PlacementProblem>,
+    future: &GameFuture,
+    time: Number,
+    cost: &HashMap<Material, Number>,
+  ) {
+    let inventory = GameView::<(BaseAspect, FutureAspect)>::new(self, future).inventory_at(time);
+    for (&material, &needed) in cost {
+      let available = inventory.get(&material).copied().unwrap_or(0);
+      if available < needed {
+        problems.push(PlacementProblem::InsufficientInventory {
+          material,
+          needed,
+          available,
+        });
+      }
+    }
+  }
+}
+
+fn push_misaligned(
+  problems: &mut Vec<PlacementProblem>,
+  machine_type: MachineTypeRef,
+  position: GridIsomorphism,
+) {
+  for location in machine_type
+    .input_locations(position)
+    .chain(machine_type.output_locations(position))
+  {
+    if location.position[0].abs() % 2 == location.position[1].abs() % 2 {
+      problems.push(PlacementProblem::MisalignedInputLocation { location });
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::geometry::Vector;
+  use crate::machine_data::PlatonicMachine;
+  use crate::modules::basic_module;
+  use crate::primitive_machines::{conveyor, iron_mine};
+
+  fn at(x: Number, y: Number) -> GridIsomorphism {
+    GridIsomorphism {
+      translation: Vector::new(x, y),
+      ..Default::default()
+    }
+  }
+
+  fn test_game() -> Game {
+    let mut game = Game::new(
+      vec![conveyor(), iron_mine(), basic_module()],
+      vec![
+        PlatonicMachine::for_tests(0, 1, 1),
+        PlatonicMachine::for_tests(2, 100, 100),
+      ],
+    );
+    game
+      .inventory_before_last_change
+      .insert(Material::Iron, 1000);
+    game
+  }
+
+  #[test]
+  fn valid_placement_has_no_problems() {
+    let game = test_game();
+    let future = game.future();
+    assert_eq!(
+      game.placement_problems(&future, 0, MachineTypeId::Preset(1), at(11, 11)),
+      vec![]
+    );
+  }
+
+  #[test]
+  fn overlap_lists_the_machines_in_the_way() {
+    let game = test_game();
+    let future = game.future();
+    assert_eq!(
+      game.placement_problems(&future, 0, MachineTypeId::Preset(1), at(3, 3)),
+      vec![PlacementProblem::Overlap {
+        machines: vec![OverlappingMachine {
+          type_id: MachineTypeId::Preset(0),
+          position: at(1, 1),
+        }]
+      }]
+    );
+  }
+
+  #[test]
+  fn misaligned_and_unaffordable_placements_are_reported() {
+    let mut game = test_game();
+    game.inventory_before_last_change.clear();
+    let future = game.future();
+    let problems = game.placement_problems(&future, 0, MachineTypeId::Preset(1), at(10, 11));
+    assert!(problems
+      .iter()
+      .any(|problem| matches!(problem, PlacementProblem::MisalignedInputLocation { .. })));
+    assert!(problems.contains(&PlacementProblem::InsufficientInventory {
+      material: Material::Iron,
+      needed: 50,
+      available: 0,
+    }));
+  }
+
+  #[test]
+  fn placement_in_module_must_fit_inside() {
+    let game = test_game();
+    let future = game.future();
+    let problems = game.placement_problems_in_module(
+      &future,
+      0,
+      MachineTypeId::Preset(2),
+      MachineTypeId::Preset(1),
+      at(17, 1),
+    );
+    assert_eq!(
+      problems,
+      vec![PlacementProblem::OutsideModule {
+        module: MachineTypeId::Preset(2),
+        inner_radius: 18,
+        extent: 20,
+      }]
+    );
+  }
+}
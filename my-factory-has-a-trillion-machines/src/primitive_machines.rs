@@ -8,6 +8,7 @@ use crate::machine_data::{
 };
 use live_prop_test::live_prop_test;
 use nalgebra::Vector2;
+use num::Integer;
 use serde::{Deserialize, Serialize};
 use std::cmp::{max, min};
 use std::convert::TryFrom;
@@ -98,6 +99,34 @@ pub fn iron_mine() -> MachineType {
   })
 }
 
+/// Unlocked by progression: the same ore as two iron mines, in the space of one.
+pub fn twin_iron_mine() -> MachineType {
+  MachineType::Assembler(Assembler {
+    info: StandardMachineInfo::new("Twin iron mine", "mine", 3, vec![(150, Material::Iron)]),
+    inputs: inputs![],
+    outputs: inputs![
+      AssemblerOutput::new(3, -2, Material::IronOre, 1),
+      AssemblerOutput::new(3, 2, Material::IronOre, 1),
+    ],
+    assembly_duration: TIME_TO_MOVE_MATERIAL,
+  })
+}
+
+/// Unlocked by progression: an iron smelter that doesn't waste any ore.
+pub fn efficient_iron_smelter() -> MachineType {
+  MachineType::Assembler(Assembler {
+    info: StandardMachineInfo::new(
+      "Efficient iron smelter",
+      "machine",
+      3,
+      vec![(20, Material::Iron)],
+    ),
+    inputs: inputs![AssemblerInput::new(-3, 0, Material::IronOre, 3),],
+    outputs: inputs![AssemblerOutput::new(3, 0, Material::Iron, 3),],
+    assembly_duration: 10 * TIME_TO_MOVE_MATERIAL,
+  })
+}
+
 #[derive(Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Debug)]
 pub struct DistributorFuture {
   outputs: Inputs<FlowPattern>,
@@ -267,12 +296,101 @@ impl MachineTypeTrait for Distributor {
   }
 }
 
+impl Distributor {
+  /// Which output the unit of material arriving at `input` at `time` leaves from, and when.
+  ///
+  /// This follows the same pairing of inputs with outputs as `relative_momentary_visuals`. Returns None if the distributor never passes that unit on, because it arrived before the distributor was ready, or because the inputs are faster than the outputs and it was one of the skipped ones.
+  pub fn output_for_input(
+    &self,
+    inputs: MachineObservedInputs,
+    future: &DistributorFuture,
+    input: usize,
+    time: Number,
+  ) -> Option<(usize, Number)> {
+    let cropped_inputs: Inputs<_> = inputs
+      .input_flows
+      .iter()
+      .map(|material_flow| {
+        material_flow.map(|material_flow| CroppedFlow {
+          flow: material_flow.flow,
+          crop_start: material_flow
+            .last_disbursement_time_leq(future.output_availability_start)
+            .unwrap(),
+        })
+      })
+      .collect();
+    let crop_start = cropped_inputs.get(input)?.as_ref()?.crop_start;
+    if time < crop_start || time < inputs.start_time {
+      return None;
+    }
+    // simultaneous disbursements are ordered by input index
+    let simultaneous_before: Number = cropped_inputs[..input]
+      .iter()
+      .map(|flow| flow.num_disbursed_at_time(time))
+      .sum();
+    let input_index_since_start = cropped_inputs.num_disbursed_before(time) + simultaneous_before
+      - cropped_inputs.num_disbursed_before(inputs.start_time);
+
+    let output_rate = future.outputs.rate();
+    let input_rate = inputs.input_flows.rate();
+    // the smallest output index that visuals would pair with this input index, if any
+    let output_index_since_start =
+      (input_index_since_start * output_rate + input_rate - 1).div_floor(&input_rate);
+    if output_index_since_start * input_rate / output_rate != input_index_since_start {
+      return None;
+    }
+    let (output_time, output) = future
+      .outputs
+      .nth_disbursement_geq_time(output_index_since_start, inputs.start_time)?;
+    Some((output, output_time))
+  }
+}
+
 #[derive(Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Debug)]
 pub struct AssemblerFuture {
   assembly_start_pattern: FlowPattern,
   outputs: Inputs<FlowPattern>,
 }
 
+impl AssemblerFuture {
+  /// The times when assemblies start, which is also when their inputs are consumed.
+  pub fn assembly_start_pattern(&self) -> FlowPattern {
+    self.assembly_start_pattern
+  }
+  /// The flows leaving each output, in the same order as `Assembler::outputs`.
+  pub fn output_patterns(&self) -> &Inputs<FlowPattern> {
+    &self.outputs
+  }
+}
+
+impl Assembler {
+  /// The start time of the assembly that uses up the unit of material arriving at `input` at `time`.
+  ///
+  /// Each assembly uses the most recent `cost` units that arrived early enough, like in `relative_momentary_visuals`. Returns None if the unit isn't one of them, which happens when it arrives faster than the assembler can use it.
+  pub fn consuming_assembly_start(
+    &self,
+    inputs: MachineObservedInputs,
+    future: &AssemblerFuture,
+    input: usize,
+    time: Number,
+  ) -> Option<Number> {
+    let material_flow = inputs.input_flows.get(input)?.as_ref()?;
+    let cost = self.inputs.get(input)?.cost;
+    let input_index = material_flow.num_disbursed_between([inputs.start_time, time]);
+    let assembly_start_time = future
+      .assembly_start_pattern
+      .first_disbursement_time_geq(time + TIME_TO_MOVE_MATERIAL);
+    let last_input_index = material_flow.num_disbursed_between([
+      inputs.start_time,
+      assembly_start_time - TIME_TO_MOVE_MATERIAL + 1,
+    ]) - 1;
+    if time < inputs.start_time || input_index <= last_input_index - cost {
+      return None;
+    }
+    Some(assembly_start_time)
+  }
+}
+
 #[live_prop_test(use_trait_tests)]
 impl MachineTypeTrait for Assembler {
   // basic information
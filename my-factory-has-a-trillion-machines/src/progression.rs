@@ -0,0 +1,327 @@
+/**
+
+Goals for the player to work toward, and the machine types they unlock.
+
+A goal is reached once the factory has delivered at least the required amount of each material to the inventory, counting from the start of the game. Deliveries are worked out from inventory-at-time queries, but they never go down: spending materials on machines doesn't undo any progress, and reaching a goal doesn't spend anything either.
+
+The goal definitions are plain data, stored in the `Game` along with which goals have been reached, so a saved game keeps its own progression even if the defaults change later. Unlocked machine types are appended to `MachineTypes::presets`, which keeps the ids of existing presets stable.
+
+*/
+use crate::geometry::Number;
+use crate::graph_algorithms::{BaseAspect, FutureAspect, GameFuture, GameView};
+use crate::machine_data::{Game, MachineType, Material};
+use crate::modules;
+use crate::primitive_machines;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Debug)]
+pub struct Goal {
+  pub name: String,
+  pub requirements: Vec<(Number, Material)>,
+  /// Indices of goals that must be reached before this one counts.
+  pub prerequisites: Vec<usize>,
+  pub unlocks: Vec<MachineType>,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Debug)]
+pub struct ReachedGoal {
+  pub goal: usize,
+  pub time: Number,
+}
+
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Debug, Default)]
+pub struct Progression {
+  pub goals: Vec<Goal>,
+  pub reached: Vec<ReachedGoal>,
+  /// How much of each material had been delivered as of `Game::last_change_time`.
+  #[serde(default)]
+  pub delivered_before_last_change: HashMap<Material, Number>,
+}
+
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Debug)]
+pub struct RequirementProgress {
+  pub material: Material,
+  pub delivered: Number,
+  pub need: Number,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct GoalProgress {
+  pub goal: usize,
+  pub requirements: Vec<RequirementProgress>,
+}
+
+impl GoalProgress {
+  /// How close the goal is to being reached, from 0.0 to 1.0. Each requirement counts equally.
+  pub fn fraction(&self) -> f64 {
+    if self.requirements.is_empty() {
+      return 1.0;
+    }
+    let total: f64 = self
+      .requirements
+      .iter()
+      .map(|requirement| {
+        if requirement.need <= 0 {
+          1.0
+        } else {
+          (requirement.delivered as f64 / requirement.need as f64).min(1.0)
+        }
+      })
+      .sum();
+    total / self.requirements.len() as f64
+  }
+  pub fn is_complete(&self) -> bool {
+    self
+      .requirements
+      .iter()
+      .all(|requirement| requirement.delivered >= requirement.need)
+  }
+}
+
+impl Goal {
+  pub fn new(
+    name: &str,
+    requirements: Vec<(Number, Material)>,
+    prerequisites: Vec<usize>,
+    unlocks: Vec<MachineType>,
+  ) -> Goal {
+    Goal {
+      name: name.to_owned(),
+      requirements,
+      prerequisites,
+      unlocks,
+    }
+  }
+}
+
+impl Progression {
+  pub fn new(goals: Vec<Goal>) -> Progression {
+    Progression {
+      goals,
+      reached: Vec::new(),
+      delivered_before_last_change: HashMap::new(),
+    }
+  }
+
+  pub fn is_reached(&self, goal: usize) -> bool {
+    self.reached.iter().any(|reached| reached.goal == goal)
+  }
+
+  /// Goals that haven't been reached yet, but whose prerequisites all have.
+  pub fn available_goals(&self) -> impl Iterator<Item = usize> + '_ {
+    (0..self.goals.len()).filter(move |&index| {
+      !self.is_reached(index)
+        && self.goals[index]
+          .prerequisites
+          .iter()
+          .all(|&prerequisite| self.is_reached(prerequisite))
+    })
+  }
+
+  pub fn progress(&self, goal: usize, delivered: &HashMap<Material, Number>) -> GoalProgress {
+    GoalProgress {
+      goal,
+      requirements: self.goals[goal]
+        .requirements
+        .iter()
+        .map(|&(need, material)| RequirementProgress {
+          material,
+          delivered: delivered.get(&material).copied().unwrap_or(0),
+          need,
+        })
+        .collect(),
+    }
+  }
+}
+
+impl Game {
+  /// How much of each material the factory has delivered to the inventory by `time`, since the game began.
+  ///
+  /// Between changes, the inventory only grows by what gets delivered, so this is the growth since the last change on top of what had been delivered before it.
+  pub fn delivered_at(&self, future: &GameFuture, time: Number) -> HashMap<Material, Number> {
+    let inventory = GameView::<(BaseAspect, FutureAspect)>::new(self, future).inventory_at(time);
+    let mut delivered = self.progression.delivered_before_last_change.clone();
+    for (material, amount) in inventory {
+      let before = self
+        .inventory_before_last_change
+        .get(&material)
+        .copied()
+        .unwrap_or(0);
+      *delivered.entry(material).or_default() += amount - before;
+    }
+    delivered
+  }
+
+  pub fn goal_progress(&self, future: &GameFuture, time: Number) -> Vec<GoalProgress> {
+    let delivered = self.delivered_at(future, time);
+    self
+      .progression
+      .available_goals()
+      .map(|goal| self.progression.progress(goal, &delivered))
+      .collect()
+  }
+
+  /// Mark every goal that is reached by `time` as reached, and add the machine types it unlocks
+  /// to the presets. Reaching a goal can make others available, which are checked immediately.
+  ///
+  /// Returns the newly reached goals, in the order they were reached.
+  pub fn update_progression(&mut self, future: &GameFuture, time: Number) -> Vec<usize> {
+    let delivered = self.delivered_at(future, time);
+    let mut newly_reached = Vec::new();
+    loop {
+      let next = self
+        .progression
+        .available_goals()
+        .find(|&goal| self.progression.progress(goal, &delivered).is_complete());
+      let goal = match next {
+        Some(goal) => goal,
+        None => break,
+      };
+      self.progression.reached.push(ReachedGoal { goal, time });
+      self
+        .machine_types
+        .presets
+        .extend(self.progression.goals[goal].unlocks.iter().cloned());
+      newly_reached.push(goal);
+    }
+    newly_reached
+  }
+}
+
+/// The machines that are available before any goals are reached, to go with `standard_goals`.
+pub fn starting_presets() -> Vec<MachineType> {
+  vec![
+    primitive_machines::conveyor(),
+    primitive_machines::splitter(),
+    primitive_machines::iron_smelter(),
+    primitive_machines::iron_mine(),
+    modules::basic_module(),
+  ]
+}
+
+pub fn standard_goals() -> Vec<Goal> {
+  vec![
+    Goal::new(
+      "Deliver 1000 Iron",
+      vec![(1000, Material::Iron)],
+      vec![],
+      vec![primitive_machines::twin_iron_mine()],
+    ),
+    Goal::new(
+      "Deliver 20000 Iron",
+      vec![(20000, Material::Iron)],
+      vec![0],
+      vec![primitive_machines::efficient_iron_smelter()],
+    ),
+  ]
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::graph_algorithms::BaseMutAspect;
+  use crate::machine_data::{PlatonicMachine, TIME_TO_MOVE_MATERIAL};
+
+  const IRON_SMELTER: usize = 2;
+  const IRON_MINE: usize = 3;
+
+  fn game_with_mine_and_smelter() -> Game {
+    let mut game = Game::new(
+      starting_presets(),
+      vec![
+        PlatonicMachine::for_tests(IRON_MINE, 0, 0),
+        PlatonicMachine::for_tests(IRON_SMELTER, 6, 0),
+      ],
+    );
+    game.progression = Progression::new(standard_goals());
+    game
+  }
+
+  fn delivered_iron(game: &Game, future: &GameFuture, time: Number) -> Number {
+    game.goal_progress(future, time)[0].requirements[0].delivered
+  }
+
+  #[test]
+  fn goals_unlock_presets_in_order() {
+    let mut game = game_with_mine_and_smelter();
+    let future = game.future();
+    assert!(game.update_progression(&future, 0).is_empty());
+    let progress = game.goal_progress(&future, 0);
+    assert_eq!(progress.len(), 1);
+    assert!(!progress[0].is_complete());
+
+    // the inventory a game starts with doesn't count as delivered
+    game
+      .inventory_before_last_change
+      .insert(Material::Iron, 20000);
+    assert!(game.update_progression(&future, 0).is_empty());
+
+    game
+      .progression
+      .delivered_before_last_change
+      .insert(Material::Iron, 20000);
+    assert_eq!(game.update_progression(&future, 0), vec![0, 1]);
+    let presets = &game.machine_types.presets;
+    assert_eq!(presets[..presets.len() - 2], starting_presets()[..]);
+    assert_eq!(
+      presets[presets.len() - 2..],
+      [
+        primitive_machines::twin_iron_mine(),
+        primitive_machines::efficient_iron_smelter()
+      ]
+    );
+    assert!(game.update_progression(&future, 0).is_empty());
+  }
+
+  #[test]
+  fn production_counts_toward_goals() {
+    let mut game = game_with_mine_and_smelter();
+    let future = game.future();
+    let later = 1_000_000 * TIME_TO_MOVE_MATERIAL;
+    assert!(game.goal_progress(&future, later)[0].is_complete());
+    assert_eq!(game.update_progression(&future, later), vec![0, 1]);
+  }
+
+  #[test]
+  fn spending_and_changes_keep_deliveries() {
+    let mut game = game_with_mine_and_smelter();
+    let future = game.future();
+    let change_time = 1000 * TIME_TO_MOVE_MATERIAL;
+    let delivered = delivered_iron(&game, &future, change_time);
+    assert!(delivered > 0);
+
+    BaseMutAspect::new(&mut game, change_time, &future);
+    *game
+      .inventory_before_last_change
+      .get_mut(&Material::Iron)
+      .unwrap() -= delivered;
+    assert_eq!(
+      game.progression.delivered_before_last_change[&Material::Iron],
+      delivered
+    );
+    let future = game.future();
+    assert_eq!(delivered_iron(&game, &future, change_time), delivered);
+    assert!(delivered_iron(&game, &future, 2 * change_time) > delivered);
+  }
+
+  #[test]
+  fn twin_mine_delivers_twice_as_much_ore() {
+    let time = 1000 * TIME_TO_MOVE_MATERIAL;
+    let delivered_ore = |machine_type| {
+      let mut presets = starting_presets();
+      presets.push(machine_type);
+      let game = Game::new(
+        presets,
+        vec![PlatonicMachine::for_tests(starting_presets().len(), 0, 0)],
+      );
+      game.delivered_at(&game.future(), time)[&Material::IronOre]
+    };
+    let single = delivered_ore(primitive_machines::iron_mine());
+    assert!(single > 0);
+    assert_eq!(
+      delivered_ore(primitive_machines::twin_iron_mine()),
+      2 * single
+    );
+  }
+}
@@ -0,0 +1,572 @@
+/**
+
+Automatically laying a line of conveyors from one machine's output to another machine's input.
+
+Conveyors are radius 1, so the centers of a connected line of them are always 2 apart; the search runs over those centers, starting right in front of the source output and ending right behind the target input. Each step places one conveyor, pointing toward the next one. A placement is only allowed if it doesn't overlap anything already in the region, stays inside the module (if the route is inside one), and doesn't leave an unused conveyor input right where some existing machine outputs, which would silently merge another flow into the route.
+
+Routes use as few conveyors as possible. If asked to prefer straight runs, ties are broken by the number of turns, which never makes the route longer. Different directions through the same cell are different search nodes, so a search that only remembers the cheapest way to each node could find routes that cross themselves, and skipping those would lose routes that only need to avoid themselves. So the search has two passes. First, Dijkstra's algorithm runs backward from the target over the nodes, ignoring crossings, which gives a lower bound on the cost of finishing from each node. Then A* runs forward over whole routes, each of which skips its own cells. A partial route is dropped if one that got to the same node at most as expensively used only cells it also uses, since that one can continue any way it could. To keep the search bounded, at most `MAX_ROUTES_THROUGH_NODE` partial routes continue from each node; that limit only comes into play when many different routes reach the same node, which in practice means the cheapest way would cross itself, and in rare cases it can make the route longer than necessary.
+
+The result is an `AddRemoveMachines` with global positions, so the frontend can show it as hovering machines before committing it.
+
+*/
+use crate::geometry::{Facing, GridIsomorphism, Number, Rotate, Vector, VectorExtension};
+use crate::machine_data::{
+  Game, GlobalMachine, InputLocation, MachineState, MachineTypeId, MachineTypeRef,
+  MachineTypeTrait, PlatonicMachine,
+};
+use crate::placement::smallest_region_containing;
+use crate::undo_history::AddRemoveMachines;
+use serde::{Deserialize, Serialize};
+use std::cmp::{max, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Debug)]
+pub struct ConveyorRouteOptions {
+  /// Among the routes with the fewest conveyors, pick one with the fewest turns.
+  pub prefer_straight_runs: bool,
+  /// Give up rather than return a route with more conveyors than this.
+  pub max_conveyors: usize,
+}
+
+impl Default for ConveyorRouteOptions {
+  fn default() -> Self {
+    ConveyorRouteOptions {
+      prefer_straight_runs: true,
+      max_conveyors: 200,
+    }
+  }
+}
+
+type Cell = (Number, Number);
+/// The direction materials are moving in, as a unit vector.
+type Direction = (Number, Number);
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+enum Node {
+  /// About to place a conveyor at `Cell`, which materials will enter moving in `Direction`.
+  At(Cell, Direction),
+  Done,
+}
+
+/// (conveyors placed, turns taken)
+type Cost = (usize, usize);
+
+/// How many different partial routes to continue from the same node, to bound the search.
+const MAX_ROUTES_THROUGH_NODE: usize = 8;
+
+/// A partial route, stored as its last node plus the partial route it extends and the direction of the conveyor placed in between.
+struct RouteStep {
+  node: Node,
+  previous: Option<(usize, Direction)>,
+}
+
+fn cell(position: Vector) -> Cell {
+  (position[0], position[1])
+}
+fn vector((x, y): (Number, Number)) -> Vector {
+  Vector::new(x, y)
+}
+fn direction(facing: Facing) -> Direction {
+  cell(facing.unit_vector())
+}
+fn directions() -> impl Iterator<Item = Direction> {
+  (0..4).map(|quarter_turns| direction(Facing::default().rotate_90(quarter_turns)))
+}
+
+struct RouteSearch<'a> {
+  conveyor: MachineTypeRef<'a>,
+  conveyor_facing: Facing,
+  obstacles: Vec<(Vector, Number)>,
+  existing_outputs: HashSet<InputLocation>,
+  /// If the route is inside a module: the module's center and inner radius.
+  bounds: Option<(Vector, Number)>,
+}
+
+impl<'a> RouteSearch<'a> {
+  fn isomorphism(&self, center: Cell, out: Direction) -> GridIsomorphism {
+    GridIsomorphism {
+      translation: vector(center),
+      ..Default::default()
+    }
+    .with_rotation_changed_to_make_facing_transform_to(
+      self.conveyor_facing,
+      vector(out).exact_facing().unwrap(),
+    )
+  }
+
+  /// Whether a conveyor can go at `center`, taking materials moving in `incoming` and sending them out in `out`.
+  fn allowed(&self, center: Cell, incoming: Direction, out: Direction) -> bool {
+    let center_vector = vector(center);
+    let radius = self.conveyor.radius();
+    if let Some((module_center, inner_radius)) = self.bounds {
+      let offset = center_vector - module_center;
+      if max(offset[0].abs(), offset[1].abs()) + radius > inner_radius {
+        return false;
+      }
+    }
+    if self.obstacles.iter().any(|&(position, obstacle_radius)| {
+      let offset = center_vector - position;
+      offset[0].abs() < radius + obstacle_radius && offset[1].abs() < radius + obstacle_radius
+    }) {
+      return false;
+    }
+
+    let isomorphism = self.isomorphism(center, out);
+    let mut outputs = self.conveyor.output_locations(isomorphism);
+    if outputs
+      .next()
+      .map(|location| (cell(location.position), direction(location.facing)))
+      != Some((cell(center_vector + vector(out)), out))
+    {
+      return false;
+    }
+    let used_input = InputLocation {
+      position: center_vector - vector(incoming),
+      facing: vector(incoming).exact_facing().unwrap(),
+    };
+    let mut found_used_input = false;
+    for input in self.conveyor.input_locations(isomorphism) {
+      if input == used_input {
+        found_used_input = true;
+      } else if self.existing_outputs.contains(&input) {
+        return false;
+      }
+    }
+    found_used_input
+  }
+}
+
+impl Game {
+  /// A line of conveyors of type `conveyor` that carries materials from the output location `from` to the input location `to`, both in global coordinates.
+  ///
+  /// The conveyors are listed in `added` in the order materials pass through them. Returns `None` if there's no route within `options.max_conveyors`, or if `from` and `to` aren't in the same region. If `from` is already the same location as `to`, the route is empty.
+  ///
+  /// `conveyor` should be shaped like `primitive_machines::conveyor()`: a single output, with inputs at the back and (to be able to turn) the sides.
+  pub fn route_conveyors(
+    &self,
+    conveyor: MachineTypeId,
+    from: InputLocation,
+    to: InputLocation,
+    options: &ConveyorRouteOptions,
+  ) -> Option<AddRemoveMachines> {
+    if from == to {
+      return Some(AddRemoveMachines {
+        added: Vec::new(),
+        removed: Vec::new(),
+      });
+    }
+
+    let conveyor_type = self.machine_types.get(conveyor);
+    let conveyor_facing = conveyor_type.relative_output_locations().first()?.facing;
+    let radius = conveyor_type.radius();
+    let start = from.position + from.facing.unit_vector() * radius;
+    let end = to.position - to.facing.unit_vector() * radius;
+    if (start - end).map(|coordinate| coordinate.rem_euclid(2 * radius)) != Vector::new(0, 0) {
+      return None;
+    }
+
+    let region_at = |center: Vector| {
+      smallest_region_containing(
+        &self.machine_types,
+        &self.global_region,
+        GridIsomorphism::default(),
+        GridIsomorphism {
+          translation: center,
+          ..Default::default()
+        },
+        radius,
+      )
+    };
+    let (region, region_isomorphism, module) = region_at(start);
+    let (end_region, end_isomorphism, _) = region_at(end);
+    if !std::ptr::eq(region, end_region) || region_isomorphism != end_isomorphism {
+      return None;
+    }
+
+    let search = RouteSearch {
+      conveyor: conveyor_type,
+      conveyor_facing,
+      obstacles: region
+        .machines
+        .iter()
+        .map(|machine| {
+          (
+            (machine.state.position * region_isomorphism).translation,
+            self.machine_types.get(machine.type_id).radius(),
+          )
+        })
+        .collect(),
+      existing_outputs: region
+        .machines
+        .iter()
+        .flat_map(|machine| {
+          self
+            .machine_types
+            .get(machine.type_id)
+            .output_locations(machine.state.position * region_isomorphism)
+        })
+        .collect(),
+      bounds: module.map(|module| {
+        (
+          region_isomorphism.translation,
+          module.module_type.inner_radius,
+        )
+      }),
+    };
+
+    let start_node = Node::At(cell(start), direction(from.facing));
+    let end_cell = cell(end);
+    let end_direction = direction(to.facing);
+    let step = |center: Cell, out: Direction| {
+      if center == end_cell && out == end_direction {
+        Node::Done
+      } else {
+        Node::At(
+          (center.0 + out.0 * 2 * radius, center.1 + out.1 * 2 * radius),
+          out,
+        )
+      }
+    };
+    let step_cost = |incoming: Direction, out: Direction| -> Cost {
+      (
+        1,
+        (options.prefer_straight_runs && out != incoming) as usize,
+      )
+    };
+
+    // Search backward from the target first, ignoring self-crossing, to learn how much it costs at least to finish a route from each node.
+    let mut remaining: HashMap<Node, Cost> = HashMap::new();
+    let mut backward = BinaryHeap::new();
+    backward.push(Reverse(((0, 0), Node::Done)));
+    while let Some(Reverse((cost, node))) = backward.pop() {
+      if remaining.contains_key(&node) {
+        continue;
+      }
+      remaining.insert(node, cost);
+      if node == start_node {
+        break;
+      }
+      if cost.0 >= options.max_conveyors {
+        continue;
+      }
+      let (center, out) = match node {
+        Node::At(next_center, out) => (
+          (
+            next_center.0 - out.0 * 2 * radius,
+            next_center.1 - out.1 * 2 * radius,
+          ),
+          out,
+        ),
+        Node::Done => (end_cell, end_direction),
+      };
+      // pointing into the target from right behind it always finishes the route instead
+      if step(center, out) != node {
+        continue;
+      }
+      for incoming in directions() {
+        if incoming != (-out.0, -out.1) && search.allowed(center, incoming, out) {
+          let previous_cost = step_cost(incoming, out);
+          backward.push(Reverse((
+            (cost.0 + previous_cost.0, cost.1 + previous_cost.1),
+            Node::At(center, incoming),
+          )));
+        }
+      }
+    }
+    if !remaining.contains_key(&start_node) {
+      return None;
+    }
+    // every node the backward search didn't reach costs at least as much as the cheapest one it was about to look at
+    let unexplored = backward.peek().map(|&Reverse((cost, _))| cost);
+    let estimate = |cost: Cost, node: Node| {
+      let rest = remaining.get(&node).copied().or(unexplored)?;
+      let total = (cost.0 + rest.0, cost.1 + rest.1);
+      if total.0 > options.max_conveyors {
+        None
+      } else {
+        Some(total)
+      }
+    };
+
+    // Then search forward over whole routes, so that each one can avoid its own cells. The estimates never overshoot, so the first finished route is a cheapest one; among equal estimates, the longest partial route goes first, which usually heads straight for the target.
+    let mut steps = vec![RouteStep {
+      node: start_node,
+      previous: None,
+    }];
+    let mut frontier = BinaryHeap::new();
+    frontier.push(Reverse((remaining[&start_node], Reverse((0, 0)), 0)));
+    let mut expanded: HashMap<Node, Vec<(Cost, HashSet<Cell>)>> = HashMap::new();
+    while let Some(Reverse((_, Reverse(cost), index))) = frontier.pop() {
+      let (center, incoming) = match steps[index].node {
+        Node::At(center, incoming) => (center, incoming),
+        Node::Done => {
+          let mut added = Vec::with_capacity(cost.0);
+          let mut index = index;
+          while let Some((previous, out)) = steps[index].previous {
+            if let Node::At(center, _) = steps[previous].node {
+              added.push(GlobalMachine(PlatonicMachine {
+                type_id: conveyor,
+                state: MachineState {
+                  position: search.isomorphism(center, out),
+                  parameters: Default::default(),
+                },
+              }));
+            }
+            index = previous;
+          }
+          added.reverse();
+          return Some(AddRemoveMachines {
+            added,
+            removed: Vec::new(),
+          });
+        }
+      };
+      let mut route_cells = HashSet::new();
+      let mut previous = Some(index);
+      while let Some(visited) = previous {
+        if let Node::At(visited_center, _) = steps[visited].node {
+          route_cells.insert(visited_center);
+        }
+        previous = steps[visited].previous.map(|(before, _)| before);
+      }
+      // a route that got here at most as expensively, through only cells this one also uses, can continue any way this one could
+      let earlier = expanded.entry(steps[index].node).or_default();
+      if earlier.len() >= MAX_ROUTES_THROUGH_NODE
+        || earlier.iter().any(|(earlier_cost, earlier_cells)| {
+          *earlier_cost <= cost && earlier_cells.is_subset(&route_cells)
+        })
+      {
+        continue;
+      }
+      earlier.push((cost, route_cells.clone()));
+
+      for out in directions() {
+        if out == (-incoming.0, -incoming.1) || !search.allowed(center, incoming, out) {
+          continue;
+        }
+        let next_node = step(center, out);
+        if let Node::At(next_center, _) = next_node {
+          if route_cells.contains(&next_center) {
+            continue;
+          }
+        }
+        let added_cost = step_cost(incoming, out);
+        let next_cost = (cost.0 + added_cost.0, cost.1 + added_cost.1);
+        if let Some(estimate) = estimate(next_cost, next_node) {
+          steps.push(RouteStep {
+            node: next_node,
+            previous: Some((index, out)),
+          });
+          frontier.push(Reverse((estimate, Reverse(next_cost), steps.len() - 1)));
+        }
+      }
+    }
+    None
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::machine_data::{MachineType, Material};
+  use crate::placement::PlacementProblem;
+  use crate::primitive_machines::{conveyor, iron_mine, iron_smelter};
+
+  fn at(x: Number, y: Number) -> GridIsomorphism {
+    GridIsomorphism {
+      translation: Vector::new(x, y),
+      ..Default::default()
+    }
+  }
+
+  /// A mine at the origin and a smelter at `smelter`; preset 0 is the conveyor.
+  fn game(smelter: GridIsomorphism, mut obstacles: Vec<PlatonicMachine>) -> Game {
+    obstacles.push(PlatonicMachine::new(MachineTypeId::Preset(1), at(0, 0)));
+    obstacles.push(PlatonicMachine::new(MachineTypeId::Preset(2), smelter));
+    let mut game = Game::new(vec![conveyor(), iron_mine(), iron_smelter()], obstacles);
+    game.canonicalize();
+    game
+  }
+
+  fn route(game: &Game, smelter: GridIsomorphism) -> Option<AddRemoveMachines> {
+    let machine_types = &game.machine_types;
+    let from = machine_types
+      .get(MachineTypeId::Preset(1))
+      .output_locations(at(0, 0))
+      .next()
+      .unwrap();
+    let to = machine_types
+      .get(MachineTypeId::Preset(2))
+      .input_locations(smelter)
+      .next()
+      .unwrap();
+    game.route_conveyors(
+      MachineTypeId::Preset(0),
+      from,
+      to,
+      &ConveyorRouteOptions::default(),
+    )
+  }
+
+  /// Build the route for real, and check that it's legal and actually delivers ore to the smelter.
+  fn assert_route_works(mut game: Game, route: &AddRemoveMachines) {
+    let future = game.future();
+    for added in &route.added {
+      let problems = game.placement_problems(&future, 0, added.type_id, added.state.position);
+      assert!(
+        problems
+          .iter()
+          .all(|problem| matches!(problem, PlacementProblem::InsufficientInventory { .. })),
+        "{:?}",
+        problems
+      );
+    }
+    game
+      .global_region
+      .machines
+      .extend(route.added.iter().map(|machine| machine.0.clone()));
+    game.canonicalize();
+    assert!(game
+      .future()
+      .global_region
+      .dumped
+      .iter()
+      .any(|(_, flow)| flow.material == Material::Iron));
+  }
+
+  fn num_turns(route: &AddRemoveMachines, initial: Facing) -> usize {
+    let mut facing = initial;
+    let mut turns = 0;
+    for machine in &route.added {
+      let out = conveyor()
+        .as_ref()
+        .output_locations(machine.state.position)
+        .next()
+        .unwrap()
+        .facing;
+      if out != facing {
+        turns += 1;
+      }
+      facing = out;
+    }
+    turns
+  }
+
+  #[test]
+  fn straight_route_is_a_straight_line() {
+    let smelter = at(16, 0);
+    let game = game(smelter, Vec::new());
+    let route = route(&game, smelter).unwrap();
+    assert_eq!(route.added.len(), 5);
+    assert_eq!(num_turns(&route, Facing::default()), 0);
+    assert_route_works(game, &route);
+  }
+
+  #[test]
+  fn route_with_a_bend_prefers_few_turns() {
+    let smelter = at(16, 10);
+    let game = game(smelter, Vec::new());
+    let route = route(&game, smelter).unwrap();
+    // 4 steps right and 5 steps up, plus the first conveyor
+    assert_eq!(route.added.len(), 10);
+    assert_eq!(num_turns(&route, Facing::default()), 2);
+    assert_route_works(game, &route);
+  }
+
+  #[test]
+  fn route_goes_around_obstacles_without_picking_up_their_outputs() {
+    let smelter = at(16, 0);
+    // a mine in the way, with its output pointing up
+    let obstacle = at(8, 0).with_rotation_changed_to_make_facing_transform_to(
+      Facing::default(),
+      Vector::new(0, 1).exact_facing().unwrap(),
+    );
+    let game = game(
+      smelter,
+      vec![PlatonicMachine::new(MachineTypeId::Preset(1), obstacle)],
+    );
+    let route = route(&game, smelter).unwrap();
+    assert!(route.added.len() > 5);
+    assert!(route
+      .added
+      .iter()
+      .all(|machine| machine.state.position.translation[1] <= 0));
+    assert_route_works(game, &route);
+  }
+
+  /// A conveyor that can only turn left, so getting to the smelter's input often means looping around.
+  fn use_left_only_conveyors(game: &mut Game) {
+    let mut left_only = match conveyor() {
+      MachineType::Distributor(distributor) => distributor,
+      _ => unreachable!(),
+    };
+    left_only.inputs = inputs![InputLocation::input(-1, 0), InputLocation::input(0, 1)];
+    game.machine_types.presets[0] = MachineType::Distributor(left_only);
+  }
+
+  fn assert_no_crossings(route: &AddRemoveMachines) {
+    let cells: HashSet<Cell> = route
+      .added
+      .iter()
+      .map(|machine| cell(machine.state.position.translation))
+      .collect();
+    assert_eq!(cells.len(), route.added.len());
+  }
+
+  #[test]
+  fn route_never_crosses_itself() {
+    let smelter = at(16, -10);
+    let mut game = game(smelter, Vec::new());
+    // the cheapest way to start heading down would be to loop over the first conveyor
+    use_left_only_conveyors(&mut game);
+    let route = route(&game, smelter).unwrap();
+    assert_no_crossings(&route);
+    assert_route_works(game, &route);
+  }
+
+  #[test]
+  fn route_finds_the_shortest_detour_next_to_itself() {
+    let smelter = at(18, 4);
+    // Without the obstacle, the route goes right past the smelter, up to y=8, and back left and down into it. The obstacle blocks the way down at x=14, so the route has to go up to y=12 and come down at x=12 instead, right next to cells it used on the way; a search that only remembers the cheapest way to each node loses that route.
+    let mut game = game(
+      smelter,
+      vec![PlatonicMachine::new(MachineTypeId::Preset(0), at(15, 9))],
+    );
+    use_left_only_conveyors(&mut game);
+    let route = route(&game, smelter).unwrap();
+    // 11 to the right, 6 up, 6 to the left, 4 down, and 1 to turn into the smelter
+    assert_eq!(route.added.len(), 28);
+    assert_no_crossings(&route);
+    assert_route_works(game, &route);
+  }
+
+  #[test]
+  fn unreachable_targets_have_no_route() {
+    let smelter = at(16, 0);
+    let game = game(smelter, Vec::new());
+    let options = ConveyorRouteOptions {
+      max_conveyors: 3,
+      ..Default::default()
+    };
+    let from = InputLocation::output(3, 0);
+    let to = InputLocation::input(13, 0);
+    assert_eq!(
+      game.route_conveyors(MachineTypeId::Preset(0), from, to, &options),
+      None
+    );
+    // the wrong parity, so conveyors can't line up with it
+    let to = InputLocation {
+      position: Vector::new(13, 1),
+      facing: Facing::default(),
+    };
+    assert_eq!(
+      game.route_conveyors(
+        MachineTypeId::Preset(0),
+        from,
+        to,
+        &ConveyorRouteOptions::default()
+      ),
+      None
+    );
+  }
+}
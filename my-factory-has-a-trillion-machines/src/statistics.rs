@@ -0,0 +1,440 @@
+/**
+
+Production statistics: how much of each material is produced, consumed and dumped over time.
+
+Everything here is computed exactly from the GameFuture, rather than sampled, so the numbers are correct no matter how long the time buckets are. Assemblers are the only machines that create or destroy materials, so "produced" and "consumed" count assembler outputs and inputs, including assemblers inside modules (once for each instance of the module). "Dumped" counts materials leaving an output that isn't connected to anything. In the global region, those go to the player's inventory; inside a module, they are lost, except at the module's own enabled outputs, which aren't counted because the materials just continue into the containing region.
+
+Only times after `Game::last_change_time` are counted, because the future doesn't describe anything that happened before the last change.
+
+*/
+use crate::flow_pattern::{FlowCollection, RATE_DIVISOR};
+use crate::geometry::Number;
+use crate::graph_algorithms::{GameFuture, RegionFuture};
+use crate::machine_data::{
+  Game, InputLocation, MachineFuture, MachineTypeId, MachineTypeRef, Material,
+  PlatonicRegionContents,
+};
+use crate::modules::{CanonicalModuleInputs, ModuleParameterValues, PlatonicModule};
+use crate::primitive_machines::{Assembler, AssemblerFuture};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Debug, Default)]
+pub struct MaterialCounts {
+  pub produced: Number,
+  pub consumed: Number,
+  pub dumped: Number,
+}
+
+/// Average rates over a bucket, in materials per RATE_DIVISOR time units (the same units as `FlowRate`).
+#[derive(Copy, Clone, PartialEq, Serialize, Deserialize, Debug, Default)]
+pub struct MaterialRates {
+  pub produced: f64,
+  pub consumed: f64,
+  pub dumped: f64,
+}
+
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Debug)]
+pub struct StatisticsBucket {
+  /// The half-open time range start <= t < end.
+  pub range: [Number; 2],
+  pub materials: HashMap<Material, MaterialCounts>,
+}
+
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Debug)]
+pub struct ProductionStatistics {
+  pub buckets: Vec<StatisticsBucket>,
+}
+
+impl MaterialCounts {
+  fn add(&mut self, other: MaterialCounts) {
+    self.produced += other.produced;
+    self.consumed += other.consumed;
+    self.dumped += other.dumped;
+  }
+}
+
+impl StatisticsBucket {
+  pub fn duration(&self) -> Number {
+    self.range[1] - self.range[0]
+  }
+  pub fn counts(&self, material: Material) -> MaterialCounts {
+    self.materials.get(&material).copied().unwrap_or_default()
+  }
+  pub fn rates(&self, material: Material) -> MaterialRates {
+    let counts = self.counts(material);
+    let duration = self.duration();
+    if duration <= 0 {
+      return MaterialRates::default();
+    }
+    let scale = RATE_DIVISOR as f64 / duration as f64;
+    MaterialRates {
+      produced: counts.produced as f64 * scale,
+      consumed: counts.consumed as f64 * scale,
+      dumped: counts.dumped as f64 * scale,
+    }
+  }
+}
+
+impl ProductionStatistics {
+  pub fn total(&self, material: Material) -> MaterialCounts {
+    let mut result = MaterialCounts::default();
+    for bucket in &self.buckets {
+      result.add(bucket.counts(material));
+    }
+    result
+  }
+}
+
+type BucketCounts = Vec<HashMap<Material, MaterialCounts>>;
+
+struct StatisticsBuilder<'a> {
+  game: &'a Game,
+  future: &'a GameFuture,
+  /// absolute time ranges, already clipped to start no earlier than the last change
+  ranges: Vec<[Number; 2]>,
+  /// Module instances with the same type, parameters, inputs and start time behave identically, so we only need to count them once.
+  undisturbed_module_counts: HashMap<
+    (
+      MachineTypeId,
+      ModuleParameterValues,
+      CanonicalModuleInputs,
+      Number,
+    ),
+    BucketCounts,
+  >,
+}
+
+impl<'a> StatisticsBuilder<'a> {
+  fn empty_counts(&self) -> BucketCounts {
+    vec![HashMap::new(); self.ranges.len()]
+  }
+
+  fn add_flow(
+    &self,
+    counts: &mut BucketCounts,
+    material: Material,
+    flow: &impl FlowCollection,
+    region_start_time: Number,
+    multiplier: Number,
+    field: fn(&mut MaterialCounts) -> &mut Number,
+  ) {
+    for (range, bucket) in self.ranges.iter().zip(counts) {
+      let amount = flow
+        .num_disbursed_between([range[0] - region_start_time, range[1] - region_start_time])
+        * multiplier;
+      if amount != 0 {
+        *field(bucket.entry(material).or_default()) += amount;
+      }
+    }
+  }
+
+  fn add_assembler(
+    &self,
+    counts: &mut BucketCounts,
+    assembler: &Assembler,
+    future: &AssemblerFuture,
+    region_start_time: Number,
+  ) {
+    let assembly_starts = future.assembly_start_pattern();
+    for input in &assembler.inputs {
+      self.add_flow(
+        counts,
+        input.material,
+        &assembly_starts,
+        region_start_time,
+        input.cost,
+        |c| &mut c.consumed,
+      );
+    }
+    for (output, pattern) in assembler.outputs.iter().zip(future.output_patterns()) {
+      self.add_flow(
+        counts,
+        output.material,
+        pattern,
+        region_start_time,
+        1,
+        |c| &mut c.produced,
+      );
+    }
+  }
+
+  /// `containing_module` is the module whose inner region this is, with the instance's parameter values.
+  fn region_counts(
+    &mut self,
+    region: &PlatonicRegionContents,
+    containing_module: Option<(&PlatonicModule, &ModuleParameterValues)>,
+    region_future: &RegionFuture,
+    region_start_time: Number,
+    passed_outward: &[InputLocation],
+  ) -> BucketCounts {
+    let mut counts = self.empty_counts();
+
+    for (location, material_flow) in &region_future.dumped {
+      if !passed_outward.contains(location) {
+        self.add_flow(
+          &mut counts,
+          material_flow.material,
+          &material_flow.flow,
+          region_start_time,
+          1,
+          |c| &mut c.dumped,
+        );
+      }
+    }
+
+    for (machine, machine_future) in region.machines.iter().zip(&region_future.machines) {
+      let type_id = match containing_module {
+        Some((module, parameters)) => module.parameterized_type_id(parameters, machine),
+        None => machine.type_id,
+      };
+      match (self.game.machine_types.get(type_id), &machine_future.future) {
+        (MachineTypeRef::Assembler(assembler), Ok(MachineFuture::Assembler(future))) => {
+          self.add_assembler(&mut counts, assembler, future, region_start_time);
+        }
+        (MachineTypeRef::Module(module), Ok(MachineFuture::Module(module_future))) => {
+          let inner_start_time = region_start_time + module_future.start_time;
+          let parameters = &module_future.parameters;
+          let outputs: Vec<InputLocation> = module
+            .module_type
+            .outputs
+            .iter()
+            .enumerate()
+            .filter(|&(index, _)| module.output_enabled(parameters, index))
+            .map(|(_, output)| output.inner_location)
+            .collect();
+          let inner_module = Some((module, parameters));
+          let inner_counts = match region_future
+            .disturbed_children
+            .get(&machine.id_within_region())
+          {
+            Some(inner_future) => self.region_counts(
+              &module.region,
+              inner_module,
+              inner_future,
+              inner_start_time,
+              &outputs,
+            ),
+            None => {
+              let key = (
+                machine.type_id,
+                parameters.clone(),
+                module_future.canonical_inputs.clone(),
+                inner_start_time,
+              );
+              match self.undisturbed_module_counts.get(&key) {
+                Some(inner_counts) => inner_counts.clone(),
+                None => {
+                  let future = self.future;
+                  let inner_future = future
+                    .undisturbed_modules
+                    .get(&(machine.type_id, parameters.clone()))
+                    .and_then(|futures| futures.get(&module_future.canonical_inputs))
+                    .expect("undisturbed module should have a future for its canonical inputs");
+                  let inner_counts = self.region_counts(
+                    &module.region,
+                    inner_module,
+                    inner_future,
+                    inner_start_time,
+                    &outputs,
+                  );
+                  self
+                    .undisturbed_module_counts
+                    .insert(key, inner_counts.clone());
+                  inner_counts
+                }
+              }
+            }
+          };
+          for (bucket, inner_bucket) in counts.iter_mut().zip(inner_counts) {
+            for (material, material_counts) in inner_bucket {
+              bucket.entry(material).or_default().add(material_counts);
+            }
+          }
+        }
+        _ => {}
+      }
+    }
+
+    counts
+  }
+}
+
+impl Game {
+  /// Count what happens in `num_buckets` consecutive buckets of `bucket_duration`, starting at `start_time`.
+  ///
+  /// `future` must be the future of this game, as returned by `Game::future()`.
+  pub fn production_statistics(
+    &self,
+    future: &GameFuture,
+    start_time: Number,
+    bucket_duration: Number,
+    num_buckets: usize,
+  ) -> ProductionStatistics {
+    let ranges: Vec<[Number; 2]> = (0..num_buckets as Number)
+      .map(|index| {
+        let start = start_time + index * bucket_duration;
+        [start, start + bucket_duration]
+      })
+      .collect();
+    let clipped_ranges = ranges
+      .iter()
+      .map(|range| {
+        [
+          range[0].max(self.last_change_time),
+          range[1].max(self.last_change_time),
+        ]
+      })
+      .collect();
+    let mut builder = StatisticsBuilder {
+      game: self,
+      future,
+      ranges: clipped_ranges,
+      undisturbed_module_counts: HashMap::new(),
+    };
+    let counts = builder.region_counts(&self.global_region, None, &future.global_region, 0, &[]);
+    ProductionStatistics {
+      buckets: ranges
+        .into_iter()
+        .zip(counts)
+        .map(|(range, materials)| StatisticsBucket { range, materials })
+        .collect(),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::machine_data::{
+    MachineType, PlatonicMachine, WorldMachinesMap, TIME_TO_MOVE_MATERIAL,
+  };
+  use crate::modules::{basic_module, ModuleParameter};
+  use crate::primitive_machines::{iron_mine, iron_smelter, AssemblerOutput};
+
+  fn mine_and_smelter() -> Game {
+    Game::new(
+      vec![iron_mine(), iron_smelter()],
+      vec![
+        PlatonicMachine::for_tests(0, 0, 0),
+        PlatonicMachine::for_tests(1, 6, 0),
+      ],
+    )
+  }
+
+  #[test]
+  fn smelter_consumes_ore_and_dumps_iron() {
+    let game = mine_and_smelter();
+    let future = game.future();
+    let statistics = game.production_statistics(&future, 0, 100 * TIME_TO_MOVE_MATERIAL, 10);
+    let ore = statistics.total(Material::IronOre);
+    let iron = statistics.total(Material::Iron);
+    assert!(ore.produced > 0);
+    assert!(ore.consumed > 0);
+    assert!(ore.consumed <= ore.produced);
+    assert_eq!(ore.dumped, 0);
+    assert!(iron.produced > 0);
+    assert_eq!(iron.consumed, 0);
+    assert_eq!(iron.dumped, iron.produced);
+
+    for bucket in &statistics.buckets {
+      let iron = bucket.counts(Material::Iron);
+      assert_eq!(iron.dumped, iron.produced);
+    }
+  }
+
+  #[test]
+  fn statistics_ignore_time_before_last_change() {
+    let mut game = mine_and_smelter();
+    game.last_change_time = 1000 * TIME_TO_MOVE_MATERIAL;
+    let future = game.future();
+    let statistics = game.production_statistics(&future, 0, 100 * TIME_TO_MOVE_MATERIAL, 5);
+    assert_eq!(
+      statistics.total(Material::IronOre),
+      MaterialCounts::default()
+    );
+  }
+
+  /// Module instances at (0, 50 * index), each containing a mine connected to output 0. Parameter 0 switches the mine to one that produces iron directly, and parameter 1 disables output 0, so the mine's products are lost inside the module.
+  fn mines_in_modules(instance_parameters: &[&[usize]]) -> Game {
+    let mut module = match basic_module() {
+      MachineType::Module(module) => module,
+      _ => unreachable!(),
+    };
+    module
+      .region
+      .machines
+      .push(PlatonicMachine::for_tests(0, 15, -3));
+    module.parameters = vec![
+      ModuleParameter::MachineChoice {
+        machine: (15, -3),
+        alternatives: vec![MachineTypeId::Preset(2)],
+      },
+      ModuleParameter::OutputDisabled { output: 0 },
+    ];
+    let mut iron_source = match iron_mine() {
+      MachineType::Assembler(assembler) => assembler,
+      _ => unreachable!(),
+    };
+    iron_source.outputs = inputs![AssemblerOutput::new(3, 0, Material::Iron, 1)];
+
+    let instances = instance_parameters
+      .iter()
+      .enumerate()
+      .map(|(index, parameters)| {
+        let mut instance = PlatonicMachine::for_tests(1, 0, index as Number * 50);
+        for (index, &value) in parameters.iter().enumerate() {
+          instance.state.parameters.set(index, value);
+        }
+        instance
+      })
+      .collect();
+    let mut game = Game::new(
+      vec![
+        iron_mine(),
+        MachineType::Module(module),
+        MachineType::Assembler(iron_source),
+      ],
+      instances,
+    );
+    game.canonicalize();
+    game
+  }
+
+  #[test]
+  fn module_instances_are_each_counted() {
+    // Two identical instances (the second one reuses the first one's counts), an instance of each parameter, one whose inner mine was disturbed, and one that was only just built.
+    let mut game = mines_in_modules(&[&[], &[], &[1], &[0, 1], &[], &[]]);
+    let built_time = 20 * TIME_TO_MOVE_MATERIAL;
+    game.last_change_time = built_time;
+    let mut disturbed_inside = WorldMachinesMap::default();
+    disturbed_inside.here.insert((15, -3), 0);
+    game
+      .last_disturbed_times
+      .children
+      .insert((0, 200), disturbed_inside);
+    game.last_disturbed_times.here.insert((0, 250), built_time);
+    let future = game.future();
+    assert_eq!(future.global_region.disturbed_children.len(), 1);
+
+    // Every mine makes one material per TIME_TO_MOVE_MATERIAL, so 60 per bucket. Five of them make ore, and all of it is dumped, either into the inventory or (for the instance with its output disabled) inside the module. The instance that was only just built makes its first ore a few TIME_TO_MOVE_MATERIAL after that, so it makes 3 fewer in the first bucket, and 1 more of those is still on its way out of the module.
+    let statistics = game.production_statistics(&future, built_time, 60 * TIME_TO_MOVE_MATERIAL, 3);
+    let counts = |produced, dumped| MaterialCounts {
+      produced,
+      consumed: 0,
+      dumped,
+    };
+    let ore_counts: Vec<MaterialCounts> = statistics
+      .buckets
+      .iter()
+      .map(|bucket| bucket.counts(Material::IronOre))
+      .collect();
+    assert_eq!(
+      ore_counts,
+      vec![counts(297, 296), counts(300, 300), counts(300, 300)]
+    );
+    for bucket in &statistics.buckets {
+      assert_eq!(bucket.counts(Material::Iron), counts(60, 60));
+    }
+  }
+}
@@ -0,0 +1,285 @@
+/**
+
+Rendering a factory as an SVG document, for design docs and bug reports.
+
+This walks the regions the same way the web frontend draws them, but doesn't need a browser: it just produces a string. Each machine gets a rounded outline and its icon, larger machines get arrows at their inputs and outputs, and if a time is given, the materials moving through the factory at that time are drawn too.
+
+To draw a saved session from the command line, use `cargo run --bin export_svg`.
+
+World coordinates are drawn with +y pointing up, like the map in the frontend, so the SVG coordinates are flipped vertically.
+
+*/
+use crate::geometry::{GridIsomorphism, Number, VectorExtension};
+use crate::graph_algorithms::{BaseAspect, FutureAspect, GameFuture, GameView, WorldRegionView};
+use crate::machine_data::{Game, InputLocation, MachineTypeTrait};
+use nalgebra::Vector2;
+use std::fmt::Write;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct SvgExportOptions {
+  /// If set, draw the materials that are moving through the factory at this time.
+  pub time: Option<Number>,
+  /// How many levels of modules to draw the insides of. `Some(0)` draws modules only as closed boxes.
+  pub max_module_depth: Option<usize>,
+  /// SVG user units per world unit.
+  pub scale: f64,
+  /// If set, icons are drawn as `<image>` elements with `href` set to this prefix, followed by the icon name and ".png". Otherwise, they are drawn as text labels.
+  pub icon_url_prefix: Option<String>,
+}
+
+impl Default for SvgExportOptions {
+  fn default() -> Self {
+    SvgExportOptions {
+      time: None,
+      max_module_depth: None,
+      scale: 10.0,
+      icon_url_prefix: None,
+    }
+  }
+}
+
+type ExportView<'a> = WorldRegionView<'a, (BaseAspect, FutureAspect)>;
+
+struct SvgWriter<'a> {
+  options: &'a SvgExportOptions,
+  body: String,
+  bounds: Option<[Vector2<f64>; 2]>,
+}
+
+impl<'a> SvgWriter<'a> {
+  fn point(&self, position: Vector2<f64>) -> Vector2<f64> {
+    Vector2::new(position[0], -position[1]) * self.options.scale
+  }
+
+  fn include_in_bounds(&mut self, center: Vector2<f64>, half_extent: f64) {
+    let extent = Vector2::new(half_extent, half_extent);
+    let [min, max] = self.bounds.get_or_insert([center, center]);
+    *min = min.zip_map(&(center - extent), f64::min);
+    *max = max.zip_map(&(center + extent), f64::max);
+  }
+
+  fn icon(&mut self, icon: &str, center: Vector2<f64>, half_size: f64, degrees: f64) {
+    let point = self.point(center);
+    let size = half_size * 2.0 * self.options.scale;
+    match &self.options.icon_url_prefix {
+      Some(prefix) => {
+        writeln!(
+          self.body,
+          r#"<image href="{prefix}{icon}.png" x="{x}" y="{y}" width="{size}" height="{size}" transform="rotate({degrees} {cx} {cy})"/>"#,
+          prefix = escape(prefix),
+          icon = escape(icon),
+          x = point[0] - size / 2.0,
+          y = point[1] - size / 2.0,
+          size = size,
+          degrees = degrees,
+          cx = point[0],
+          cy = point[1],
+        )
+        .unwrap();
+      }
+      None => {
+        writeln!(
+          self.body,
+          r#"<text class="icon" x="{x}" y="{y}" font-size="{font_size}" text-anchor="middle" dominant-baseline="central">{icon}</text>"#,
+          x = point[0],
+          y = point[1],
+          font_size = size / 3.0,
+          icon = escape(icon),
+        )
+        .unwrap();
+      }
+    }
+  }
+
+  fn outline(&mut self, center: Vector2<f64>, half_size: f64) {
+    let point = self.point(center);
+    let size = half_size * 2.0 * self.options.scale;
+    writeln!(
+      self.body,
+      r#"<rect class="machine" x="{x}" y="{y}" width="{size}" height="{size}" rx="{corner}" fill="none" stroke="black"/>"#,
+      x = point[0] - size / 2.0,
+      y = point[1] - size / 2.0,
+      size = size,
+      corner = self.options.scale / 2.0,
+    )
+    .unwrap();
+  }
+
+  /// An arrow at `center`, pointing the way materials move through `location`.
+  fn arrow(&mut self, class: &str, center: Vector2<f64>, location: InputLocation) {
+    let forwards = location.facing.unit_vector().to_f64();
+    let sideways = Vector2::new(-forwards[1], forwards[0]);
+    let corners = [
+      center + forwards * 0.5,
+      center - forwards * 0.5 + sideways * 0.4,
+      center - forwards * 0.5 - sideways * 0.4,
+    ];
+    let mut points = String::new();
+    for corner in &corners {
+      let point = self.point(*corner);
+      write!(points, "{},{} ", point[0], point[1]).unwrap();
+    }
+    writeln!(
+      self.body,
+      r#"<polygon class="{}" points="{}"/>"#,
+      class,
+      points.trim_end()
+    )
+    .unwrap();
+  }
+
+  fn material(&mut self, icon: &str, center: Vector2<f64>) {
+    if self.options.icon_url_prefix.is_some() {
+      self.icon(icon, center, 0.6, 0.0);
+    } else {
+      let point = self.point(center);
+      writeln!(
+        self.body,
+        r#"<circle class="material {}" cx="{}" cy="{}" r="{}"/>"#,
+        escape(icon),
+        point[0],
+        point[1],
+        0.6 * self.options.scale,
+      )
+      .unwrap();
+    }
+  }
+
+  fn region(&mut self, region: ExportView, depth: usize) {
+    for machine in region.machines() {
+      let machine_type = machine.machine_type();
+      let isomorphism: GridIsomorphism = machine.isomorphism();
+      let center = isomorphism.translation.to_f64();
+      let radius = machine_type.radius() as f64;
+      self.include_in_bounds(center, radius + 1.0);
+      self.outline(center, radius);
+      let degrees = -90.0 * isomorphism.rotation.quarter_turns_from_posx_towards_posy() as f64;
+      self.icon(machine_type.icon(), center, radius, degrees);
+
+      if machine_type.radius() > 1 {
+        for location in machine_type.input_locations(isomorphism) {
+          let position = (location.position + location.facing.unit_vector()).to_f64();
+          self.arrow("input", position, location);
+        }
+        for location in machine_type.output_locations(isomorphism) {
+          let position = (location.position - location.facing.unit_vector()).to_f64();
+          self.arrow("output", position, location);
+        }
+      }
+
+      if let Some(time) = self.options.time {
+        if let Some(visuals) = machine.momentary_visuals(time) {
+          for (position, material) in visuals.materials {
+            self.material(material.icon(), position);
+          }
+        }
+      }
+
+      if let Some(module) = machine.as_module() {
+        if self
+          .options
+          .max_module_depth
+          .map_or(true, |max_depth| depth < max_depth)
+        {
+          self.region(module.inner_region(), depth + 1);
+        }
+      }
+    }
+  }
+
+  fn finish(self) -> String {
+    let [min, max] = self
+      .bounds
+      .unwrap_or([Vector2::new(0.0, 0.0), Vector2::new(0.0, 0.0)]);
+    // flipping y swaps which corner is the top left
+    let top_left = self.point(Vector2::new(min[0], max[1]));
+    let size = (max - min) * self.options.scale;
+    format!(
+      "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"{} {} {} {}\" width=\"{}\" height=\"{}\">\n{}</svg>\n",
+      top_left[0], top_left[1], size[0], size[1], size[0], size[1], self.body
+    )
+  }
+}
+
+fn escape(text: &str) -> String {
+  text
+    .replace('&', "&amp;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+    .replace('"', "&quot;")
+}
+
+/// `future` must be the future of `game`, as returned by `Game::future()`.
+pub fn export_svg(game: &Game, future: &GameFuture, options: &SvgExportOptions) -> String {
+  let view = GameView::<(BaseAspect, FutureAspect)>::new(game, future);
+  let mut writer = SvgWriter {
+    options,
+    body: String::new(),
+    bounds: None,
+  };
+  writer.region(view.global_region(), 0);
+  writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::machine_data::{MachineType, PlatonicMachine};
+  use crate::modules::basic_module;
+  use crate::primitive_machines::{conveyor, iron_mine};
+
+  fn test_game() -> Game {
+    let mut module = match basic_module() {
+      MachineType::Module(module) => module,
+      _ => unreachable!(),
+    };
+    module
+      .region
+      .machines
+      .push(PlatonicMachine::for_tests(1, 0, 0));
+    Game::new(
+      vec![iron_mine(), conveyor(), MachineType::Module(module)],
+      vec![
+        PlatonicMachine::for_tests(0, 0, 0),
+        PlatonicMachine::for_tests(1, 4, 0),
+        PlatonicMachine::for_tests(2, 0, 40),
+      ],
+    )
+  }
+
+  #[test]
+  fn export_includes_every_machine_and_respects_depth_limit() {
+    let game = test_game();
+    let future = game.future();
+    let full = export_svg(&game, &future, &SvgExportOptions::default());
+    assert!(full.starts_with("<svg"));
+    assert_eq!(full.matches("class=\"machine\"").count(), 4);
+
+    let shallow = export_svg(
+      &game,
+      &future,
+      &SvgExportOptions {
+        max_module_depth: Some(0),
+        ..Default::default()
+      },
+    );
+    assert_eq!(shallow.matches("class=\"machine\"").count(), 3);
+  }
+
+  #[test]
+  fn export_draws_materials_only_when_given_a_time() {
+    let game = test_game();
+    let future = game.future();
+    let without_time = export_svg(&game, &future, &SvgExportOptions::default());
+    assert!(!without_time.contains("class=\"material"));
+    let with_time = export_svg(
+      &game,
+      &future,
+      &SvgExportOptions {
+        time: Some(100 * crate::machine_data::TIME_TO_MOVE_MATERIAL),
+        ..Default::default()
+      },
+    );
+    assert!(with_time.contains("class=\"material ore\""));
+  }
+}
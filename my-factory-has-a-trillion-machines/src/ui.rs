@@ -9,11 +9,14 @@ This layer's interface with the frontend: The frontend produces scrolling and po
 This layer's interface with the backend: This layer produces AddRemoveMachines instructions and applies them to the backend, then examines the resulting game states.
 
 */
-use crate::geometry::{Number, Vector};
+use crate::geometry::{GridIsomorphism, Number, Rotate, Vector};
 use crate::graph_algorithms::{
   BaseAspect, FutureAspect, GameFuture, GameView, WorldMachineView, WorldRegionView,
 };
-use crate::machine_data::{Game, GlobalMachine, MachineMomentaryVisuals, Material};
+use crate::machine_data::{
+  Game, GlobalMachine, MachineMomentaryVisuals, Material, WorldMachinesMap,
+};
+use crate::undo_history::InsufficientMaterials;
 use live_prop_test::{live_prop_test, lpt_assert_eq};
 use nalgebra::Vector2;
 use std::collections::{HashMap, HashSet};
@@ -29,7 +32,8 @@ enum Selection {
   NormalMachines(HashSet<GlobalMachine>),
   HoveringMachinesMovedFrom {
     source_machines: HashSet<GlobalMachine>,
-    offset: Vector,
+    /// where the machines are hovering, relative to where they came from
+    transform: GridIsomorphism,
   },
   NovelHoveringMachines(Vec<GlobalMachine>),
 }
@@ -107,6 +111,32 @@ impl UiState {
 
 type StateViewAspects = (BaseAspect, FutureAspect);
 
+/// The turn that rotating a selection applies, as an isomorphism with no translation.
+pub fn selection_rotation(clockwise: bool) -> GridIsomorphism {
+  let mut result = GridIsomorphism::default();
+  result.rotation = result.rotation.rotate_90(if clockwise { 3 } else { 1 });
+  result
+}
+
+/// The turn that flipping a selection applies, as an isomorphism with no translation.
+pub fn selection_flip() -> GridIsomorphism {
+  GridIsomorphism {
+    flip: true,
+    ..Default::default()
+  }
+}
+
+/// `turn` (which should have no translation), moved so that it leaves `center` where it is.
+///
+/// Turning every machine of a selection by this, with `center` at one of the machines, turns the selection as a unit without moving it away from where the player is looking.
+pub fn turn_about(center: Vector, turn: GridIsomorphism) -> GridIsomorphism {
+  let to_center = GridIsomorphism {
+    translation: center,
+    ..Default::default()
+  };
+  to_center.inverse() * turn * to_center
+}
+
 #[live_prop_test]
 impl UiState {
   pub fn check_invariants(&self) -> Result<(), String> {
@@ -131,13 +161,48 @@ impl UiState {
     precondition = "self.check_invariants()",
     postcondition = "self.check_invariants()"
   )]
-  pub fn click_rotate_selection(&mut self, clockwise: bool) {}
+  pub fn click_rotate_selection(&mut self, clockwise: bool) {
+    self.turn_hovering_machines(selection_rotation(clockwise));
+  }
 
   #[live_prop_test(
     precondition = "self.check_invariants()",
     postcondition = "self.check_invariants()"
   )]
-  pub fn click_flip_selection(&mut self) {}
+  pub fn click_flip_selection(&mut self) {
+    self.turn_hovering_machines(selection_flip());
+  }
+
+  /// Turn the hovering machines about one of them. Machines that are already placed don't turn until you pick them up.
+  fn turn_hovering_machines(&mut self, turn: GridIsomorphism) {
+    match &mut self.selected {
+      Selection::HoveringMachinesMovedFrom {
+        source_machines,
+        transform,
+      } => {
+        // a set has no first machine, so pick the same one every time
+        if let Some(center) = source_machines
+          .iter()
+          .map(|machine| (machine.state.position * *transform).translation)
+          .min_by_key(|center| (center[0], center[1]))
+        {
+          *transform = *transform * turn_about(center, turn);
+        }
+      }
+      Selection::NovelHoveringMachines(machines) => {
+        if let Some(center) = machines
+          .first()
+          .map(|machine| machine.state.position.translation)
+        {
+          let turn = turn_about(center, turn);
+          for machine in machines {
+            machine.state.position = machine.state.position * turn;
+          }
+        }
+      }
+      Selection::NormalMachines(_) => {}
+    }
+  }
 
   #[live_prop_test(
     precondition = "self.check_invariants()",
@@ -205,7 +270,31 @@ impl UiState {
     precondition = "self.check_invariants()",
     postcondition = "self.check_invariants()"
   )]
-  pub fn undo(&mut self) {}
+  pub fn undo(&mut self) -> Result<(), InsufficientMaterials> {
+    let _ = self.discard_hovering_machines();
+    self.game.undo(
+      &mut WorldMachinesMap::default(),
+      &self.future,
+      self.current_game_time,
+    )?;
+    self.future = self.game.future();
+    Ok(())
+  }
+
+  #[live_prop_test(
+    precondition = "self.check_invariants()",
+    postcondition = "self.check_invariants()"
+  )]
+  pub fn redo(&mut self) -> Result<(), InsufficientMaterials> {
+    let _ = self.discard_hovering_machines();
+    self.game.redo(
+      &mut WorldMachinesMap::default(),
+      &self.future,
+      self.current_game_time,
+    )?;
+    self.future = self.game.future();
+    Ok(())
+  }
 
   pub fn set_current_game_time(&mut self, time: Number) {
     self.current_game_time = time;
@@ -295,10 +384,10 @@ impl<'a, F: Fn(&GlobalMachine) -> bool> DisplayedStuffCollector<'a, F> {
     match self.state.selected.clone() {
       Selection::HoveringMachinesMovedFrom {
         source_machines,
-        offset,
+        transform,
       } => {
-        for machine in source_machines {
-          machine.state.position.translation += offset;
+        for mut machine in source_machines {
+          machine.state.position = machine.state.position * transform;
           self.collect_machine(machine, MachineRealness::Hovering)
         }
       }
@@ -315,3 +404,36 @@ impl<'a, F: Fn(&GlobalMachine) -> bool> DisplayedStuffCollector<'a, F> {
     self.result
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn turning_about_a_machine_leaves_it_in_place() {
+    let machine = GridIsomorphism {
+      translation: Vector::new(5, 3),
+      ..Default::default()
+    };
+    let neighbor = GridIsomorphism {
+      translation: Vector::new(7, 3),
+      ..Default::default()
+    };
+
+    let turn = turn_about(machine.translation, selection_rotation(false));
+    assert_eq!((machine * turn).translation, machine.translation);
+    assert_eq!(
+      (machine * turn).rotation,
+      selection_rotation(false).rotation
+    );
+    assert_eq!((neighbor * turn).translation, Vector::new(5, 5));
+
+    let flip = turn_about(machine.translation, selection_flip());
+    assert_eq!((neighbor * flip).translation, Vector::new(3, 3));
+    assert_eq!(neighbor * flip * flip, neighbor);
+    assert_eq!(
+      neighbor * turn_about(machine.translation, selection_rotation(true)) * turn,
+      neighbor
+    );
+  }
+}
@@ -1,14 +1,19 @@
-use crate::geometry::Number;
+use crate::geometry::{GridIsomorphism, Number};
 use crate::graph_algorithms::{
   BaseAspect, BaseMutAspect, FutureAspect, GameFuture, GameView, SelectedAspect, SelectedMutAspect,
   WorldMachineView, WorldRegionView,
 };
 use crate::machine_data::{
-  Game, GlobalMachine, MachineTypeId, WorldMachinesMap, TIME_TO_MOVE_MATERIAL,
+  Game, GlobalMachine, MachineState, MachineType, MachineTypeId, MachineTypeRef, MachineTypeTrait,
+  Material, PlatonicMachine, PlatonicRegionContents, WorldMachinesMap, TIME_TO_MOVE_MATERIAL,
 };
+use crate::modules::PlatonicModule;
+use crate::placement::smallest_region_containing;
+use derivative::Derivative;
 use live_prop_test::{live_prop_test, lpt_assert, lpt_assert_eq};
-use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
 
 #[live_prop_test]
 pub trait ModifyGame: Clone {
@@ -56,24 +61,23 @@ impl<T: ModifyGameUndoable> ModifyGame for T {
 }
 
 fn check_modify_game(
-  _game_before: &Game,
+  game_before: &Game,
   game_after: &Game,
   _selected_before: &WorldMachinesMap<()>,
   _selected_after: &WorldMachinesMap<()>,
   modify_time: Number,
 ) -> Result<(), String> {
   lpt_assert!(game_after.is_canonical());
-  // TODO implement this check:
-  /*
-  let before_future = before.future();
-  let before_material_totals = before.material_totals();
-  let before_view = GameView::<Aspects>::new(before, &before_future);
-  let after_material_totals = after.material_totals();
-  lpt_assert_eq!(
-    after.inventory_before_last_change + after_material_totals.global_region,
-    before_view.inventory_at(modify_time) + before_material_totals.global_region
-  );
-  */
+  // materials are neither created nor destroyed by building: whatever is spent ends up in the machines, and is refunded when they are removed
+  let before_future = game_before.future();
+  let before_view = GameView::<(BaseAspect, FutureAspect)>::new(game_before, &before_future);
+  let mut before_totals = before_view.inventory_at(modify_time);
+  add_materials(&mut before_totals, &game_before.embodied_materials(), 1);
+  let mut after_totals = game_after.inventory_before_last_change.clone();
+  add_materials(&mut after_totals, &game_after.embodied_materials(), 1);
+  before_totals.retain(|_, amount| *amount != 0);
+  after_totals.retain(|_, amount| *amount != 0);
+  lpt_assert_eq!(after_totals, before_totals);
   // Note: Null changes COULD be allowed to not change last_change_time...
   // but also maybe they shouldn't be a ModifyGame at all, because they probably shouldn't go in the undo history?
   lpt_assert_eq!(game_after.last_change_time, modify_time);
@@ -155,6 +159,15 @@ fn check_undo<Undo: ModifyGame>(
     &undone_selected,
     undo_time,
   )?;
+  // apart from what depends on when things happened, which is checked above and below, the undo should put the whole game back the way it was
+  let mut expected = before.game().clone();
+  expected.last_disturbed_times = undone_game.last_disturbed_times.clone();
+  expected.last_change_time = undone_game.last_change_time;
+  expected.inventory_before_last_change = undone_game.inventory_before_last_change.clone();
+  expected.progression.delivered_before_last_change =
+    undone_game.progression.delivered_before_last_change.clone();
+  lpt_assert_eq!(undone_game, expected);
+
   let undone_future = undone_game.future();
   let undone =
     GameView::<AspectsForCheckModifyGame>::new(&undone_game, &undone_selected, &undone_future);
@@ -258,12 +271,158 @@ impl CheckUndoneMap {
   }
 }
 
+/// Returned when a change needs more materials than the inventory holds at the time of the change.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Debug)]
+pub struct InsufficientMaterials {
+  /// How much more of each material would be needed.
+  pub shortfall: Vec<(Number, Material)>,
+}
+
+fn add_materials(
+  totals: &mut HashMap<Material, Number>,
+  amounts: &HashMap<Material, Number>,
+  multiplier: Number,
+) {
+  for (&material, &amount) in amounts {
+    *totals.entry(material).or_default() += amount * multiplier;
+  }
+}
+
+/// Deduct `cost` from the inventory; negative amounts are refunds. Must be called after the change is made, because making the change resets `inventory_before_last_change`.
+fn spend(game: &mut Game, cost: &HashMap<Material, Number>) {
+  add_materials(&mut game.inventory_before_last_change, cost, -1);
+}
+
 #[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Debug)]
 pub struct AddRemoveMachines {
   pub added: Vec<GlobalMachine>,
   pub removed: Vec<GlobalMachine>,
 }
 
+/// Why `Game::add_remove_machines` refused to make a change.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Debug)]
+pub enum AddRemoveMachinesError {
+  InsufficientMaterials(InsufficientMaterials),
+  /// Adding or removing machines inside a module instance edits that instance's module, and its parameters wouldn't make sense anymore; see `EditModuleError::BreaksParameters`.
+  BreaksParameters(String),
+}
+
+impl From<InsufficientMaterials> for AddRemoveMachinesError {
+  fn from(error: InsufficientMaterials) -> Self {
+    AddRemoveMachinesError::InsufficientMaterials(error)
+  }
+}
+
+impl fmt::Display for AddRemoveMachinesError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      AddRemoveMachinesError::InsufficientMaterials(error) => {
+        write!(f, "needs more materials: {:?}", error.shortfall)
+      }
+      AddRemoveMachinesError::BreaksParameters(problem) => {
+        write!(f, "the module's parameters would break: {}", problem)
+      }
+    }
+  }
+}
+
+impl AddRemoveMachines {
+  /// Combine two changes into a single change that has the same effect as applying `self` and then `later`.
+  ///
+  /// Machines that one change adds and the other removes again are dropped from both lists,
+  /// so that the combined change doesn't disturb them.
+  pub fn then(mut self, mut later: AddRemoveMachines) -> AddRemoveMachines {
+    later.removed.retain(
+      |machine| match self.added.iter().position(|added| added == machine) {
+        Some(index) => {
+          self.added.swap_remove(index);
+          false
+        }
+        None => true,
+      },
+    );
+    later.added.retain(|machine| {
+      match self.removed.iter().position(|removed| removed == machine) {
+        Some(index) => {
+          self.removed.swap_remove(index);
+          false
+        }
+        None => true,
+      }
+    });
+    self.added.extend(later.added);
+    self.removed.extend(later.removed);
+    self
+  }
+
+  /// Checks that the change makes sense for `game`. Machines added or removed inside a module instance go into an edited copy of its module (see `WorldModuleView::inner_region_mut`), whose parameters have to keep making sense, the same as for `EditModule::check`.
+  pub fn check(&self, game: &Game) -> Result<(), AddRemoveMachinesError> {
+    let machine_types = &game.machine_types;
+    // the inner region of each affected module instance, with its isomorphism and its edited module
+    let mut edited: Vec<(&PlatonicRegionContents, GridIsomorphism, PlatonicModule)> = Vec::new();
+    let changes = self
+      .removed
+      .iter()
+      .map(|machine| (machine, false))
+      .chain(self.added.iter().map(|machine| (machine, true)));
+    for (machine, is_added) in changes {
+      let (region, isomorphism, module) = smallest_region_containing(
+        machine_types,
+        &game.global_region,
+        GridIsomorphism::default(),
+        machine.state.position,
+        machine_types.get(machine.type_id).radius(),
+      );
+      let module = match module {
+        Some(module) => module,
+        None => continue,
+      };
+      let index = match edited
+        .iter()
+        .position(|(edited_region, edited_isomorphism, _)| {
+          std::ptr::eq(*edited_region, region) && *edited_isomorphism == isomorphism
+        }) {
+        Some(index) => index,
+        None => {
+          edited.push((region, isomorphism, module.clone()));
+          edited.len() - 1
+        }
+      };
+      let local = PlatonicMachine {
+        type_id: machine.type_id,
+        state: MachineState {
+          position: machine.state.position / isomorphism,
+          parameters: machine.state.parameters.clone(),
+        },
+      };
+      let machines = &mut edited[index].2.region.machines;
+      if is_added {
+        machines.push(local);
+      } else if let Some(index) = machines.iter().position(|existing| *existing == local) {
+        machines.remove(index);
+      }
+    }
+    for (_, _, module) in &edited {
+      module
+        .check_parameters(machine_types)
+        .map_err(AddRemoveMachinesError::BreaksParameters)?;
+    }
+    Ok(())
+  }
+
+  /// The materials this change would take from the inventory. Negative amounts are refunds for removed machines.
+  pub fn net_cost(&self, game: &Game) -> HashMap<Material, Number> {
+    let machine_types = &game.machine_types;
+    let mut result = machine_types.total_build_cost(self.added.iter().map(|machine| &machine.0));
+    add_materials(
+      &mut result,
+      &machine_types.total_build_cost(self.removed.iter().map(|machine| &machine.0)),
+      -1,
+    );
+    result
+  }
+}
+
 //impl_world_views_for_aspect_tuple!(&mut (BaseMutAspect, SelectedMutAspect,));
 
 type AddRemoveMachinesAspects = (BaseMutAspect, SelectedMutAspect);
@@ -323,13 +482,17 @@ impl ModifyGameUndoable for AddRemoveMachines {
       }
     }
 
-    let mut game_view =
-      GameView::<AddRemoveMachinesAspects>::new(BaseMutAspect::new(game, time, future), selected);
-    handle_region(
-      game_view.global_region_mut(),
-      &mut self.added,
-      &mut self.removed,
-    );
+    let cost = self.net_cost(game);
+    {
+      let mut game_view =
+        GameView::<AddRemoveMachinesAspects>::new(BaseMutAspect::new(game, time, future), selected);
+      handle_region(
+        game_view.global_region_mut(),
+        &mut self.added,
+        &mut self.removed,
+      );
+    }
+    spend(game, &cost);
 
     AddRemoveMachines {
       added: self.removed,
@@ -338,30 +501,721 @@ impl ModifyGameUndoable for AddRemoveMachines {
   }
 }
 
+/// A change to the inner region of a module type, which applies to every instance of that type at once.
+///
+/// The positions of `added` and `removed` are in the module's own frame, like the machines in `PlatonicModule::region`.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Debug)]
+pub struct EditModule {
+  pub module: MachineTypeId,
+  pub added: Vec<PlatonicMachine>,
+  pub removed: Vec<PlatonicMachine>,
+  /// After the edit, switch the instances of `module` over to this preset. Editing a preset moves its instances to an edited copy, so this is how the undo puts them back.
+  #[serde(default)]
+  pub restore_preset: Option<MachineTypeId>,
+}
+
+/// Why `Game::edit_module` refused to make a change.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Debug)]
+pub enum EditModuleError {
+  InsufficientMaterials(InsufficientMaterials),
+  /// Nothing in the world is an instance of the module, so there's nothing to edit.
+  NoInstances,
+  /// The machine type being edited isn't a module.
+  NotAModule,
+  /// One of the added machines is the module being edited, or contains it.
+  ContainsItself,
+  /// One of the removed machines isn't in the module.
+  NotInModule(PlatonicMachine),
+  /// The edited module's parameters wouldn't make sense anymore, e.g. because the machine a `ModuleParameter::MachineChoice` swaps was removed.
+  BreaksParameters(String),
+  /// `restore_preset` isn't a preset module, or the module being edited is a preset itself.
+  NotAPresetCopy,
+}
+
+impl From<InsufficientMaterials> for EditModuleError {
+  fn from(error: InsufficientMaterials) -> Self {
+    EditModuleError::InsufficientMaterials(error)
+  }
+}
+
+impl fmt::Display for EditModuleError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      EditModuleError::InsufficientMaterials(error) => {
+        write!(f, "needs more materials: {:?}", error.shortfall)
+      }
+      EditModuleError::NoInstances => write!(f, "there are no instances of the module to edit"),
+      EditModuleError::NotAModule => write!(f, "only modules can be edited"),
+      EditModuleError::ContainsItself => write!(f, "a module can't contain itself"),
+      EditModuleError::NotInModule(_) => write!(f, "that machine isn't in the module"),
+      EditModuleError::BreaksParameters(problem) => {
+        write!(f, "the module's parameters would break: {}", problem)
+      }
+      EditModuleError::NotAPresetCopy => {
+        write!(
+          f,
+          "only a copy of a preset module can go back to being the preset"
+        )
+      }
+    }
+  }
+}
+
+impl EditModule {
+  /// Checks that the change makes sense for `game`. `modify_game_undoable` assumes that it does:
+  /// in particular, an edited module with no instances would disappear when the game is canonicalized, leaving nothing for the undo to refer to.
+  pub fn check(&self, game: &Game) -> Result<(), EditModuleError> {
+    // checked first, because `MachineTypes::get` panics for machine types that don't exist, and those have no instances
+    if game.num_instances(self.module) == 0 {
+      return Err(EditModuleError::NoInstances);
+    }
+    let module = match game.machine_types.get(self.module) {
+      MachineTypeRef::Module(module) => module,
+      _ => return Err(EditModuleError::NotAModule),
+    };
+    if let Some(preset) = self.restore_preset {
+      let is_preset_module = match preset {
+        MachineTypeId::Preset(index) => matches!(
+          game.machine_types.presets.get(index),
+          Some(MachineType::Module(_))
+        ),
+        MachineTypeId::Module(_) => false,
+      };
+      if !is_preset_module || matches!(self.module, MachineTypeId::Preset(_)) {
+        return Err(EditModuleError::NotAPresetCopy);
+      }
+    }
+    let containing = game.machine_types.modules_containing(self.module);
+    if self
+      .added
+      .iter()
+      .any(|machine| containing.contains(&machine.type_id))
+    {
+      return Err(EditModuleError::ContainsItself);
+    }
+    let mut edited = module.clone();
+    let remaining = &mut edited.region.machines;
+    for removed in &self.removed {
+      match remaining.iter().position(|machine| machine == removed) {
+        Some(index) => {
+          remaining.remove(index);
+        }
+        None => return Err(EditModuleError::NotInModule(removed.clone())),
+      }
+    }
+    remaining.extend(self.added.iter().cloned());
+    edited
+      .check_parameters(&game.machine_types)
+      .map_err(EditModuleError::BreaksParameters)
+  }
+
+  /// The materials this change would take from the inventory. Every instance of the module in the world changes, so each of them costs (or refunds) the same amount.
+  pub fn net_cost(&self, game: &Game) -> HashMap<Material, Number> {
+    let machine_types = &game.machine_types;
+    let mut per_instance = machine_types.total_build_cost(&self.added);
+    add_materials(
+      &mut per_instance,
+      &machine_types.total_build_cost(&self.removed),
+      -1,
+    );
+    let mut result = HashMap::new();
+    add_materials(&mut result, &per_instance, game.num_instances(self.module));
+    result
+  }
+}
+
+type EditModuleAspects = (BaseMutAspect, SelectedMutAspect);
+#[live_prop_test(use_trait_tests)]
+impl ModifyGameUndoable for EditModule {
+  type Undo = EditModule;
+
+  fn modify_game_undoable(
+    self,
+    game: &mut Game,
+    selected: &mut WorldMachinesMap<()>,
+    future: &GameFuture,
+    time: Number,
+  ) -> EditModule {
+    let cost = self.net_cost(game);
+
+    // Every instance behaves differently now, and so does every module that contains one.
+    // We don't try to track the inner workings of the affected modules; we just disturb the
+    // outermost machines that contain an instance, which implicitly disturbs everything inside them.
+    let containing = game.machine_types.modules_containing(self.module);
+    {
+      let mut game_view =
+        GameView::<EditModuleAspects>::new(BaseMutAspect::new(game, time, future), selected);
+      game_view
+        .global_region_mut()
+        .retain_machines(|mut machine| {
+          if containing.contains(&machine.global().type_id) {
+            machine.disturb();
+          }
+          true
+        });
+    }
+
+    let module_index = match self.module {
+      MachineTypeId::Module(index) => index,
+      MachineTypeId::Preset(_) => {
+        // The preset is also the template for newly built machines, so it mustn't change;
+        // instead, the existing instances switch over to an edited copy.
+        let index = game.machine_types.custom_modules.len();
+        let copy = game.machine_types.get_module(self.module).clone();
+        game.machine_types.custom_modules.push(copy);
+        for machine in game.platonic_machines_mut() {
+          if machine.type_id == self.module {
+            machine.type_id = MachineTypeId::Module(index);
+          }
+        }
+        index
+      }
+    };
+
+    let region = &mut game.machine_types.custom_modules[module_index].region;
+    for removed in &self.removed {
+      let index = region
+        .machines
+        .iter()
+        .position(|machine| machine == removed)
+        .expect("tried to remove a machine that wasn't in the module");
+      region.machines.remove(index);
+    }
+    region.machines.extend(self.added.iter().cloned());
+    if let Some(preset) = self.restore_preset {
+      // the copy is left without instances, so canonicalizing drops it
+      for machine in game.platonic_machines_mut() {
+        if machine.type_id == MachineTypeId::Module(module_index) {
+          machine.type_id = preset;
+        }
+      }
+    }
+
+    let new_indices = game.canonicalize_reporting_module_indices();
+    spend(game, &cost);
+    let module = match self.restore_preset {
+      Some(preset) => preset,
+      None => MachineTypeId::Module(*new_indices.get(&module_index).expect(
+        "EditModule::check makes sure that the edited module has instances, so it isn't dropped",
+      )),
+    };
+
+    EditModule {
+      module,
+      added: self.removed,
+      removed: self.added,
+      restore_preset: match self.module {
+        MachineTypeId::Preset(_) => Some(self.module),
+        MachineTypeId::Module(_) => None,
+      },
+    }
+  }
+}
+
+/// Anything that can go in the undo history.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Debug)]
+pub enum UndoableChange {
+  AddRemoveMachines(AddRemoveMachines),
+  EditModule(EditModule),
+}
+
+impl From<AddRemoveMachines> for UndoableChange {
+  fn from(change: AddRemoveMachines) -> Self {
+    UndoableChange::AddRemoveMachines(change)
+  }
+}
+
+impl From<EditModule> for UndoableChange {
+  fn from(change: EditModule) -> Self {
+    UndoableChange::EditModule(change)
+  }
+}
+
+impl UndoableChange {
+  pub fn net_cost(&self, game: &Game) -> HashMap<Material, Number> {
+    match self {
+      UndoableChange::AddRemoveMachines(change) => change.net_cost(game),
+      UndoableChange::EditModule(change) => change.net_cost(game),
+    }
+  }
+
+  /// Combine two changes into one, if they are of kinds that can be combined; see `AddRemoveMachines::then`.
+  fn then(self, later: UndoableChange) -> Result<UndoableChange, (UndoableChange, UndoableChange)> {
+    match (self, later) {
+      (UndoableChange::AddRemoveMachines(earlier), UndoableChange::AddRemoveMachines(later)) => {
+        Ok(earlier.then(later).into())
+      }
+      (earlier, later) => Err((earlier, later)),
+    }
+  }
+}
+
+#[live_prop_test(use_trait_tests)]
+impl ModifyGameUndoable for UndoableChange {
+  type Undo = UndoableChange;
+
+  fn modify_game_undoable(
+    self,
+    game: &mut Game,
+    selected: &mut WorldMachinesMap<()>,
+    future: &GameFuture,
+    time: Number,
+  ) -> UndoableChange {
+    match self {
+      UndoableChange::AddRemoveMachines(change) => change
+        .modify_game_undoable(game, selected, future, time)
+        .into(),
+      UndoableChange::EditModule(change) => change
+        .modify_game_undoable(game, selected, future, time)
+        .into(),
+    }
+  }
+}
+
+pub const DEFAULT_UNDO_HISTORY_LIMIT: usize = 200;
+
+/// How the undo history is treated when the `Game` containing it is serialized.
+#[derive(Copy, Clone, PartialEq, Eq, Serialize, Deserialize, Debug, Derivative)]
+#[derivative(Default)]
+pub enum UndoHistoryPersistence {
+  /// The history is saved along with the rest of the game.
+  #[derivative(Default)]
+  WithGame,
+  /// Only the settings are saved with the game, so that saves stay small.
+  /// If you want to keep the entries anyway, serialize `Game::undo_history` on its own,
+  /// and put it back after loading the game it came from.
+  Separate,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Derivative)]
+#[derivative(Default)]
+enum GestureState {
+  #[derivative(Default)]
+  Closed,
+  /// A gesture is in progress, but hasn't recorded any changes yet.
+  Started,
+  /// A gesture is in progress, and the top of the undo stack belongs to it.
+  Coalescing,
+}
+
+/// The undo and redo stacks of a `Game`.
+///
+/// The stacks are bounded by `limit`; when a new entry would exceed it, the oldest entry is forgotten.
+///
+/// The frontend can group several changes into one undo entry (for example, all the conveyors placed
+/// during one drag) by calling `begin_gesture` and `end_gesture` around them.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Debug, Derivative)]
+#[derivative(Default)]
+pub struct UndoHistory {
+  pub persistence: UndoHistoryPersistence,
+  #[derivative(Default(value = "Some(DEFAULT_UNDO_HISTORY_LIMIT)"))]
+  limit: Option<usize>,
+  undo_stack: VecDeque<UndoableChange>,
+  redo_stack: VecDeque<UndoableChange>,
+  #[serde(skip)]
+  gesture: GestureState,
+}
+
+fn push_bounded(stack: &mut VecDeque<UndoableChange>, entry: UndoableChange, limit: Option<usize>) {
+  stack.push_back(entry);
+  truncate_to_limit(stack, limit);
+}
+
+fn truncate_to_limit(stack: &mut VecDeque<UndoableChange>, limit: Option<usize>) {
+  if let Some(limit) = limit {
+    while stack.len() > limit {
+      stack.pop_front();
+    }
+  }
+}
+
+impl UndoHistory {
+  pub fn limit(&self) -> Option<usize> {
+    self.limit
+  }
+
+  /// Change the maximum number of entries in each stack, immediately forgetting the oldest entries if there are too many.
+  pub fn set_limit(&mut self, limit: Option<usize>) {
+    self.limit = limit;
+    truncate_to_limit(&mut self.undo_stack, limit);
+    truncate_to_limit(&mut self.redo_stack, limit);
+  }
+
+  pub fn num_undo_entries(&self) -> usize {
+    self.undo_stack.len()
+  }
+  pub fn num_redo_entries(&self) -> usize {
+    self.redo_stack.len()
+  }
+
+  /// Start grouping changes together; every change recorded before the next `end_gesture` becomes part of a single undo entry.
+  pub fn begin_gesture(&mut self) {
+    self.gesture = GestureState::Started;
+  }
+  pub fn end_gesture(&mut self) {
+    self.gesture = GestureState::Closed;
+  }
+
+  /// Record the undo of a brand-new change, discarding anything that could have been redone.
+  pub fn record_change(&mut self, undo: impl Into<UndoableChange>) {
+    let undo = undo.into();
+    self.redo_stack.clear();
+    if self.gesture == GestureState::Coalescing {
+      if let Some(previous) = self.undo_stack.pop_back() {
+        // the new change happened after the previous one, so its undo must happen first
+        match undo.then(previous) {
+          Ok(combined) => {
+            self.undo_stack.push_back(combined);
+            return;
+          }
+          Err((undo, previous)) => {
+            self.undo_stack.push_back(previous);
+            push_bounded(&mut self.undo_stack, undo, self.limit);
+            return;
+          }
+        }
+      }
+    }
+    push_bounded(&mut self.undo_stack, undo, self.limit);
+    if self.gesture == GestureState::Started {
+      self.gesture = GestureState::Coalescing;
+    }
+  }
+
+  /// The same as this history, but with no entries; this is what's left when the history isn't saved with the game.
+  pub fn without_entries(&self) -> UndoHistory {
+    UndoHistory {
+      persistence: self.persistence,
+      limit: self.limit,
+      ..Default::default()
+    }
+  }
+
+  /// Serialize the history the way it should appear inside a serialized `Game`, respecting `persistence`.
+  pub fn serialize_with_game<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    match self.persistence {
+      UndoHistoryPersistence::WithGame => self.serialize(serializer),
+      UndoHistoryPersistence::Separate => self.without_entries().serialize(serializer),
+    }
+  }
+}
+
 impl Game {
+  /// Checks that the inventory at `time` covers `cost`.
+  pub fn check_affordable(
+    &self,
+    cost: &HashMap<Material, Number>,
+    future: &GameFuture,
+    time: Number,
+  ) -> Result<(), InsufficientMaterials> {
+    let inventory = GameView::<(BaseAspect, FutureAspect)>::new(self, future).inventory_at(time);
+    let shortfall: Vec<(Number, Material)> = cost
+      .iter()
+      .filter_map(|(&material, &amount)| {
+        let available = inventory.get(&material).copied().unwrap_or(0);
+        if amount > available {
+          Some((amount - available, material))
+        } else {
+          None
+        }
+      })
+      .collect();
+    if shortfall.is_empty() {
+      Ok(())
+    } else {
+      Err(InsufficientMaterials { shortfall })
+    }
+  }
+
+  /// Make the change, paying for it from the inventory, unless it doesn't make sense (see `AddRemoveMachines::check`) or the inventory at `time` can't cover the cost.
   pub fn add_remove_machines(
     &mut self,
     action: AddRemoveMachines,
     selected: &mut WorldMachinesMap<()>,
     future: &GameFuture,
     time: Number,
-  ) {
+  ) -> Result<(), AddRemoveMachinesError> {
+    action.check(self)?;
+    self.check_affordable(&action.net_cost(self), future, time)?;
     let undo = action.modify_game_undoable(self, selected, future, time);
-    self.redo_stack.clear();
-    self.undo_stack.push(undo);
+    self.undo_history.record_change(undo);
+    Ok(())
+  }
+
+  /// Make the change, paying for it from the inventory, unless it doesn't make sense (see `EditModule::check`) or the inventory at `time` can't cover the cost.
+  pub fn edit_module(
+    &mut self,
+    action: EditModule,
+    selected: &mut WorldMachinesMap<()>,
+    future: &GameFuture,
+    time: Number,
+  ) -> Result<(), EditModuleError> {
+    action.check(self)?;
+    self.check_affordable(&action.net_cost(self), future, time)?;
+    let undo = action.modify_game_undoable(self, selected, future, time);
+    self.undo_history.record_change(undo);
+    Ok(())
   }
 
-  pub fn undo(&mut self, selected: &mut WorldMachinesMap<()>, future: &GameFuture, time: Number) {
-    if let Some(undo) = self.undo_stack.pop() {
+  /// Undoing refunds whatever the change cost, but undoing a removal has to pay for the machines again,
+  /// which can fail; in that case, the entry stays on the undo stack.
+  pub fn undo(
+    &mut self,
+    selected: &mut WorldMachinesMap<()>,
+    future: &GameFuture,
+    time: Number,
+  ) -> Result<(), InsufficientMaterials> {
+    self.undo_history.end_gesture();
+    if let Some(undo) = self.undo_history.undo_stack.back() {
+      self.check_affordable(&undo.net_cost(self), future, time)?;
+    }
+    if let Some(undo) = self.undo_history.undo_stack.pop_back() {
       let redo = undo.modify_game_undoable(self, selected, future, time);
-      self.redo_stack.push(redo);
+      let limit = self.undo_history.limit;
+      push_bounded(&mut self.undo_history.redo_stack, redo, limit);
     }
+    Ok(())
   }
 
-  pub fn redo(&mut self, selected: &mut WorldMachinesMap<()>, future: &GameFuture, time: Number) {
-    if let Some(redo) = self.redo_stack.pop() {
+  pub fn redo(
+    &mut self,
+    selected: &mut WorldMachinesMap<()>,
+    future: &GameFuture,
+    time: Number,
+  ) -> Result<(), InsufficientMaterials> {
+    self.undo_history.end_gesture();
+    if let Some(redo) = self.undo_history.redo_stack.back() {
+      self.check_affordable(&redo.net_cost(self), future, time)?;
+    }
+    if let Some(redo) = self.undo_history.redo_stack.pop_back() {
       let undo = redo.modify_game_undoable(self, selected, future, time);
-      self.undo_stack.push(undo);
+      let limit = self.undo_history.limit;
+      push_bounded(&mut self.undo_history.undo_stack, undo, limit);
     }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::machine_data::PlatonicMachine;
+  use crate::modules::basic_module;
+  use crate::primitive_machines::conveyor;
+
+  fn machine(x: Number) -> GlobalMachine {
+    GlobalMachine(PlatonicMachine::for_tests(0, x, 1))
+  }
+
+  fn add(xs: &[Number]) -> AddRemoveMachines {
+    AddRemoveMachines {
+      added: xs.iter().copied().map(machine).collect(),
+      removed: vec![],
+    }
+  }
+
+  #[test]
+  fn then_cancels_machines_that_are_added_and_removed_again() {
+    let combined = add(&[1, 3]).then(AddRemoveMachines {
+      added: vec![machine(5)],
+      removed: vec![machine(3)],
+    });
+    assert_eq!(combined.added, vec![machine(1), machine(5)]);
+    assert_eq!(combined.removed, vec![]);
+  }
+
+  #[test]
+  fn history_forgets_oldest_entries_beyond_limit() {
+    let mut history = UndoHistory::default();
+    history.set_limit(Some(2));
+    for x in 0..5 {
+      history.record_change(add(&[x]));
+    }
+    assert_eq!(history.num_undo_entries(), 2);
+    assert_eq!(history.undo_stack[0], add(&[3]).into());
+  }
+
+  #[test]
+  fn history_coalesces_changes_within_a_gesture() {
+    let mut history = UndoHistory::default();
+    history.record_change(add(&[0]));
+    history.begin_gesture();
+    for x in 1..4 {
+      history.record_change(add(&[x]));
+    }
+    history.end_gesture();
+    history.record_change(add(&[4]));
+    assert_eq!(history.num_undo_entries(), 3);
+    assert_eq!(history.undo_stack[1], add(&[3, 2, 1]).into());
+  }
+
+  fn conveyor_game(iron: Number) -> Game {
+    let mut game = Game::new(vec![conveyor()], Vec::new());
+    game
+      .inventory_before_last_change
+      .insert(Material::Iron, iron);
+    game
+  }
+
+  #[test]
+  fn building_spends_materials_and_undo_refunds_them() {
+    let mut game = conveyor_game(2);
+    let mut selected = WorldMachinesMap::default();
+    let future = game.future();
+    game
+      .add_remove_machines(add(&[1, 3]), &mut selected, &future, 10)
+      .unwrap();
+    assert_eq!(game.inventory_before_last_change[&Material::Iron], 0);
+
+    let future = game.future();
+    assert_eq!(
+      game.add_remove_machines(add(&[5]), &mut selected, &future, 20),
+      Err(AddRemoveMachinesError::InsufficientMaterials(
+        InsufficientMaterials {
+          shortfall: vec![(1, Material::Iron)]
+        }
+      ))
+    );
+    assert_eq!(game.global_region.machines.len(), 2);
+
+    game.undo(&mut selected, &future, 20).unwrap();
+    assert_eq!(game.inventory_before_last_change[&Material::Iron], 2);
+    assert!(game.global_region.machines.is_empty());
+  }
+
+  #[test]
+  fn nonsensical_module_edits_are_refused() {
+    let mut game = conveyor_game(1000);
+    game.machine_types.presets.push(basic_module());
+    let module = MachineTypeId::Preset(1);
+    let mut selected = WorldMachinesMap::default();
+    let edit = |added: Vec<PlatonicMachine>, removed: Vec<PlatonicMachine>| EditModule {
+      module,
+      added,
+      removed,
+      restore_preset: None,
+    };
+    let instance = PlatonicMachine {
+      type_id: module,
+      ..machine(41).0
+    };
+
+    let future = game.future();
+    assert_eq!(
+      game.edit_module(edit(vec![machine(1).0], vec![]), &mut selected, &future, 0),
+      Err(EditModuleError::NoInstances)
+    );
+
+    game
+      .add_remove_machines(
+        AddRemoveMachines {
+          added: vec![GlobalMachine(instance.clone()), machine(1)],
+          removed: vec![],
+        },
+        &mut selected,
+        &future,
+        0,
+      )
+      .unwrap();
+    let future = game.future();
+    assert_eq!(
+      game.edit_module(edit(vec![instance], vec![]), &mut selected, &future, 0),
+      Err(EditModuleError::ContainsItself)
+    );
+    assert_eq!(
+      game.edit_module(edit(vec![], vec![machine(1).0]), &mut selected, &future, 0),
+      Err(EditModuleError::NotInModule(machine(1).0))
+    );
+    assert_eq!(
+      game.edit_module(
+        EditModule {
+          module: MachineTypeId::Preset(0),
+          ..edit(vec![], vec![])
+        },
+        &mut selected,
+        &future,
+        0
+      ),
+      Err(EditModuleError::NotAModule)
+    );
+    assert_eq!(
+      game.edit_module(
+        EditModule {
+          restore_preset: Some(module),
+          ..edit(vec![], vec![])
+        },
+        &mut selected,
+        &future,
+        0
+      ),
+      Err(EditModuleError::NotAPresetCopy)
+    );
+  }
+
+  #[test]
+  fn undoing_a_preset_edit_switches_back_to_the_preset() {
+    let mut game = conveyor_game(1000);
+    game.machine_types.presets.push(basic_module());
+    let module = MachineTypeId::Preset(1);
+    let instance = GlobalMachine(PlatonicMachine {
+      type_id: module,
+      ..machine(41).0
+    });
+    let mut selected = WorldMachinesMap::default();
+    let future = game.future();
+    game
+      .add_remove_machines(
+        AddRemoveMachines {
+          added: vec![instance.clone()],
+          removed: vec![],
+        },
+        &mut selected,
+        &future,
+        0,
+      )
+      .unwrap();
+    let before_edit = game.clone();
+
+    // the preset itself stays as it was, and the instance switches to an edited copy
+    let future = game.future();
+    game
+      .edit_module(
+        EditModule {
+          module,
+          added: vec![machine(1).0],
+          removed: vec![],
+          restore_preset: None,
+        },
+        &mut selected,
+        &future,
+        10,
+      )
+      .unwrap();
+    assert_eq!(game.machine_types.presets[1], basic_module());
+    assert_eq!(
+      game.machine_types.custom_modules[0].region.machines,
+      vec![machine(1).0]
+    );
+    assert_eq!(
+      game.global_region.machines[0].type_id,
+      MachineTypeId::Module(0)
+    );
+
+    let future = game.future();
+    game.undo(&mut selected, &future, 20).unwrap();
+    assert_eq!(game.global_region, before_edit.global_region);
+    assert_eq!(game.machine_types, before_edit.machine_types);
+
+    // and redoing it makes a new copy again
+    let future = game.future();
+    game.redo(&mut selected, &future, 30).unwrap();
+    assert_eq!(
+      game.global_region.machines[0].type_id,
+      MachineTypeId::Module(0)
+    );
+    assert_eq!(
+      game.machine_types.custom_modules[0].region.machines,
+      vec![machine(1).0]
+    );
   }
 }